@@ -1,15 +1,19 @@
 use d64::{Sector, SectorRef, TrackNo};
 
+/// Offset of the BAM entry of track 1 in the 1541 BAM sector.
+const COMMODORE1541_ENTRY_OFFSET: usize = 4;
+/// Size of a BAM entry in the 1541 BAM sector. One byte for the number of
+/// unused sectors followed by a bitmap of 3 bytes.
+const COMMODORE1541_ENTRY_SIZE: usize = 4;
+
 pub struct BlockAvailabilityMap<'a> {
     sector: &'a mut Sector,
-}
-
-fn get_track_offset(sector: SectorRef) -> usize {
-    sector.0 as usize * 4
-}
-
-fn get_sector_offset(sector: SectorRef) -> usize {
-    get_track_offset(sector) + (sector.1 as usize / 8) + 1
+    /// Track number of the first BAM entry stored in the sector.
+    first_track: TrackNo,
+    /// Offset of the first BAM entry in the sector.
+    entry_offset: usize,
+    /// Size of a single BAM entry (number of unused sectors followed by the bitmap).
+    entry_size: usize,
 }
 
 fn get_sector_mask(sector: SectorRef) -> u8 {
@@ -18,12 +22,45 @@ fn get_sector_mask(sector: SectorRef) -> u8 {
 
 impl<'a> BlockAvailabilityMap<'a> {
     pub fn new(sector: &'a mut Sector) -> BlockAvailabilityMap<'a> {
-        BlockAvailabilityMap { sector }
+        BlockAvailabilityMap::with_entries(
+            sector,
+            1,
+            COMMODORE1541_ENTRY_OFFSET,
+            COMMODORE1541_ENTRY_SIZE,
+        )
+    }
+
+    /// Create a BAM where the entries are stored at a different location in
+    /// the sector.
+    ///
+    /// Drives with more tracks (8050, 8250) spread their BAM over multiple
+    /// sectors. Each sector contains the entries of a range of tracks starting
+    /// at first_track.
+    pub fn with_entries(
+        sector: &'a mut Sector,
+        first_track: TrackNo,
+        entry_offset: usize,
+        entry_size: usize,
+    ) -> BlockAvailabilityMap<'a> {
+        BlockAvailabilityMap {
+            sector,
+            first_track,
+            entry_offset,
+            entry_size,
+        }
+    }
+
+    fn get_track_offset(&self, sector: SectorRef) -> usize {
+        self.entry_offset + (sector.0 - self.first_track) as usize * self.entry_size
+    }
+
+    fn get_sector_offset(&self, sector: SectorRef) -> usize {
+        self.get_track_offset(sector) + (sector.1 as usize / 8) + 1
     }
 
     pub fn mark_used(&mut self, sector: SectorRef) {
-        let track_offset = get_track_offset(sector);
-        let sector_offset = get_sector_offset(sector);
+        let track_offset = self.get_track_offset(sector);
+        let sector_offset = self.get_sector_offset(sector);
         let bit_mask = get_sector_mask(sector);
 
         let availability = *self.sector.get_byte(sector_offset);
@@ -37,7 +74,7 @@ impl<'a> BlockAvailabilityMap<'a> {
     }
 
     pub fn is_unused(&mut self, sector: SectorRef) -> bool {
-        let sector_offset = get_sector_offset(sector);
+        let sector_offset = self.get_sector_offset(sector);
         let bit_mask = get_sector_mask(sector);
 
         let availability = *self.sector.get_byte(sector_offset);
@@ -48,8 +85,8 @@ impl<'a> BlockAvailabilityMap<'a> {
     }
 
     pub fn mark_unused(&mut self, sector: SectorRef) {
-        let track_offset = get_track_offset(sector);
-        let sector_offset = get_sector_offset(sector);
+        let track_offset = self.get_track_offset(sector);
+        let sector_offset = self.get_sector_offset(sector);
         let bit_mask = get_sector_mask(sector);
         let availability = *self.sector.get_byte(sector_offset);
         let new_availability = availability | bit_mask;
@@ -62,7 +99,7 @@ impl<'a> BlockAvailabilityMap<'a> {
     }
 
     pub fn count_unused_track_sectors(&self, track_no: TrackNo) -> u8 {
-        let track_offset = get_track_offset((track_no, 0));
+        let track_offset = self.get_track_offset((track_no, 0));
         *self.sector.get_byte(track_offset)
     }
 
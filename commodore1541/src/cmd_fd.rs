@@ -0,0 +1,370 @@
+use crate::dos::*;
+use crate::{PartitionEntry, PartitionType};
+use d64::{Disk, Geometry, Layout, SectorNo, SectorRef, TrackNo};
+use petscii::PetsciiString;
+
+/// Number of tracks of an FD image. The last track is the system partition.
+const NUM_TRACKS: TrackNo = 81;
+/// Track containing the system partition.
+const TRACK_SYSTEM: TrackNo = NUM_TRACKS;
+/// First sector of the partition table.
+const SECTOR_PARTITION_TABLE: SectorRef = (TRACK_SYSTEM, 8);
+/// Number of sectors of the partition table. The first entry is the system
+/// partition, leaving room for 31 partitions.
+const PARTITION_TABLE_NUM_SECTORS: u8 = 4;
+const BYTES_PER_SECTOR: usize = 256;
+/// Partitions are allocated in blocks of 512 bytes.
+const BYTES_PER_BLOCK: usize = 512;
+const SECTORS_PER_BLOCK: usize = BYTES_PER_BLOCK / BYTES_PER_SECTOR;
+
+/// CMD FD-2000 and FD-4000 disk-drives.
+///
+/// The FD drives divide a disk in partitions. Each partition is a consecutive
+/// range of 512 byte blocks and contains its own layout (a native partition,
+/// or an emulation of a 1541, 1571 or 1581 disk). The last track of the image
+/// is the system partition, it contains the partition table.
+///
+/// As a [Layout] the partitions are the entries of the disk:
+/// [Layout::list_entries] lists the partitions, [Layout::create_file] creates a
+/// partition and [Layout::read_file] returns the content of a partition. Use
+/// [CmdFd::read_partition] to access the partition via the layout of the
+/// partition type.
+///
+/// Use the [CmdFd1M], [CmdFd2M] and [CmdFd4M] aliases.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<CmdFd2M>::new();
+/// disk.format();
+///
+/// let partition = PartitionEntry {
+///     name: PetsciiString::from(&String::from("GAMES")),
+///     partition_type: PartitionType::Native,
+///     num_blocks: 2 * 128,
+///     ..PartitionEntry::default()
+/// };
+/// disk.create_file(&partition, &[]);
+/// let partition = disk.list_entries()[0].clone();
+///
+/// let fd = CmdFd2M::default();
+/// let mut native = fd.read_partition::<CmdNative>(&disk, &partition).unwrap();
+/// assert_eq!(native.num_tracks(), 2);
+/// native.format();
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("HELLO")),
+///     ..FileEntry::default()
+/// };
+/// native.create_file(&entry, b"HELLO WORLD");
+/// fd.write_partition(&mut disk, &partition, &native).unwrap();
+///
+/// let mut native = fd.read_partition::<CmdNative>(&disk, &partition).unwrap();
+/// assert_eq!(String::from(&native.list_entries()[0].name), "HELLO");
+/// ```
+#[derive(Default)]
+pub struct CmdFd<const SECTORS_PER_TRACK: u16> {}
+
+/// CMD FD disk with a double density (720KB) disk (D1M files).
+pub type CmdFd1M = CmdFd<40>;
+/// CMD FD disk with a high density (1.44MB) disk (D2M files).
+pub type CmdFd2M = CmdFd<80>;
+/// CMD FD disk with an enhanced density (2.88MB) disk (D4M files).
+pub type CmdFd4M = CmdFd<160>;
+
+impl<const SECTORS_PER_TRACK: u16> Geometry for CmdFd<SECTORS_PER_TRACK> {
+    fn num_tracks(&self) -> u8 {
+        NUM_TRACKS
+    }
+
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        if track == 0 || track > NUM_TRACKS {
+            return 0;
+        }
+        SECTORS_PER_TRACK
+    }
+
+    fn bytes_per_sector(&self) -> u16 {
+        BYTES_PER_SECTOR as u16
+    }
+}
+
+impl<const SECTORS_PER_TRACK: u16> Layout for CmdFd<SECTORS_PER_TRACK> {
+    type FileEntryType = PartitionEntry;
+    type StringType = PetsciiString;
+
+    /// The name of the disk is the name of the system partition.
+    fn get_disk_name(&self, disk: &Disk<Self>) -> PetsciiString
+    where
+        Self: Sized,
+    {
+        self.get_system_partition(disk).name
+    }
+
+    fn set_disk_name(&self, disk: &mut Disk<Self>, new_name: &Self::StringType)
+    where
+        Self: Sized,
+    {
+        let mut system_partition = self.get_system_partition(disk);
+        system_partition.name = new_name.clone();
+        self.update_partition_entry(disk, &system_partition);
+    }
+
+    fn format_disk(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized,
+    {
+        self.clear_disk(disk);
+        let table_sectors = (0..PARTITION_TABLE_NUM_SECTORS)
+            .map(|i| (SECTOR_PARTITION_TABLE.0, SECTOR_PARTITION_TABLE.1 + i))
+            .collect::<Vec<SectorRef>>();
+        chain_sectors(disk, &table_sectors);
+
+        let system_partition = PartitionEntry {
+            name: PetsciiString::from(&String::from("SYSTEM")),
+            partition_type: PartitionType::System,
+            start_block: self.num_data_blocks(),
+            num_blocks: SECTORS_PER_TRACK as usize / SECTORS_PER_BLOCK,
+            partition_entry_ref: (SECTOR_PARTITION_TABLE, 0),
+        };
+        self.update_partition_entry(disk, &system_partition);
+    }
+
+    fn clear_disk(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized,
+    {
+        let sector_refs = get_all_sector_refs(self);
+        clear_sector_refs(disk, &sector_refs);
+    }
+
+    /// List the partitions of the disk. The system partition isn't listed.
+    fn list_entries(&self, disk: &Disk<Self>) -> Vec<PartitionEntry>
+    where
+        Self: Sized,
+    {
+        self.list_partition_table(disk)
+            .into_iter()
+            .filter(|entry| {
+                entry.partition_type != PartitionType::None
+                    && entry.partition_type != PartitionType::System
+            })
+            .collect()
+    }
+
+    /// Return the content of the given partition.
+    fn read_file(&self, disk: &Disk<Self>, file_entry: &PartitionEntry) -> Vec<u8>
+    where
+        Self: Sized,
+    {
+        let mut result = Vec::with_capacity(file_entry.num_blocks * BYTES_PER_BLOCK);
+        for sector_ref in self.get_partition_sector_refs(file_entry) {
+            result.extend_from_slice(disk.get_sector(sector_ref).as_slice());
+        }
+        result
+    }
+
+    /// Create a new partition and store the content in it.
+    ///
+    /// The partition is as large as [PartitionEntry::num_blocks], or larger
+    /// when the content doesn't fit.
+    fn create_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType, content: &[u8])
+    where
+        Self: Sized,
+    {
+        let num_blocks = file_entry
+            .num_blocks
+            .max(content.len().div_ceil(BYTES_PER_BLOCK));
+        let entry_ref = match self.find_unused_partition_entry(disk) {
+            Some(entry_ref) => entry_ref,
+            None => return,
+        };
+        if let Some(start_block) = self.allocate_blocks(disk, num_blocks) {
+            let partition = PartitionEntry {
+                start_block,
+                num_blocks,
+                partition_entry_ref: entry_ref,
+                ..file_entry.clone()
+            };
+            self.write_partition_content(disk, &partition, content);
+            self.update_partition_entry(disk, &partition);
+        }
+    }
+
+    fn delete_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType)
+    where
+        Self: Sized,
+    {
+        let sectors_to_clear = self.get_partition_sector_refs(file_entry);
+        clear_sector_refs(disk, &sectors_to_clear);
+        let sector = disk.get_sector_mut(file_entry.partition_entry_ref.0);
+        let offset = file_entry.partition_entry_ref.1 * FILE_LIST_ENTRY_SIZE;
+        file_entry.scratch(sector, offset);
+    }
+
+    fn num_unused_sectors(&self, disk: &mut Disk<Self>) -> usize
+    where
+        Self: Sized,
+    {
+        let used_blocks = self
+            .list_entries(disk)
+            .iter()
+            .map(|entry| entry.num_blocks)
+            .sum::<usize>();
+        (self.num_data_blocks() - used_blocks) * SECTORS_PER_BLOCK
+    }
+}
+
+impl<const SECTORS_PER_TRACK: u16> CmdFd<SECTORS_PER_TRACK> {
+    /// Copy the content of a partition into a disk of the given layout.
+    ///
+    /// The disk contains as many tracks of the layout as fit inside the
+    /// partition.
+    pub fn read_partition<P>(
+        &self,
+        disk: &Disk<Self>,
+        partition: &PartitionEntry,
+    ) -> std::io::Result<Disk<P>>
+    where
+        P: Layout + Default,
+    {
+        let content = self.read_file(disk, partition);
+        let layout = P::default();
+        let num_tracks = num_tracks_fitting(&layout, content.len());
+        let mut result = Disk::with_num_tracks(layout, num_tracks);
+        result.read_from_reader(&mut content.as_slice())?;
+        Ok(result)
+    }
+
+    /// Store the content of the given disk inside a partition.
+    pub fn write_partition<P>(
+        &self,
+        disk: &mut Disk<Self>,
+        partition: &PartitionEntry,
+        partition_disk: &Disk<P>,
+    ) -> std::io::Result<()>
+    where
+        P: Layout + Default,
+    {
+        let mut content = Vec::new();
+        partition_disk.write_to_writer(&mut content)?;
+        self.write_partition_content(disk, partition, &content);
+        Ok(())
+    }
+
+    /// Number of blocks before the system partition.
+    fn num_data_blocks(&self) -> usize {
+        (NUM_TRACKS - 1) as usize * SECTORS_PER_TRACK as usize / SECTORS_PER_BLOCK
+    }
+
+    fn get_block_sector_ref(&self, sector_index: usize) -> SectorRef {
+        let sectors_per_track = SECTORS_PER_TRACK as usize;
+        (
+            (sector_index / sectors_per_track + 1) as TrackNo,
+            (sector_index % sectors_per_track) as SectorNo,
+        )
+    }
+
+    fn get_partition_sector_refs(&self, partition: &PartitionEntry) -> Vec<SectorRef> {
+        let first_sector = partition.start_block * SECTORS_PER_BLOCK;
+        let num_sectors = partition.num_blocks * SECTORS_PER_BLOCK;
+        (first_sector..first_sector + num_sectors)
+            .map(|sector_index| self.get_block_sector_ref(sector_index))
+            .collect()
+    }
+
+    /// Overwrite the content of the partition. Remaining bytes are set to 0.
+    fn write_partition_content(
+        &self,
+        disk: &mut Disk<Self>,
+        partition: &PartitionEntry,
+        content: &[u8],
+    ) {
+        let sector_refs = self.get_partition_sector_refs(partition);
+        clear_sector_refs(disk, &sector_refs);
+        for (sector_ref, chunk) in sector_refs.iter().zip(content.chunks(BYTES_PER_SECTOR)) {
+            disk.get_sector_mut(*sector_ref).set_bytes(0, chunk);
+        }
+    }
+
+    fn list_partition_table(&self, disk: &Disk<Self>) -> Vec<PartitionEntry> {
+        let mut result = Vec::new();
+        let mut entry_bytes = [0_u8; FILE_LIST_ENTRY_SIZE];
+        for sector_ref in get_sector_ref_chain(disk, SECTOR_PARTITION_TABLE) {
+            let sector = disk.get_sector(sector_ref);
+            for sector_entry in 0..FILE_LIST_ENTRIES_PER_SECTOR {
+                sector.get_bytes(sector_entry * FILE_LIST_ENTRY_SIZE, &mut entry_bytes);
+                result.push(PartitionEntry::from_bytes(
+                    &entry_bytes,
+                    (sector_ref, sector_entry),
+                ));
+            }
+        }
+        result
+    }
+
+    fn get_system_partition(&self, disk: &Disk<Self>) -> PartitionEntry {
+        let mut entry_bytes = [0_u8; FILE_LIST_ENTRY_SIZE];
+        disk.get_sector(SECTOR_PARTITION_TABLE)
+            .get_bytes(0, &mut entry_bytes);
+        PartitionEntry::from_bytes(&entry_bytes, (SECTOR_PARTITION_TABLE, 0))
+    }
+
+    fn find_unused_partition_entry(&self, disk: &Disk<Self>) -> Option<(SectorRef, usize)> {
+        self.list_partition_table(disk)
+            .into_iter()
+            .find(|entry| entry.partition_type == PartitionType::None)
+            .map(|entry| entry.partition_entry_ref)
+    }
+
+    fn update_partition_entry(&self, disk: &mut Disk<Self>, partition: &PartitionEntry) {
+        let sector = disk.get_sector_mut(partition.partition_entry_ref.0);
+        let offset = partition.partition_entry_ref.1 * FILE_LIST_ENTRY_SIZE;
+        partition.store(sector, offset);
+    }
+
+    /// Find the first range of unused blocks that can hold num_blocks.
+    fn allocate_blocks(&self, disk: &Disk<Self>, num_blocks: usize) -> Option<usize> {
+        let mut used_ranges = self
+            .list_entries(disk)
+            .iter()
+            .map(|entry| (entry.start_block, entry.start_block + entry.num_blocks))
+            .collect::<Vec<(usize, usize)>>();
+        used_ranges.sort_unstable();
+
+        let mut start_block = 0;
+        for (used_start, used_end) in used_ranges {
+            if start_block + num_blocks <= used_start {
+                break;
+            }
+            start_block = start_block.max(used_end);
+        }
+        if start_block + num_blocks <= self.num_data_blocks() {
+            Some(start_block)
+        } else {
+            None
+        }
+    }
+}
+
+/// Number of consecutive tracks of the layout that fit inside size bytes.
+fn num_tracks_fitting<L>(layout: &L, size: usize) -> TrackNo
+where
+    L: Geometry,
+{
+    let bytes_per_sector = layout.bytes_per_sector() as usize;
+    let mut num_tracks = 0;
+    let mut total_size = 0;
+    while num_tracks < layout.num_tracks() {
+        let track_size = layout.num_sectors(num_tracks + 1) as usize * bytes_per_sector;
+        if track_size == 0 || total_size + track_size > size {
+            break;
+        }
+        total_size += track_size;
+        num_tracks += 1;
+    }
+    num_tracks
+}
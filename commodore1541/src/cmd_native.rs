@@ -0,0 +1,443 @@
+use crate::dos::*;
+use crate::{FileEntry, FileListEntryRef, FileType};
+use d64::{DirectoryLayout, Disk, Geometry, Layout, SectorNo, SectorRef, Track, TrackNo};
+use petscii::{PetsciiString, PETSCII_H, PETSCII_NBSP, PETSCII_ONE, PETSCII_ZERO};
+
+/// Maximum number of tracks of a native partition.
+const MAX_TRACKS: TrackNo = 255;
+const SECTORS_PER_TRACK: u16 = 256;
+const BYTES_PER_SECTOR: usize = 256;
+/// Reference to the sector containing the disk name and disk id of the root directory.
+const SECTOR_ROOT_HEADER: SectorRef = (1, 1);
+/// First sector of the BAM. The BAM continues in the following sectors.
+const SECTOR_BAM: SectorRef = (1, 2);
+/// Default sector where to start the file list of the root directory.
+const SECTOR_ROOT_LISTING: SectorRef = (1, 34);
+/// Size of the BAM of a single track. Each sector is a single bit.
+const BAM_TRACK_SIZE: usize = SECTORS_PER_TRACK as usize / 8;
+const BAM_OFFSET_VERSION: usize = 2;
+const BAM_OFFSET_DISK_ID: usize = 4;
+const BAM_OFFSET_IO_BYTE: usize = 6;
+const BAM_OFFSET_LAST_TRACK: usize = 8;
+const BAM_IO_BYTE: u8 = 0xc0;
+const DOS_VERSION_OFFSET: usize = 2;
+const DOS_VERSION: u8 = PETSCII_H;
+const DISK_NAME_OFFSET_START: usize = 4;
+const DISK_NAME_LENGTH: usize = 16;
+const DISK_NAME_OFFSET_END: usize = DISK_NAME_OFFSET_START + DISK_NAME_LENGTH;
+const DISK_ID_OFFSET: usize = 0x16;
+const DOS_TYPE_OFFSET: usize = 0x19;
+const HEADER_PADDING_END: usize = 0x1d;
+const HEADER_OFFSET_SELF: usize = 0x20;
+const HEADER_OFFSET_PARENT: usize = 0x22;
+const HEADER_OFFSET_PARENT_ENTRY: usize = 0x24;
+const HEADER_OFFSET_PARENT_ENTRY_OFFSET: usize = 0x26;
+/// Number of sectors a new subdirectory uses: the header and the first
+/// sector of the file list.
+const DIRECTORY_NUM_SECTORS: usize = 2;
+
+/// CMD native partition.
+///
+/// Native partitions are used by the CMD FD and HD drives, and stored
+/// standalone as DNP files. A native partition contains up to 255 tracks of
+/// 256 sectors each. The number of tracks is decided when the partition is
+/// created; use [Disk::with_num_tracks] to create a disk of the right size.
+///
+/// The disk name and id are stored on 1/1. The BAM starts at 1/2 and uses a
+/// single bit for each sector. Directory listings can contain subdirectories
+/// ([FileType::Directory]). Each subdirectory has its own header sector,
+/// that refers to its parent directory.
+///
+/// DNP files are complete native partitions. The number of tracks is read from
+/// the BAM when the image is loaded.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::with_num_tracks(CmdNative::default(), 4);
+/// disk.format();
+/// assert_eq!(disk.num_unused_sectors(), 4 * 256 - 35);
+///
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("HELLO")),
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &[1, 2, 3]);
+///
+/// let entries = disk.list_entries();
+/// assert_eq!(entries.len(), 1);
+/// assert_eq!(&disk.read_file(&entries[0])[..3], &[1, 2, 3]);
+/// ```
+///
+/// Loading a DNP file determines the number of tracks from its BAM.
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::with_num_tracks(CmdNative::default(), 3);
+/// disk.format();
+/// disk.create_directory(&PetsciiString::from(&String::from("GAMES")));
+/// let games = disk.list_entries()[0].clone();
+/// disk.enter_directory(&games);
+/// disk.create_directory(&PetsciiString::from(&String::from("ACTION")));
+///
+/// let mut dnp = Vec::new();
+/// disk.write_to_writer(&mut dnp).unwrap();
+/// assert_eq!(dnp.len(), 3 * 256 * 256);
+///
+/// let mut disk = Disk::<CmdNative>::new();
+/// disk.read_from_reader(&mut dnp.as_slice()).unwrap();
+/// assert_eq!(disk.num_tracks(), 3);
+///
+/// let games = disk.list_entries()[0].clone();
+/// disk.enter_directory(&games);
+/// let action = disk.list_entries()[0].clone();
+/// assert_eq!(String::from(&action.name), "ACTION");
+/// disk.enter_directory(&action);
+/// disk.leave_directory();
+/// assert_eq!(disk.current_directory(), Some(games.start_sector));
+/// disk.leave_directory();
+/// assert_eq!(disk.current_directory(), None);
+/// ```
+#[derive(Default)]
+pub struct CmdNative {}
+
+impl Geometry for CmdNative {
+    /// Maximum number of tracks of a native partition.
+    fn num_tracks(&self) -> u8 {
+        MAX_TRACKS
+    }
+
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        if track == 0 {
+            return 0;
+        }
+        SECTORS_PER_TRACK
+    }
+
+    fn bytes_per_sector(&self) -> u16 {
+        BYTES_PER_SECTOR as u16
+    }
+
+    /// Read the number of the last track from the BAM.
+    fn read_num_tracks(&self, first_track: &Track) -> Option<TrackNo> {
+        let bam_sector = first_track.get_sector(SECTOR_BAM.1);
+        let num_tracks = *bam_sector.get_byte(BAM_OFFSET_LAST_TRACK);
+        if *bam_sector.get_byte(BAM_OFFSET_VERSION) != DOS_VERSION || num_tracks == 0 {
+            return None;
+        }
+        Some(num_tracks)
+    }
+}
+
+impl Layout for CmdNative {
+    /// Type to contain a single entry for [Layout::list_entries].
+    type FileEntryType = FileEntry;
+    type StringType = PetsciiString;
+
+    fn get_disk_name(&self, disk: &Disk<Self>) -> PetsciiString
+    where
+        Self: Sized,
+    {
+        let sector = disk.get_sector(SECTOR_ROOT_HEADER);
+        let mut bytes = [0_u8; DISK_NAME_LENGTH];
+        sector.get_bytes(DISK_NAME_OFFSET_START, &mut bytes);
+        PetsciiString::fixed_size(&bytes)
+    }
+
+    fn set_disk_name(&self, disk: &mut Disk<Self>, new_name: &Self::StringType)
+    where
+        Self: Sized,
+    {
+        let sector = disk.get_sector_mut(SECTOR_ROOT_HEADER);
+        let name = new_name.as_slice();
+        let name = &name[..name.len().min(DISK_NAME_LENGTH)];
+        sector.fill(DISK_NAME_OFFSET_START, DISK_NAME_OFFSET_END, PETSCII_NBSP);
+        sector.set_bytes(DISK_NAME_OFFSET_START, name);
+    }
+
+    fn format_disk(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized,
+    {
+        self.clear_disk(disk);
+        self.initialize_header(disk);
+        self.set_disk_name(disk, &PetsciiString::from(&String::from("NONAME")));
+        self.initialize_bam(disk);
+        self.initialize_directory_listing(disk);
+    }
+
+    fn clear_disk(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized,
+    {
+        let sector_refs = self.get_all_sector_refs(disk);
+        clear_sector_refs(disk, &sector_refs);
+    }
+
+    /// List the file entries of the current directory.
+    fn list_entries(&self, disk: &Disk<Self>) -> Vec<FileEntry>
+    where
+        Self: Sized,
+    {
+        list_entries(disk, self.directory_header(disk))
+    }
+
+    fn read_file(&self, disk: &Disk<Self>, file_entry: &FileEntry) -> Vec<u8>
+    where
+        Self: Sized,
+    {
+        read_file(disk, file_entry)
+    }
+
+    /// Create a new file in the current directory.
+    fn create_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType, content: &[u8])
+    where
+        Self: Sized,
+    {
+        create_file(self, disk, file_entry, content)
+    }
+
+    /// Delete the given file. Directories are only deleted when they are
+    /// empty, like the CMD drives do.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    ///
+    /// let mut disk = Disk::with_num_tracks(CmdNative::default(), 3);
+    /// disk.format();
+    /// let num_unused_sectors = disk.num_unused_sectors();
+    /// disk.create_directory(&PetsciiString::from(&String::from("GAMES")));
+    /// let games = disk.list_entries()[0].clone();
+    /// disk.enter_directory(&games);
+    /// let entry = FileEntry {
+    ///     name: PetsciiString::from(&String::from("PONG")),
+    ///     ..FileEntry::default()
+    /// };
+    /// disk.create_file(&entry, &[1, 2, 3]);
+    /// disk.leave_directory();
+    ///
+    /// disk.delete_file(&games);
+    /// assert_eq!(disk.list_entries().len(), 1);
+    ///
+    /// disk.enter_directory(&games);
+    /// let pong = disk.list_entries()[0].clone();
+    /// disk.delete_file(&pong);
+    /// disk.leave_directory();
+    /// disk.delete_file(&games);
+    /// assert!(disk.list_entries().is_empty());
+    /// assert_eq!(disk.num_unused_sectors(), num_unused_sectors);
+    /// ```
+    fn delete_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType)
+    where
+        Self: Sized,
+    {
+        if file_entry.file_type == FileType::Directory
+            && !list_entries(disk, file_entry.start_sector).is_empty()
+        {
+            return;
+        }
+        delete_file(self, disk, file_entry)
+    }
+
+    fn num_unused_sectors(&self, disk: &mut Disk<Self>) -> usize
+    where
+        Self: Sized,
+    {
+        self.get_all_sector_refs(disk)
+            .into_iter()
+            .filter(|sector_ref| self.is_sector_unused(disk, *sector_ref))
+            .count()
+    }
+}
+
+impl DosLayout for CmdNative {
+    fn is_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) -> bool {
+        let (bam_sector, offset, mask) = self.get_bam_location(sector_ref);
+        (*disk.get_sector(bam_sector).get_byte(offset) & mask) != 0
+    }
+
+    fn mark_sector_used(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
+        let (bam_sector, offset, mask) = self.get_bam_location(sector_ref);
+        let sector = disk.get_sector_mut(bam_sector);
+        let availability = *sector.get_byte(offset);
+        sector.set_byte(offset, availability & !mask);
+    }
+
+    fn mark_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
+        let (bam_sector, offset, mask) = self.get_bam_location(sector_ref);
+        let sector = disk.get_sector_mut(bam_sector);
+        let availability = *sector.get_byte(offset);
+        sector.set_byte(offset, availability | mask);
+    }
+
+    fn uses_super_side_sector(&self) -> bool {
+        true
+    }
+
+    /// Header sector of the current directory.
+    fn directory_header(&self, disk: &Disk<Self>) -> SectorRef {
+        disk.current_directory().unwrap_or(SECTOR_ROOT_HEADER)
+    }
+}
+
+impl CmdNative {
+    /// All sector refs of the given disk. The number of tracks is taken from the
+    /// disk as native partitions don't have a fixed size.
+    fn get_all_sector_refs(&self, disk: &Disk<Self>) -> Vec<SectorRef> {
+        let mut sector_refs = Vec::new();
+        for track_no in 1..=disk.num_tracks() {
+            for sector_no in 0..self.num_sectors(track_no) {
+                sector_refs.push((track_no, sector_no as SectorNo));
+            }
+        }
+        sector_refs
+    }
+
+    /// Location of the bit inside the BAM for the given sector.
+    ///
+    /// The BAM of track 1 starts at offset 32 of [SECTOR_BAM], each following
+    /// track directly after its previous track. The highest bit of a byte
+    /// refers to the lowest sector.
+    fn get_bam_location(&self, sector_ref: SectorRef) -> (SectorRef, usize, u8) {
+        let byte_index = sector_ref.0 as usize * BAM_TRACK_SIZE + sector_ref.1 as usize / 8;
+        let bam_sector = (
+            SECTOR_BAM.0,
+            SECTOR_BAM.1 + (byte_index / BYTES_PER_SECTOR) as SectorNo,
+        );
+        let mask = 0x80 >> (sector_ref.1 % 8);
+        (bam_sector, byte_index % BYTES_PER_SECTOR, mask)
+    }
+
+    fn initialize_header(&self, disk: &mut Disk<Self>) {
+        let sector = disk.get_sector_mut(SECTOR_ROOT_HEADER);
+        set_next_sector(sector, SECTOR_ROOT_LISTING);
+        sector.set_byte(DOS_VERSION_OFFSET, DOS_VERSION);
+        sector.fill(DISK_NAME_OFFSET_START, HEADER_PADDING_END, PETSCII_NBSP);
+        sector.set_byte(DISK_ID_OFFSET, PETSCII_ZERO);
+        sector.set_byte(DISK_ID_OFFSET + 1, PETSCII_ONE);
+        sector.set_byte(DOS_TYPE_OFFSET, PETSCII_ONE);
+        sector.set_byte(DOS_TYPE_OFFSET + 1, DOS_VERSION);
+        sector.set_byte(HEADER_OFFSET_SELF, SECTOR_ROOT_HEADER.0);
+        sector.set_byte(HEADER_OFFSET_SELF + 1, SECTOR_ROOT_HEADER.1);
+    }
+
+    fn initialize_bam(&self, disk: &mut Disk<Self>) {
+        let num_tracks = disk.num_tracks();
+        let sector = disk.get_sector_mut(SECTOR_BAM);
+        end_sector_chain(sector);
+        sector.set_byte(BAM_OFFSET_VERSION, DOS_VERSION);
+        sector.set_byte(BAM_OFFSET_VERSION + 1, !DOS_VERSION);
+        sector.set_byte(BAM_OFFSET_DISK_ID, PETSCII_ZERO);
+        sector.set_byte(BAM_OFFSET_DISK_ID + 1, PETSCII_ONE);
+        sector.set_byte(BAM_OFFSET_IO_BYTE, BAM_IO_BYTE);
+        sector.set_byte(BAM_OFFSET_LAST_TRACK, num_tracks);
+
+        for sector_ref in self.get_all_sector_refs(disk) {
+            self.mark_sector_unused(disk, sector_ref);
+        }
+        for sector_no in 0..SECTOR_ROOT_LISTING.1 {
+            self.mark_sector_used(disk, (1, sector_no));
+        }
+    }
+
+    fn initialize_directory_listing(&self, disk: &mut Disk<Self>) {
+        let sector = disk.get_sector_mut(SECTOR_ROOT_LISTING);
+        end_sector_chain(sector);
+        self.mark_sector_used(disk, SECTOR_ROOT_LISTING);
+    }
+
+    /// Initialize the header sector of a subdirectory.
+    fn initialize_directory_header(
+        &self,
+        disk: &mut Disk<Self>,
+        header_ref: SectorRef,
+        listing_ref: SectorRef,
+        name: &PetsciiString,
+        parent_entry_ref: FileListEntryRef,
+    ) {
+        let parent_ref = self.directory_header(disk);
+        let mut disk_id = [0_u8; 2];
+        disk.get_sector(SECTOR_ROOT_HEADER)
+            .get_bytes(DISK_ID_OFFSET, &mut disk_id);
+
+        let sector = disk.get_sector_mut(header_ref);
+        set_next_sector(sector, listing_ref);
+        sector.set_byte(DOS_VERSION_OFFSET, DOS_VERSION);
+        sector.fill(DISK_NAME_OFFSET_START, HEADER_PADDING_END, PETSCII_NBSP);
+        let name = name.as_slice();
+        sector.set_bytes(
+            DISK_NAME_OFFSET_START,
+            &name[..name.len().min(DISK_NAME_LENGTH)],
+        );
+        sector.set_bytes(DISK_ID_OFFSET, &disk_id);
+        sector.set_byte(DOS_TYPE_OFFSET, PETSCII_ONE);
+        sector.set_byte(DOS_TYPE_OFFSET + 1, DOS_VERSION);
+        sector.set_byte(HEADER_OFFSET_SELF, header_ref.0);
+        sector.set_byte(HEADER_OFFSET_SELF + 1, header_ref.1);
+        sector.set_byte(HEADER_OFFSET_PARENT, parent_ref.0);
+        sector.set_byte(HEADER_OFFSET_PARENT + 1, parent_ref.1);
+        sector.set_byte(HEADER_OFFSET_PARENT_ENTRY, parent_entry_ref.0 .0);
+        sector.set_byte(HEADER_OFFSET_PARENT_ENTRY + 1, parent_entry_ref.0 .1);
+        sector.set_byte(
+            HEADER_OFFSET_PARENT_ENTRY_OFFSET,
+            (parent_entry_ref.1 * FILE_LIST_ENTRY_SIZE + SECTOR_HEADER_SIZE) as u8,
+        );
+    }
+}
+
+impl DirectoryLayout for CmdNative {
+    /// Enter the given subdirectory. Entries that aren't a directory are ignored.
+    fn enter_directory(&self, disk: &mut Disk<Self>, file_entry: &FileEntry) {
+        if file_entry.file_type == FileType::Directory {
+            disk.set_current_directory(Some(file_entry.start_sector));
+        }
+    }
+
+    /// Leave the current directory. Leaving the root directory is ignored.
+    fn leave_directory(&self, disk: &mut Disk<Self>) {
+        let header = disk.get_sector(self.directory_header(disk));
+        let parent_ref = (
+            *header.get_byte(HEADER_OFFSET_PARENT),
+            *header.get_byte(HEADER_OFFSET_PARENT + 1),
+        );
+        if parent_ref.0 == 0 || parent_ref == SECTOR_ROOT_HEADER {
+            disk.set_current_directory(None);
+        } else {
+            disk.set_current_directory(Some(parent_ref));
+        }
+    }
+
+    fn create_directory(&self, disk: &mut Disk<Self>, name: &PetsciiString) {
+        if let Some(sectors) = allocate_sectors(self, disk, DIRECTORY_NUM_SECTORS) {
+            clear_sector_refs(disk, &sectors);
+            let header_ref = sectors[0];
+            let listing_ref = sectors[1];
+            end_sector_chain(disk.get_sector_mut(listing_ref));
+
+            let file_entry = FileEntry {
+                name: name.clone(),
+                file_type: FileType::Directory,
+                num_sectors: DIRECTORY_NUM_SECTORS,
+                start_sector: header_ref,
+                ..FileEntry::default()
+            };
+            match create_file_list_entry(self, disk, &file_entry) {
+                Some(entry_ref) => {
+                    self.initialize_directory_header(disk, header_ref, listing_ref, name, entry_ref)
+                }
+                None => release_sectors(self, disk, &sectors),
+            }
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use crate::dos::*;
+use crate::{BlockAvailabilityMap, Commodore1571Geometry, FileEntry};
+use d64::{Disk, Geometry, Layout, SectorNo, SectorRef, TrackNo};
+use petscii::{PetsciiString, PETSCII_A, PETSCII_NBSP, PETSCII_ONE, PETSCII_TWO, PETSCII_ZERO};
+
+/// Number of tracks on a single side of the disk.
+const TRACKS_PER_SIDE: TrackNo = 35;
+/// Track number containing info about the disk, and files on the disk.
+const TRACK_HEADER: TrackNo = 18;
+/// Track containing the BAM of the second side. The rest of the track isn't
+/// used.
+const TRACK_SECOND_BAM: TrackNo = TRACK_HEADER + TRACKS_PER_SIDE;
+/// Reference to the sector containing the BAM of the first side, disk name and
+/// disk id.
+const SECTOR_DISK_HEADER: SectorRef = (TRACK_HEADER, 0);
+/// Reference to the sector containing the bitmaps of the second side.
+const SECTOR_SECOND_BAM: SectorRef = (TRACK_SECOND_BAM, 0);
+/// Default sector where to start the file list stored on the disk.
+const SECTOR_DISK_LISTING: SectorRef = (TRACK_HEADER, 1);
+const DOS_VERSION_OFFSET: usize = 2;
+const DOUBLE_SIDED_OFFSET: usize = 3;
+const DOUBLE_SIDED: u8 = 0x80;
+/// Offset in the header sector of the number of unused sectors of the tracks
+/// of the second side.
+const SECOND_SIDE_UNUSED_OFFSET: usize = 0xdd;
+/// Size of the bitmap of a single track of the second side.
+const SECOND_SIDE_BITMAP_SIZE: usize = 3;
+const DISK_NAME_OFFSET_START: usize = 9 * 16;
+const DISK_NAME_LENGTH: usize = 16;
+const DISK_NAME_OFFSET_END: usize = DISK_NAME_OFFSET_START + DISK_NAME_LENGTH;
+const DISK_ID_OFFSET: usize = 162;
+
+/// Commodore 1571 disk-drive (D71 files).
+///
+/// Uses both sides of the disk with the 1541 layout, see
+/// [Commodore1571Geometry]. Tracks 36-70 are the tracks of the second side.
+/// The header sector (18/0) contains the BAM of the first side and the number
+/// of unused sectors of the second side. The bitmaps of the second side are
+/// stored on 53/0; the rest of track 53 isn't used.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore1571>::new();
+/// disk.format();
+/// assert_eq!(disk.as_bytes().len(), 349696);
+/// assert_eq!(disk.num_unused_sectors(), 1328);
+///
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("BOTHSIDES")),
+///     file_type: FileType::Program,
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &vec![7; 1000 * 254]);
+/// assert_eq!(disk.num_unused_sectors(), 328);
+///
+/// let entries = disk.list_entries();
+/// assert_eq!(entries[0].num_sectors, 1000);
+/// assert_eq!(disk.read_file(&entries[0]), vec![7; 1000 * 254]);
+///
+/// disk.delete_file(&entries[0]);
+/// assert_eq!(disk.num_unused_sectors(), 1328);
+/// ```
+#[derive(Default)]
+pub struct Commodore1571 {}
+
+impl Geometry for Commodore1571 {
+    fn num_tracks(&self) -> u8 {
+        Commodore1571Geometry::default().num_tracks()
+    }
+
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        Commodore1571Geometry::default().num_sectors(track)
+    }
+
+    fn bytes_per_sector(&self) -> u16 {
+        Commodore1571Geometry::default().bytes_per_sector()
+    }
+}
+
+/// Commodore DOS file system on the [Commodore1571Geometry].
+impl Layout for Commodore1571 {
+    /// Type to contain a single entry for [Layout::list_entries].
+    type FileEntryType = FileEntry;
+    type StringType = PetsciiString;
+
+    fn get_disk_name(&self, disk: &Disk<Self>) -> PetsciiString
+    where
+        Self: Sized,
+    {
+        let sector = disk.get_sector(SECTOR_DISK_HEADER);
+        let mut bytes = [0_u8; DISK_NAME_LENGTH];
+        sector.get_bytes(DISK_NAME_OFFSET_START, &mut bytes);
+        PetsciiString::fixed_size(&bytes)
+    }
+
+    fn set_disk_name(&self, disk: &mut Disk<Self>, new_name: &Self::StringType)
+    where
+        Self: Sized,
+    {
+        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        let name = new_name.as_slice();
+        let name = &name[..name.len().min(DISK_NAME_LENGTH)];
+        sector.fill(DISK_NAME_OFFSET_START, DISK_NAME_OFFSET_END, PETSCII_NBSP);
+        sector.set_bytes(DISK_NAME_OFFSET_START, name);
+    }
+
+    fn format_disk(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized,
+    {
+        self.clear_disk(disk);
+        self.initialize_header(disk);
+        self.initialize_bam(disk);
+        self.set_disk_name(disk, &PetsciiString::from(&String::from("NONAME")));
+        self.initialize_directory_listing(disk);
+    }
+
+    fn clear_disk(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized,
+    {
+        let sector_refs = get_all_sector_refs(self);
+        clear_sector_refs(disk, &sector_refs);
+    }
+
+    fn list_entries(&self, disk: &Disk<Self>) -> Vec<FileEntry>
+    where
+        Self: Sized,
+    {
+        list_entries(disk, SECTOR_DISK_HEADER)
+    }
+
+    fn read_file(&self, disk: &Disk<Self>, file_entry: &FileEntry) -> Vec<u8>
+    where
+        Self: Sized,
+    {
+        read_file(disk, file_entry)
+    }
+
+    fn create_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType, content: &[u8])
+    where
+        Self: Sized,
+    {
+        create_file(self, disk, file_entry, content)
+    }
+
+    fn delete_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType)
+    where
+        Self: Sized,
+    {
+        delete_file(self, disk, file_entry)
+    }
+
+    fn num_unused_sectors(&self, disk: &mut Disk<Self>) -> usize
+    where
+        Self: Sized,
+    {
+        (1..=self.num_tracks())
+            .filter(|track_no| !self.is_system_track(*track_no))
+            .map(|track_no| self.count_unused_track_sectors(disk, track_no) as usize)
+            .sum()
+    }
+}
+
+impl DosLayout for Commodore1571 {
+    fn is_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) -> bool {
+        if sector_ref.0 <= TRACKS_PER_SIDE {
+            let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+            return BlockAvailabilityMap::new(sector).is_unused(sector_ref);
+        }
+        let (offset, bit_mask) = self.second_side_bitmap_offset(sector_ref);
+        *disk.get_sector(SECTOR_SECOND_BAM).get_byte(offset) & bit_mask != 0
+    }
+
+    fn mark_sector_used(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
+        self.set_availability(disk, sector_ref, false);
+    }
+
+    fn mark_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
+        self.set_availability(disk, sector_ref, true);
+    }
+
+    /// The header track is reserved for the directory listing, the track of
+    /// the second BAM isn't used.
+    fn is_system_track(&self, track_no: TrackNo) -> bool {
+        track_no == TRACK_HEADER || track_no == TRACK_SECOND_BAM
+    }
+
+    fn directory_header(&self, _disk: &Disk<Self>) -> SectorRef {
+        SECTOR_DISK_HEADER
+    }
+
+    /// Allocate a new sector for the directory listing on the header track.
+    fn allocate_file_list_sector(&self, disk: &mut Disk<Self>) -> Option<SectorRef> {
+        allocate_track_sector(self, disk, TRACK_HEADER)
+    }
+}
+
+impl Commodore1571 {
+    /// Offset in the header sector of the number of unused sectors of a track
+    /// of the second side.
+    fn second_side_unused_offset(&self, track_no: TrackNo) -> usize {
+        SECOND_SIDE_UNUSED_OFFSET + (track_no - TRACKS_PER_SIDE - 1) as usize
+    }
+
+    /// Offset in the second BAM sector of the bitmap byte containing the given
+    /// sector, together with the bit of the sector.
+    fn second_side_bitmap_offset(&self, sector_ref: SectorRef) -> (usize, u8) {
+        let track_offset = (sector_ref.0 - TRACKS_PER_SIDE - 1) as usize * SECOND_SIDE_BITMAP_SIZE;
+        (
+            track_offset + sector_ref.1 as usize / 8,
+            1 << (sector_ref.1 % 8),
+        )
+    }
+
+    fn count_unused_track_sectors(&self, disk: &mut Disk<Self>, track_no: TrackNo) -> u8 {
+        if track_no <= TRACKS_PER_SIDE {
+            let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+            return BlockAvailabilityMap::new(sector).count_unused_track_sectors(track_no);
+        }
+        let offset = self.second_side_unused_offset(track_no);
+        *disk.get_sector(SECTOR_DISK_HEADER).get_byte(offset)
+    }
+
+    fn set_availability(&self, disk: &mut Disk<Self>, sector_ref: SectorRef, unused: bool) {
+        if sector_ref.0 <= TRACKS_PER_SIDE {
+            let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+            let mut bam = BlockAvailabilityMap::new(sector);
+            if unused {
+                bam.mark_unused(sector_ref);
+            } else {
+                bam.mark_used(sector_ref);
+            }
+            return;
+        }
+
+        if self.is_sector_unused(disk, sector_ref) == unused {
+            return;
+        }
+        let (offset, bit_mask) = self.second_side_bitmap_offset(sector_ref);
+        let bam = disk.get_sector_mut(SECTOR_SECOND_BAM);
+        let availability = *bam.get_byte(offset);
+        bam.set_byte(offset, availability ^ bit_mask);
+
+        let offset = self.second_side_unused_offset(sector_ref.0);
+        let header = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        let sectors_free = *header.get_byte(offset);
+        if unused {
+            header.set_byte(offset, sectors_free + 1);
+        } else {
+            header.set_byte(offset, sectors_free - 1);
+        }
+    }
+
+    // Initialize the dos version, double sided flag and disk ID default=01-2A
+    fn initialize_header(&self, disk: &mut Disk<Self>) {
+        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        sector.set_byte(DOS_VERSION_OFFSET, PETSCII_A);
+        sector.set_byte(DOUBLE_SIDED_OFFSET, DOUBLE_SIDED);
+        for offset in 160..171 {
+            sector.set_byte(offset, PETSCII_NBSP);
+        }
+        sector.set_byte(DISK_ID_OFFSET, PETSCII_ZERO);
+        sector.set_byte(DISK_ID_OFFSET + 1, PETSCII_ONE);
+        sector.set_byte(165, PETSCII_TWO);
+        sector.set_byte(166, PETSCII_A);
+    }
+
+    fn initialize_bam(&self, disk: &mut Disk<Self>) {
+        for sector_ref in get_all_sector_refs(self) {
+            self.mark_sector_unused(disk, sector_ref);
+        }
+        self.mark_sector_used(disk, SECTOR_DISK_HEADER);
+        for sector_no in 0..self.num_sectors(TRACK_SECOND_BAM) {
+            self.mark_sector_used(disk, (TRACK_SECOND_BAM, sector_no as SectorNo));
+        }
+    }
+
+    fn initialize_directory_listing(&self, disk: &mut Disk<Self>) {
+        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        set_next_sector(sector, SECTOR_DISK_LISTING);
+
+        let sector = disk.get_sector_mut(SECTOR_DISK_LISTING);
+        end_sector_chain(sector);
+        self.mark_sector_used(disk, SECTOR_DISK_LISTING);
+    }
+}
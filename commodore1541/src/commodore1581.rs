@@ -0,0 +1,267 @@
+use crate::dos::*;
+use crate::{BlockAvailabilityMap, Commodore1581Geometry, FileEntry};
+use d64::{Disk, Geometry, Layout, SectorRef, TrackNo};
+use petscii::{PetsciiString, PETSCII_D, PETSCII_NBSP, PETSCII_ONE, PETSCII_THREE, PETSCII_ZERO};
+
+/// Track number containing info about the disk, and files on the disk.
+const TRACK_HEADER: TrackNo = 40;
+/// Reference to the sector containing the disk name and disk id.
+const SECTOR_DISK_HEADER: SectorRef = (TRACK_HEADER, 0);
+/// Default sector where to start the file list stored on the disk.
+const SECTOR_DISK_LISTING: SectorRef = (TRACK_HEADER, 3);
+/// Number of tracks that are stored inside a single BAM sector.
+const BAM_TRACKS_PER_SECTOR: TrackNo = 40;
+const BAM_DOS_VERSION_INVERTED_OFFSET: usize = 3;
+const BAM_DISK_ID_OFFSET: usize = 4;
+const BAM_IO_BYTE_OFFSET: usize = 6;
+/// Verify and CRC check enabled.
+const BAM_IO_BYTE: u8 = 0xc0;
+const BAM_ENTRY_OFFSET: usize = 0x10;
+/// One byte for the number of unused sectors followed by a bitmap of 5 bytes.
+const BAM_ENTRY_SIZE: usize = 6;
+const DOS_VERSION_OFFSET: usize = 2;
+const DOS_VERSION: u8 = PETSCII_D;
+const DISK_NAME_OFFSET_START: usize = 4;
+const DISK_NAME_LENGTH: usize = 16;
+const DISK_NAME_OFFSET_END: usize = DISK_NAME_OFFSET_START + DISK_NAME_LENGTH;
+const DISK_ID_OFFSET: usize = 0x16;
+const DOS_TYPE_OFFSET: usize = 0x19;
+const HEADER_PADDING_END: usize = 0x1d;
+
+/// Commodore 1581 disk-drive (D81 files).
+///
+/// The 3.5" disks have 80 tracks of 40 sectors. The disk name and id are
+/// stored on 40/0, followed by two BAM sectors (40/1 and 40/2) each
+/// containing the availability of 40 tracks. The directory listing starts at
+/// 40/3.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore1581>::new();
+/// disk.format();
+/// disk.set_name(&PetsciiString::from(&String::from("THREEANDHALF")));
+/// assert_eq!(disk.as_bytes().len(), 819200);
+/// assert_eq!(disk.num_unused_sectors(), 3160);
+///
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("HELLO")),
+///     file_type: FileType::Program,
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &[42; 1000]);
+/// assert_eq!(disk.num_unused_sectors(), 3156);
+///
+/// let entries = disk.list_entries();
+/// assert_eq!(entries.len(), 1);
+/// assert_eq!(String::from(&entries[0].name), "HELLO");
+/// assert_eq!(disk.read_file(&entries[0]), vec![42; 1000]);
+/// assert_eq!(String::from(&disk.get_name()), "THREEANDHALF");
+/// ```
+#[derive(Default)]
+pub struct Commodore1581 {}
+
+impl Geometry for Commodore1581 {
+    fn num_tracks(&self) -> u8 {
+        Commodore1581Geometry::default().num_tracks()
+    }
+
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        Commodore1581Geometry::default().num_sectors(track)
+    }
+
+    fn bytes_per_sector(&self) -> u16 {
+        Commodore1581Geometry::default().bytes_per_sector()
+    }
+}
+
+/// Commodore DOS file system on the [Commodore1581Geometry].
+impl Layout for Commodore1581 {
+    /// Type to contain a single entry for [Layout::list_entries].
+    type FileEntryType = FileEntry;
+    type StringType = PetsciiString;
+
+    fn get_disk_name(&self, disk: &Disk<Self>) -> PetsciiString
+    where
+        Self: Sized,
+    {
+        let sector = disk.get_sector(SECTOR_DISK_HEADER);
+        let mut bytes = [0_u8; DISK_NAME_LENGTH];
+        sector.get_bytes(DISK_NAME_OFFSET_START, &mut bytes);
+        PetsciiString::fixed_size(&bytes)
+    }
+
+    fn set_disk_name(&self, disk: &mut Disk<Self>, new_name: &Self::StringType)
+    where
+        Self: Sized,
+    {
+        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        let name = new_name.as_slice();
+        let name = &name[..name.len().min(DISK_NAME_LENGTH)];
+        sector.fill(DISK_NAME_OFFSET_START, DISK_NAME_OFFSET_END, PETSCII_NBSP);
+        sector.set_bytes(DISK_NAME_OFFSET_START, name);
+    }
+
+    fn format_disk(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized,
+    {
+        self.clear_disk(disk);
+        self.initialize_header(disk);
+        self.set_disk_name(disk, &PetsciiString::from(&String::from("NONAME")));
+        self.initialize_bam(disk);
+        self.initialize_directory_listing(disk);
+    }
+
+    fn clear_disk(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized,
+    {
+        let sector_refs = get_all_sector_refs(self);
+        clear_sector_refs(disk, &sector_refs);
+    }
+
+    fn list_entries(&self, disk: &Disk<Self>) -> Vec<FileEntry>
+    where
+        Self: Sized,
+    {
+        list_entries(disk, SECTOR_DISK_HEADER)
+    }
+
+    fn read_file(&self, disk: &Disk<Self>, file_entry: &FileEntry) -> Vec<u8>
+    where
+        Self: Sized,
+    {
+        read_file(disk, file_entry)
+    }
+
+    fn create_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType, content: &[u8])
+    where
+        Self: Sized,
+    {
+        create_file(self, disk, file_entry, content)
+    }
+
+    fn delete_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType)
+    where
+        Self: Sized,
+    {
+        delete_file(self, disk, file_entry)
+    }
+
+    fn num_unused_sectors(&self, disk: &mut Disk<Self>) -> usize
+    where
+        Self: Sized,
+    {
+        let mut result = 0;
+        for track_no in 1..=self.num_tracks() {
+            if track_no == TRACK_HEADER {
+                continue;
+            }
+            let bam = self.get_block_availability_map(disk, track_no);
+            result += bam.count_unused_track_sectors(track_no) as usize;
+        }
+        result
+    }
+}
+
+impl DosLayout for Commodore1581 {
+    fn is_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) -> bool {
+        self.get_block_availability_map(disk, sector_ref.0)
+            .is_unused(sector_ref)
+    }
+
+    fn mark_sector_used(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
+        self.get_block_availability_map(disk, sector_ref.0)
+            .mark_used(sector_ref);
+    }
+
+    fn mark_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
+        self.get_block_availability_map(disk, sector_ref.0)
+            .mark_unused(sector_ref);
+    }
+
+    /// The header track is reserved for the directory listing.
+    fn is_system_track(&self, track_no: TrackNo) -> bool {
+        track_no == TRACK_HEADER
+    }
+
+    fn uses_super_side_sector(&self) -> bool {
+        true
+    }
+
+    fn directory_header(&self, _disk: &Disk<Self>) -> SectorRef {
+        SECTOR_DISK_HEADER
+    }
+
+    /// Allocate a new sector for the directory listing on the header track.
+    fn allocate_file_list_sector(&self, disk: &mut Disk<Self>) -> Option<SectorRef> {
+        allocate_track_sector(self, disk, TRACK_HEADER)
+    }
+}
+
+impl Commodore1581 {
+    fn get_bam_sector(&self, bam_index: u8) -> SectorRef {
+        (TRACK_HEADER, bam_index + 1)
+    }
+
+    /// Get the BAM sector that contains the availability of the given track.
+    fn get_block_availability_map<'a>(
+        &self,
+        disk: &'a mut Disk<Self>,
+        track_no: TrackNo,
+    ) -> BlockAvailabilityMap<'a> {
+        let bam_index = (track_no - 1) / BAM_TRACKS_PER_SECTOR;
+        let first_track = bam_index * BAM_TRACKS_PER_SECTOR + 1;
+        let sector = disk.get_sector_mut(self.get_bam_sector(bam_index));
+        BlockAvailabilityMap::with_entries(sector, first_track, BAM_ENTRY_OFFSET, BAM_ENTRY_SIZE)
+    }
+
+    fn initialize_header(&self, disk: &mut Disk<Self>) {
+        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        set_next_sector(sector, SECTOR_DISK_LISTING);
+        sector.set_byte(DOS_VERSION_OFFSET, DOS_VERSION);
+        sector.fill(DISK_NAME_OFFSET_START, HEADER_PADDING_END, PETSCII_NBSP);
+        sector.set_byte(DISK_ID_OFFSET, PETSCII_ZERO);
+        sector.set_byte(DISK_ID_OFFSET + 1, PETSCII_ONE);
+        sector.set_byte(DOS_TYPE_OFFSET, PETSCII_THREE);
+        sector.set_byte(DOS_TYPE_OFFSET + 1, DOS_VERSION);
+    }
+
+    fn initialize_bam(&self, disk: &mut Disk<Self>) {
+        let num_bam_sectors = self.num_tracks() / BAM_TRACKS_PER_SECTOR;
+        for bam_index in 0..num_bam_sectors {
+            let next_sector = if bam_index + 1 == num_bam_sectors {
+                SECTOR_END_OF_CHAIN
+            } else {
+                self.get_bam_sector(bam_index + 1)
+            };
+            let sector = disk.get_sector_mut(self.get_bam_sector(bam_index));
+            set_next_sector(sector, next_sector);
+            sector.set_byte(DOS_VERSION_OFFSET, DOS_VERSION);
+            sector.set_byte(BAM_DOS_VERSION_INVERTED_OFFSET, !DOS_VERSION);
+            sector.set_byte(BAM_DISK_ID_OFFSET, PETSCII_ZERO);
+            sector.set_byte(BAM_DISK_ID_OFFSET + 1, PETSCII_ONE);
+            sector.set_byte(BAM_IO_BYTE_OFFSET, BAM_IO_BYTE);
+        }
+
+        for sector_ref in get_all_sector_refs(self) {
+            self.mark_sector_unused(disk, sector_ref);
+        }
+
+        self.mark_sector_used(disk, SECTOR_DISK_HEADER);
+        for bam_index in 0..num_bam_sectors {
+            self.mark_sector_used(disk, self.get_bam_sector(bam_index));
+        }
+    }
+
+    fn initialize_directory_listing(&self, disk: &mut Disk<Self>) {
+        let sector = disk.get_sector_mut(SECTOR_DISK_LISTING);
+        end_sector_chain(sector);
+        self.mark_sector_used(disk, SECTOR_DISK_LISTING);
+    }
+}
@@ -0,0 +1,345 @@
+use crate::dos::*;
+use crate::{BlockAvailabilityMap, FileEntry};
+use d64::{Disk, Geometry, Layout, SectorRef, TrackNo, X64Layout};
+use petscii::{PetsciiString, PETSCII_C, PETSCII_NBSP, PETSCII_ONE, PETSCII_TWO, PETSCII_ZERO};
+
+/// Number of tracks on a single side of the disk.
+const TRACKS_PER_SIDE: TrackNo = 77;
+/// Device types of X64 files, as defined by VICE.
+const X64_DEVICE_TYPE_8050: u8 = 32;
+const X64_DEVICE_TYPE_8250: u8 = 49;
+/// Track containing the BAM.
+const TRACK_BAM: TrackNo = 38;
+/// Track number containing info about the disk, and files on the disk.
+const TRACK_HEADER: TrackNo = 39;
+/// Reference to the sector containing the disk name and disk id.
+const SECTOR_DISK_HEADER: SectorRef = (TRACK_HEADER, 0);
+/// Default sector where to start the file list stored on the disk.
+const SECTOR_DISK_LISTING: SectorRef = (TRACK_HEADER, 1);
+const BYTES_PER_SECTOR: usize = 256;
+/// Number of sectors between two BAM sectors on [TRACK_BAM].
+const BAM_SECTOR_INTERLEAVE: u8 = 3;
+/// Number of tracks that are stored inside a single BAM sector.
+const BAM_TRACKS_PER_SECTOR: TrackNo = 50;
+const BAM_OFFSET_FIRST_TRACK: usize = 4;
+const BAM_OFFSET_LAST_TRACK: usize = 5;
+const BAM_ENTRY_OFFSET: usize = 6;
+/// One byte for the number of unused sectors followed by a bitmap of 4 bytes.
+const BAM_ENTRY_SIZE: usize = 5;
+const DOS_VERSION_OFFSET: usize = 2;
+const DOS_VERSION: u8 = PETSCII_C;
+const DISK_NAME_OFFSET_START: usize = 6;
+const DISK_NAME_LENGTH: usize = 16;
+const DISK_NAME_OFFSET_END: usize = DISK_NAME_OFFSET_START + DISK_NAME_LENGTH;
+const DISK_ID_OFFSET: usize = 0x18;
+const DOS_TYPE_OFFSET: usize = 0x1b;
+const HEADER_PADDING_END: usize = 0x21;
+
+/// Commodore 8050 and 8250 disk-drives.
+///
+/// The 8050 is a single sided drive with 77 tracks. The 8250 uses the same
+/// layout on both sides of the disk, resulting in 154 tracks. Use the
+/// [Commodore8050] and [Commodore8250] aliases.
+///
+/// The disk name and id are stored on 39/0. The BAM is spread over multiple
+/// sectors on track 38, each containing the availability of 50 tracks. The
+/// header, BAM sectors and directory listing are chained together.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore8250>::new();
+/// disk.format();
+/// disk.set_name(&PetsciiString::from(&String::from("PETDISK")));
+///
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("HELLO")),
+///     file_type: FileType::Sequence,
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &[42; 1000]);
+/// assert_eq!(disk.num_unused_sectors(), 4129);
+///
+/// let entries = disk.list_entries();
+/// assert_eq!(entries.len(), 1);
+/// assert_eq!(String::from(&entries[0].name), "HELLO");
+/// assert_eq!(entries[0].num_sectors, 4);
+/// assert_eq!(&disk.read_file(&entries[0])[..1000], &[42; 1000]);
+/// assert_eq!(String::from(&disk.get_name()), "PETDISK");
+/// ```
+#[derive(Default)]
+pub struct Commodore8x50<const NUM_TRACKS: TrackNo> {}
+
+/// Commodore 8050 disk-drive (D80 files).
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+///
+/// let mut disk = Disk::<Commodore8050>::new();
+/// disk.format();
+///
+/// assert_eq!(disk.num_unused_sectors(), 2052);
+///
+/// let mut bytes = Vec::new();
+/// disk.write_x64_to_writer(&mut bytes, "").unwrap();
+/// assert_eq!(bytes[6], 32);
+/// ```
+pub type Commodore8050 = Commodore8x50<TRACKS_PER_SIDE>;
+
+/// Commodore 8250 disk-drive (D82 files).
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+///
+/// let mut disk = Disk::<Commodore8250>::new();
+/// disk.format();
+///
+/// assert_eq!(disk.num_unused_sectors(), 4133);
+///
+/// let mut bytes = Vec::new();
+/// disk.write_x64_to_writer(&mut bytes, "").unwrap();
+/// assert_eq!(bytes[6], 49);
+/// ```
+pub type Commodore8250 = Commodore8x50<{ 2 * TRACKS_PER_SIDE }>;
+
+impl<const NUM_TRACKS: TrackNo> Geometry for Commodore8x50<NUM_TRACKS> {
+    fn num_tracks(&self) -> u8 {
+        NUM_TRACKS
+    }
+
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        if track == 0 || track > NUM_TRACKS {
+            return 0;
+        }
+        let track_on_side = (track - 1) % TRACKS_PER_SIDE + 1;
+        if (1..=39).contains(&track_on_side) {
+            return 29;
+        }
+        if (40..=53).contains(&track_on_side) {
+            return 27;
+        }
+        if (54..=64).contains(&track_on_side) {
+            return 25;
+        }
+        23
+    }
+
+    fn bytes_per_sector(&self) -> u16 {
+        BYTES_PER_SECTOR as u16
+    }
+}
+
+impl<const NUM_TRACKS: TrackNo> Layout for Commodore8x50<NUM_TRACKS> {
+    /// Type to contain a single entry for [Layout::list_entries].
+    type FileEntryType = FileEntry;
+    type StringType = PetsciiString;
+
+    fn get_disk_name(&self, disk: &Disk<Self>) -> PetsciiString
+    where
+        Self: Sized,
+    {
+        let sector = disk.get_sector(SECTOR_DISK_HEADER);
+        let mut bytes = [0_u8; DISK_NAME_LENGTH];
+        sector.get_bytes(DISK_NAME_OFFSET_START, &mut bytes);
+        PetsciiString::fixed_size(&bytes)
+    }
+
+    fn set_disk_name(&self, disk: &mut Disk<Self>, new_name: &Self::StringType)
+    where
+        Self: Sized,
+    {
+        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        let name = new_name.as_slice();
+        let name = &name[..name.len().min(DISK_NAME_LENGTH)];
+        sector.fill(DISK_NAME_OFFSET_START, DISK_NAME_OFFSET_END, PETSCII_NBSP);
+        sector.set_bytes(DISK_NAME_OFFSET_START, name);
+    }
+
+    fn format_disk(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized,
+    {
+        self.clear_disk(disk);
+        self.initialize_header(disk);
+        self.set_disk_name(disk, &PetsciiString::from(&String::from("NONAME")));
+        self.initialize_bam(disk);
+        self.initialize_directory_listing(disk);
+    }
+
+    fn clear_disk(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized,
+    {
+        let sector_refs = get_all_sector_refs(self);
+        clear_sector_refs(disk, &sector_refs);
+    }
+
+    fn list_entries(&self, disk: &Disk<Self>) -> Vec<FileEntry>
+    where
+        Self: Sized,
+    {
+        list_entries(disk, self.get_last_bam_sector())
+    }
+
+    fn read_file(&self, disk: &Disk<Self>, file_entry: &FileEntry) -> Vec<u8>
+    where
+        Self: Sized,
+    {
+        read_file(disk, file_entry)
+    }
+
+    fn create_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType, content: &[u8])
+    where
+        Self: Sized,
+    {
+        create_file(self, disk, file_entry, content)
+    }
+
+    fn delete_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType)
+    where
+        Self: Sized,
+    {
+        delete_file(self, disk, file_entry)
+    }
+
+    fn num_unused_sectors(&self, disk: &mut Disk<Self>) -> usize
+    where
+        Self: Sized,
+    {
+        let mut result = 0;
+        for track_no in 1..=self.num_tracks() {
+            if track_no == TRACK_HEADER {
+                continue;
+            }
+            let bam = self.get_block_availability_map(disk, track_no);
+            result += bam.count_unused_track_sectors(track_no) as usize;
+        }
+        result
+    }
+}
+
+impl<const NUM_TRACKS: TrackNo> X64Layout for Commodore8x50<NUM_TRACKS> {
+    fn x64_device_type(&self) -> u8 {
+        if NUM_TRACKS > TRACKS_PER_SIDE {
+            X64_DEVICE_TYPE_8250
+        } else {
+            X64_DEVICE_TYPE_8050
+        }
+    }
+
+    fn x64_num_sides(&self) -> u8 {
+        NUM_TRACKS.div_ceil(TRACKS_PER_SIDE)
+    }
+}
+
+impl<const NUM_TRACKS: TrackNo> DosLayout for Commodore8x50<NUM_TRACKS> {
+    fn is_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) -> bool {
+        self.get_block_availability_map(disk, sector_ref.0)
+            .is_unused(sector_ref)
+    }
+
+    fn mark_sector_used(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
+        self.get_block_availability_map(disk, sector_ref.0)
+            .mark_used(sector_ref);
+    }
+
+    fn mark_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
+        self.get_block_availability_map(disk, sector_ref.0)
+            .mark_unused(sector_ref);
+    }
+
+    /// The header track is reserved for the directory listing.
+    fn is_system_track(&self, track_no: TrackNo) -> bool {
+        track_no == TRACK_HEADER
+    }
+
+    fn directory_header(&self, _disk: &Disk<Self>) -> SectorRef {
+        self.get_last_bam_sector()
+    }
+
+    /// Allocate a new sector for the directory listing on the header track.
+    fn allocate_file_list_sector(&self, disk: &mut Disk<Self>) -> Option<SectorRef> {
+        allocate_track_sector(self, disk, TRACK_HEADER)
+    }
+}
+
+impl<const NUM_TRACKS: TrackNo> Commodore8x50<NUM_TRACKS> {
+    fn num_bam_sectors(&self) -> u8 {
+        (self.num_tracks() - 1) / BAM_TRACKS_PER_SECTOR + 1
+    }
+
+    fn get_bam_sector(&self, bam_index: u8) -> SectorRef {
+        (TRACK_BAM, bam_index * BAM_SECTOR_INTERLEAVE)
+    }
+
+    fn get_last_bam_sector(&self) -> SectorRef {
+        self.get_bam_sector(self.num_bam_sectors() - 1)
+    }
+
+    /// Get the BAM sector that contains the availability of the given track.
+    fn get_block_availability_map<'a>(
+        &self,
+        disk: &'a mut Disk<Self>,
+        track_no: TrackNo,
+    ) -> BlockAvailabilityMap<'a> {
+        let bam_index = (track_no - 1) / BAM_TRACKS_PER_SECTOR;
+        let first_track = bam_index * BAM_TRACKS_PER_SECTOR + 1;
+        let sector = disk.get_sector_mut(self.get_bam_sector(bam_index));
+        BlockAvailabilityMap::with_entries(sector, first_track, BAM_ENTRY_OFFSET, BAM_ENTRY_SIZE)
+    }
+
+    fn initialize_header(&self, disk: &mut Disk<Self>) {
+        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        set_next_sector(sector, self.get_bam_sector(0));
+        sector.set_byte(DOS_VERSION_OFFSET, DOS_VERSION);
+        sector.fill(DISK_NAME_OFFSET_START, HEADER_PADDING_END, PETSCII_NBSP);
+        sector.set_byte(DISK_ID_OFFSET, PETSCII_ZERO);
+        sector.set_byte(DISK_ID_OFFSET + 1, PETSCII_ONE);
+        sector.set_byte(DOS_TYPE_OFFSET, PETSCII_TWO);
+        sector.set_byte(DOS_TYPE_OFFSET + 1, DOS_VERSION);
+    }
+
+    fn initialize_bam(&self, disk: &mut Disk<Self>) {
+        let num_bam_sectors = self.num_bam_sectors();
+        for bam_index in 0..num_bam_sectors {
+            let next_sector = if bam_index + 1 == num_bam_sectors {
+                SECTOR_DISK_LISTING
+            } else {
+                self.get_bam_sector(bam_index + 1)
+            };
+            let first_track = bam_index * BAM_TRACKS_PER_SECTOR + 1;
+            let last_track = (first_track + BAM_TRACKS_PER_SECTOR - 1).min(self.num_tracks());
+
+            let sector = disk.get_sector_mut(self.get_bam_sector(bam_index));
+            set_next_sector(sector, next_sector);
+            sector.set_byte(DOS_VERSION_OFFSET, DOS_VERSION);
+            sector.set_byte(BAM_OFFSET_FIRST_TRACK, first_track);
+            sector.set_byte(BAM_OFFSET_LAST_TRACK, last_track + 1);
+        }
+
+        for sector_ref in get_all_sector_refs(self) {
+            self.mark_sector_unused(disk, sector_ref);
+        }
+
+        self.mark_sector_used(disk, SECTOR_DISK_HEADER);
+        for bam_index in 0..num_bam_sectors {
+            self.mark_sector_used(disk, self.get_bam_sector(bam_index));
+        }
+    }
+
+    fn initialize_directory_listing(&self, disk: &mut Disk<Self>) {
+        let sector = disk.get_sector_mut(SECTOR_DISK_LISTING);
+        end_sector_chain(sector);
+        self.mark_sector_used(disk, SECTOR_DISK_LISTING);
+    }
+}
@@ -0,0 +1,798 @@
+//! CP/M file system as used by the Commodore 128 on 1541 and 1571 disks.
+//!
+//! CP/M divides the tracks after the reserved (system) tracks into allocation
+//! blocks. The directory is stored in the first blocks. Each directory entry
+//! describes an extent of a file: the user area, name, extent number, number
+//! of records (128 bytes) and the blocks that contain the content.
+//!
+//! Formatted disks get a Commodore DOS header with all sectors allocated, and
+//! a boot sector that identifies them as Commodore 128 disks. The layout
+//! hasn't been compared with disks formatted by C128 CP/M yet.
+use crate::dos::{clear_sector_refs, get_all_sector_refs};
+use crate::{Commodore1541Geometry, Commodore1571Geometry};
+use d64::{Disk, Geometry, Layout, SectorNo, SectorRef, TrackNo};
+
+const DIRECTORY_ENTRY_SIZE: usize = 32;
+/// Size of a CP/M record; file sizes are a multiple of records.
+const RECORD_SIZE: usize = 128;
+/// Number of records in a logical extent.
+const RECORDS_PER_EXTENT: usize = 128;
+/// Number of logical extents that can be addressed by the low byte of the
+/// extent number.
+const EXTENTS_PER_LOW_BYTE: usize = 32;
+/// User byte of a directory entry that isn't used.
+const USER_UNUSED: u8 = 0xe5;
+/// User byte of the directory label (CP/M 3).
+const USER_LABEL: u8 = 0x20;
+/// Highest user area that contains files.
+const MAX_USER: u8 = 15;
+const OFFSET_USER: usize = 0;
+const OFFSET_NAME: usize = 1;
+const NAME_LENGTH: usize = 8;
+const OFFSET_EXTENSION: usize = 9;
+const EXTENSION_LENGTH: usize = 3;
+/// Label names use the name and extension of the entry.
+const LABEL_LENGTH: usize = NAME_LENGTH + EXTENSION_LENGTH;
+const OFFSET_EXTENT_LOW: usize = 12;
+const OFFSET_LABEL_FLAGS: usize = 12;
+const LABEL_EXISTS: u8 = 0x01;
+const OFFSET_EXTENT_HIGH: usize = 14;
+const OFFSET_RECORD_COUNT: usize = 15;
+const OFFSET_BLOCKS: usize = 16;
+/// Bit 7 of the name and extension characters is used for attributes.
+const ATTRIBUTE_BIT: u8 = 0x80;
+/// Byte used to pad the last record of a file.
+const END_OF_FILE: u8 = 0x1a;
+/// Track of the Commodore DOS header and directory listing.
+const TRACK_DOS_HEADER: TrackNo = 18;
+const SECTOR_DOS_HEADER: SectorRef = (TRACK_DOS_HEADER, 0);
+const SECTOR_DOS_LISTING: SectorRef = (TRACK_DOS_HEADER, 1);
+const DOS_VERSION: u8 = 0x41;
+const DOUBLE_SIDED_OFFSET: usize = 3;
+const DOUBLE_SIDED: u8 = 0x80;
+const TRACKS_PER_SIDE: TrackNo = 35;
+const DOS_NAME_OFFSET: usize = 0x90;
+/// Disk name, disk ID and DOS type of the Commodore DOS header, padded with
+/// shifted spaces.
+const DOS_NAME: &[u8; 27] =
+    b"CP/M DISK\xa0\xa0\xa0\xa0\xa0\xa0\xa0\xa0\xa065\xa02A\xa0\xa0\xa0\xa0";
+/// Sector the Commodore 128 reads when it boots from the disk.
+const SECTOR_BOOT: SectorRef = (1, 0);
+/// Boot sector signature, followed by the load address, bank and number of
+/// sectors to load, an empty message and file name, and an RTS instruction.
+const BOOT_SECTOR: &[u8; 10] = b"CBM\0\0\0\0\0\0\x60";
+
+/// Parameters of a CP/M disk format, comparable to the disk parameter block
+/// of the CP/M BIOS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpmFormat {
+    /// Number of sectors used on every track. Tracks with more sectors only
+    /// use the first sectors, tracks with fewer sectors aren't used. None uses
+    /// all sectors of each track.
+    pub sectors_per_track: Option<u16>,
+    /// Distance between logically consecutive sectors on a track.
+    pub skew: u16,
+    /// Number of tracks at the start of the disk reserved for the system.
+    pub reserved_tracks: u8,
+    /// Physical tracks that aren't used by CP/M, like the Commodore DOS
+    /// directory track.
+    pub skipped_tracks: &'static [TrackNo],
+    /// Size of an allocation block in bytes.
+    pub block_size: usize,
+    /// Number of entries of the directory.
+    pub directory_entries: usize,
+}
+
+impl CpmFormat {
+    /// Commodore 128 CP/M 3.0 format of a single sided (1541) disk: 136
+    /// blocks of 1 KB. Only 17 sectors of each track are used, and track 18
+    /// is left for Commodore DOS.
+    pub const C128_SINGLE_SIDED: CpmFormat = CpmFormat {
+        sectors_per_track: Some(17),
+        skew: 5,
+        reserved_tracks: 2,
+        skipped_tracks: &[18],
+        block_size: 1024,
+        directory_entries: 64,
+    };
+
+    /// Commodore 128 CP/M 3.0 format of a double sided (1571) disk: 140
+    /// blocks of 2 KB, using 17 sectors of each track on both sides, with 128
+    /// directory entries. Track 18 and 53 are left for Commodore DOS.
+    pub const C128_DOUBLE_SIDED: CpmFormat = CpmFormat {
+        sectors_per_track: Some(17),
+        skew: 5,
+        reserved_tracks: 2,
+        skipped_tracks: &[18, 53],
+        block_size: 2048,
+        directory_entries: 128,
+    };
+}
+
+/// File stored on a CP/M disk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CpmEntry {
+    /// User area (0-15) the file belongs to. Files of other user areas
+    /// aren't stored.
+    pub user: u8,
+    /// Name of the file, at most 8 characters.
+    pub name: String,
+    /// Extension of the file, at most 3 characters.
+    pub extension: String,
+    pub read_only: bool,
+    /// System files are hidden from directory listings.
+    pub system: bool,
+    /// Size of the file in bytes, a multiple of the record size (128 bytes).
+    /// Set by [Layout::list_entries].
+    pub size: usize,
+}
+
+impl CpmEntry {
+    /// Name and extension of the file, like `HELLO.TXT`.
+    pub fn file_name(&self) -> String {
+        if self.extension.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}.{}", self.name, self.extension)
+        }
+    }
+
+    /// The entry as stored in the directory: upper case names, truncated to
+    /// their maximum length.
+    fn stored(&self) -> CpmEntry {
+        CpmEntry {
+            name: stored_name(&self.name, NAME_LENGTH),
+            extension: stored_name(&self.extension, EXTENSION_LENGTH),
+            ..self.clone()
+        }
+    }
+
+    /// Does the given directory entry belong to this file.
+    fn matches(&self, entry: &[u8; DIRECTORY_ENTRY_SIZE]) -> bool {
+        entry[OFFSET_USER] == self.user
+            && read_name(entry, OFFSET_NAME, NAME_LENGTH) == self.name
+            && read_name(entry, OFFSET_EXTENSION, EXTENSION_LENGTH) == self.extension
+    }
+}
+
+/// Read a name from a directory entry, without attribute bits and padding.
+fn read_name(entry: &[u8], offset: usize, length: usize) -> String {
+    entry[offset..offset + length]
+        .iter()
+        .map(|byte| (byte & !ATTRIBUTE_BIT) as char)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// Name as written into a directory entry by [write_name], without padding.
+fn stored_name(name: &str, length: usize) -> String {
+    name.to_ascii_uppercase()
+        .chars()
+        .filter(char::is_ascii)
+        .take(length)
+        .collect::<String>()
+        .trim_end()
+        .to_string()
+}
+
+/// Write a name into a directory entry, in upper case and padded with spaces.
+fn write_name(entry: &mut [u8], offset: usize, length: usize, name: &str) {
+    let name = name.to_ascii_uppercase();
+    let mut bytes = name.bytes().filter(u8::is_ascii);
+    for byte in &mut entry[offset..offset + length] {
+        *byte = bytes.next().unwrap_or(b' ');
+    }
+}
+
+/// Logical extent number of a directory entry.
+fn extent_number(entry: &[u8; DIRECTORY_ENTRY_SIZE]) -> usize {
+    (entry[OFFSET_EXTENT_LOW] as usize & (EXTENTS_PER_LOW_BYTE - 1))
+        + (entry[OFFSET_EXTENT_HIGH] as usize & 0x3f) * EXTENTS_PER_LOW_BYTE
+}
+
+fn is_file_entry(entry: &[u8; DIRECTORY_ENTRY_SIZE]) -> bool {
+    entry[OFFSET_USER] <= MAX_USER
+}
+
+/// Sectors that contain the CP/M data area.
+struct DataArea {
+    /// Physical sector of each logical sector, track after track.
+    sector_refs: Vec<SectorRef>,
+    bytes_per_sector: usize,
+}
+
+impl DataArea {
+    /// Physical sector of the given sector of the data area.
+    fn sector_ref(&self, data_sector: usize) -> SectorRef {
+        self.sector_refs[data_sector]
+    }
+
+    /// Sector and offset of the given directory entry.
+    fn directory_entry_location(&self, index: usize) -> (SectorRef, usize) {
+        let offset = index * DIRECTORY_ENTRY_SIZE;
+        (
+            self.sector_ref(offset / self.bytes_per_sector),
+            offset % self.bytes_per_sector,
+        )
+    }
+}
+
+/// CP/M file system stored on the given geometry.
+///
+/// Use the [Cpm1541] and [Cpm1571] aliases for Commodore 128 disks. Files are
+/// stored as whole records; the last record is padded with `0x1A`. The name
+/// of the disk is stored in the directory label.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+///
+/// let mut disk = Disk::<Cpm1541>::new();
+/// disk.format();
+/// disk.set_name(&String::from("WORKDISK"));
+/// assert_eq!(disk.num_unused_sectors(), 536);
+///
+/// let hello = CpmEntry {
+///     name: String::from("hello"),
+///     extension: String::from("txt"),
+///     ..CpmEntry::default()
+/// };
+/// disk.create_file(&hello, b"Hello CP/M");
+/// let big = CpmEntry {
+///     user: 3,
+///     name: String::from("BIG"),
+///     extension: String::from("DAT"),
+///     ..CpmEntry::default()
+/// };
+/// let content: Vec<u8> = (0..40000).map(|i| i as u8).collect();
+/// disk.create_file(&big, &content);
+/// assert_eq!(disk.num_unused_sectors(), 536 - 41 * 4);
+///
+/// let entries = disk.list_entries();
+/// assert_eq!(entries.len(), 2);
+/// assert_eq!(entries[0].file_name(), "HELLO.TXT");
+/// assert_eq!(entries[0].size, 128);
+/// assert_eq!(&disk.read_file(&entries[0])[..10], b"Hello CP/M");
+/// assert_eq!(entries[1].user, 3);
+/// assert_eq!(entries[1].size, 40064);
+/// assert_eq!(&disk.read_file(&entries[1])[..40000], &content[..]);
+/// assert_eq!(disk.get_name(), "WORKDISK");
+///
+/// // The directory starts on the first track after the reserved tracks. The
+/// // first file block follows the two directory blocks, skewed on the track.
+/// assert_eq!(&disk.get_sector((3, 0)).as_slice()[0x21..0x29], b"HELLO   ");
+/// assert_eq!(&disk.get_sector((3, 6)).as_slice()[..10], b"Hello CP/M");
+///
+/// // Storing a file with the same name replaces it.
+/// disk.create_file(&hello, b"Hello again");
+/// assert_eq!(disk.list_entries().len(), 2);
+/// assert_eq!(&disk.read_file(&entries[0])[..11], b"Hello again");
+///
+/// disk.delete_file(&entries[1]);
+/// assert_eq!(disk.list_entries().len(), 1);
+///
+/// // User areas above 15 are refused.
+/// disk.create_file(&CpmEntry { user: 20, ..hello.clone() }, b"Elsewhere");
+/// assert_eq!(disk.list_entries().len(), 1);
+/// assert_eq!(disk.num_unused_sectors(), 536 - 4);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Cpm<G> {
+    geometry: G,
+    cpm_format: CpmFormat,
+}
+
+/// CP/M on a single sided Commodore 1541 disk (D64 files).
+pub type Cpm1541 = Cpm<Commodore1541Geometry>;
+
+/// CP/M on a double sided Commodore 1571 disk (D71 files).
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+///
+/// let mut disk = Disk::<Cpm1571>::new();
+/// disk.format();
+/// assert_eq!(disk.num_unused_sectors(), 1104);
+///
+/// let entry = CpmEntry {
+///     name: String::from("LARGE"),
+///     ..CpmEntry::default()
+/// };
+/// let content = vec![0x55; 70000];
+/// disk.create_file(&entry, &content);
+/// assert_eq!(disk.num_unused_sectors(), 1104 - 35 * 8);
+///
+/// let entries = disk.list_entries();
+/// assert_eq!(entries[0].file_name(), "LARGE");
+/// assert_eq!(entries[0].size, 70016);
+/// assert_eq!(&disk.read_file(&entries[0])[..70000], &content[..]);
+///
+/// // Commodore DOS sees a double sided disk without unused sectors, which the
+/// // Commodore 128 can boot from.
+/// assert_eq!(&disk.get_sector((1, 0)).as_slice()[..3], b"CBM");
+/// let mut dos_disk = Disk::<Commodore1571>::from_bytes(disk.into_bytes()).unwrap();
+/// assert_eq!(String::from(&dos_disk.get_name()), "CP/M DISK");
+/// assert_eq!(dos_disk.num_unused_sectors(), 0);
+/// assert!(dos_disk.list_entries().is_empty());
+/// ```
+pub type Cpm1571 = Cpm<Commodore1571Geometry>;
+
+impl Default for Cpm1541 {
+    fn default() -> Self {
+        Cpm::new(
+            Commodore1541Geometry::STANDARD,
+            CpmFormat::C128_SINGLE_SIDED,
+        )
+    }
+}
+
+impl Default for Cpm1571 {
+    fn default() -> Self {
+        Cpm::new(
+            Commodore1571Geometry::default(),
+            CpmFormat::C128_DOUBLE_SIDED,
+        )
+    }
+}
+
+impl<G: Geometry> Geometry for Cpm<G> {
+    fn num_tracks(&self) -> u8 {
+        self.geometry.num_tracks()
+    }
+
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        self.geometry.num_sectors(track)
+    }
+
+    fn bytes_per_sector(&self) -> u16 {
+        self.geometry.bytes_per_sector()
+    }
+}
+
+impl<G: Geometry> Cpm<G> {
+    /// CP/M file system with the given format on the given geometry.
+    pub fn new(geometry: G, cpm_format: CpmFormat) -> Self {
+        Cpm {
+            geometry,
+            cpm_format,
+        }
+    }
+
+    pub fn cpm_format(&self) -> &CpmFormat {
+        &self.cpm_format
+    }
+
+    /// Number of sectors of the given track used by CP/M.
+    fn cpm_sectors(&self, track_no: TrackNo) -> u16 {
+        self.cpm_format
+            .sectors_per_track
+            .unwrap_or_else(|| self.geometry.num_sectors(track_no))
+    }
+
+    /// Physical sector of each logical sector of the given track.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use commodore1541::*;
+    ///
+    /// assert_eq!(
+    ///     Cpm1541::default().skew_table(3),
+    ///     vec![0, 5, 10, 15, 3, 8, 13, 1, 6, 11, 16, 4, 9, 14, 2, 7, 12]
+    /// );
+    /// ```
+    pub fn skew_table(&self, track_no: TrackNo) -> Vec<SectorNo> {
+        let sectors_per_track = self.cpm_sectors(track_no) as usize;
+        let mut used = vec![false; sectors_per_track];
+        let mut result = Vec::with_capacity(sectors_per_track);
+        let mut sector_no = 0;
+        for _ in 0..sectors_per_track {
+            while used[sector_no] {
+                sector_no = (sector_no + 1) % sectors_per_track;
+            }
+            used[sector_no] = true;
+            result.push(sector_no as SectorNo);
+            sector_no = (sector_no + self.cpm_format.skew as usize) % sectors_per_track;
+        }
+        result
+    }
+
+    fn data_area(&self) -> DataArea {
+        DataArea {
+            sector_refs: (1..=self.geometry.num_tracks())
+                .filter(|track_no| !self.cpm_format.skipped_tracks.contains(track_no))
+                .filter(|track_no| {
+                    self.geometry.num_sectors(*track_no) >= self.cpm_sectors(*track_no)
+                })
+                .skip(self.cpm_format.reserved_tracks as usize)
+                .flat_map(|track_no| {
+                    self.skew_table(track_no)
+                        .into_iter()
+                        .map(move |sector_no| (track_no, sector_no))
+                })
+                .collect(),
+            bytes_per_sector: self.geometry.bytes_per_sector() as usize,
+        }
+    }
+
+    fn sectors_per_block(&self) -> usize {
+        self.cpm_format.block_size / self.geometry.bytes_per_sector() as usize
+    }
+
+    /// Number of allocation blocks of the data area, including the directory.
+    pub fn num_blocks(&self) -> usize {
+        self.data_area().sector_refs.len() / self.sectors_per_block()
+    }
+
+    fn num_directory_blocks(&self) -> usize {
+        (self.cpm_format.directory_entries * DIRECTORY_ENTRY_SIZE)
+            .div_ceil(self.cpm_format.block_size)
+    }
+
+    /// Block numbers are stored in two bytes when they don't fit in one.
+    fn has_wide_block_numbers(&self) -> bool {
+        self.num_blocks() > 256
+    }
+
+    fn blocks_per_entry(&self) -> usize {
+        if self.has_wide_block_numbers() {
+            8
+        } else {
+            16
+        }
+    }
+
+    fn records_per_entry(&self) -> usize {
+        self.blocks_per_entry() * self.cpm_format.block_size / RECORD_SIZE
+    }
+
+    /// Block numbers stored in a directory entry, without unused slots.
+    fn read_block_numbers(&self, entry: &[u8; DIRECTORY_ENTRY_SIZE]) -> Vec<usize> {
+        let blocks = &entry[OFFSET_BLOCKS..];
+        let block_numbers: Vec<usize> = if self.has_wide_block_numbers() {
+            blocks
+                .chunks_exact(2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+                .collect()
+        } else {
+            blocks.iter().map(|byte| *byte as usize).collect()
+        };
+        block_numbers
+            .into_iter()
+            .filter(|block| *block != 0 && *block < self.num_blocks())
+            .collect()
+    }
+
+    fn write_block_numbers(&self, entry: &mut [u8; DIRECTORY_ENTRY_SIZE], blocks: &[usize]) {
+        let slots = &mut entry[OFFSET_BLOCKS..];
+        if self.has_wide_block_numbers() {
+            for (slot, block) in slots.chunks_exact_mut(2).zip(blocks) {
+                slot.copy_from_slice(&(*block as u16).to_le_bytes());
+            }
+        } else {
+            for (slot, block) in slots.iter_mut().zip(blocks) {
+                *slot = *block as u8;
+            }
+        }
+    }
+
+    fn block_sector_refs(&self, data_area: &DataArea, block: usize) -> Vec<SectorRef> {
+        let sectors_per_block = self.sectors_per_block();
+        (block * sectors_per_block..(block + 1) * sectors_per_block)
+            .map(|data_sector| data_area.sector_ref(data_sector))
+            .collect()
+    }
+}
+
+impl<G> Cpm<G>
+where
+    G: Geometry,
+    Cpm<G>: Default,
+{
+    fn read_directory(&self, disk: &Disk<Self>) -> Vec<[u8; DIRECTORY_ENTRY_SIZE]> {
+        let data_area = self.data_area();
+        (0..self.cpm_format.directory_entries)
+            .map(|index| {
+                let (sector_ref, offset) = data_area.directory_entry_location(index);
+                let mut entry = [0_u8; DIRECTORY_ENTRY_SIZE];
+                disk.get_sector(sector_ref).get_bytes(offset, &mut entry);
+                entry
+            })
+            .collect()
+    }
+
+    fn write_directory_entry(
+        &self,
+        disk: &mut Disk<Self>,
+        index: usize,
+        entry: &[u8; DIRECTORY_ENTRY_SIZE],
+    ) {
+        let (sector_ref, offset) = self.data_area().directory_entry_location(index);
+        disk.get_sector_mut(sector_ref).set_bytes(offset, entry);
+    }
+
+    /// Write the header and an empty listing of Commodore DOS. The block
+    /// availability map is left cleared, which marks all sectors used on both
+    /// sides.
+    fn write_dos_header(&self, disk: &mut Disk<Self>) {
+        if !self.cpm_format.skipped_tracks.contains(&TRACK_DOS_HEADER) {
+            return;
+        }
+        let header = disk.get_sector_mut(SECTOR_DOS_HEADER);
+        header.set_bytes(
+            0,
+            &[SECTOR_DOS_LISTING.0, SECTOR_DOS_LISTING.1, DOS_VERSION],
+        );
+        if self.geometry.num_tracks() > TRACKS_PER_SIDE {
+            header.set_byte(DOUBLE_SIDED_OFFSET, DOUBLE_SIDED);
+        }
+        header.set_bytes(DOS_NAME_OFFSET, DOS_NAME);
+        disk.get_sector_mut(SECTOR_DOS_LISTING)
+            .set_bytes(0, &[0, 0xff]);
+    }
+
+    /// Indices of the directory entries of the given file, ordered by extent.
+    fn find_extents(
+        &self,
+        directory: &[[u8; DIRECTORY_ENTRY_SIZE]],
+        file_entry: &CpmEntry,
+    ) -> Vec<usize> {
+        let mut result: Vec<usize> = (0..directory.len())
+            .filter(|index| file_entry.matches(&directory[*index]))
+            .collect();
+        result.sort_by_key(|index| extent_number(&directory[*index]));
+        result
+    }
+
+    /// Blocks that are in use by the directory or by files.
+    fn used_blocks(&self, directory: &[[u8; DIRECTORY_ENTRY_SIZE]]) -> Vec<bool> {
+        let mut result = vec![false; self.num_blocks()];
+        for used in result.iter_mut().take(self.num_directory_blocks()) {
+            *used = true;
+        }
+        for entry in directory.iter().filter(|entry| is_file_entry(entry)) {
+            for block in self.read_block_numbers(entry) {
+                result[block] = true;
+            }
+        }
+        result
+    }
+
+    fn read_block(
+        &self,
+        disk: &Disk<Self>,
+        data_area: &DataArea,
+        block: usize,
+        result: &mut Vec<u8>,
+    ) {
+        for sector_ref in self.block_sector_refs(data_area, block) {
+            result.extend_from_slice(disk.get_sector(sector_ref).as_slice());
+        }
+    }
+
+    fn write_block(
+        &self,
+        disk: &mut Disk<Self>,
+        data_area: &DataArea,
+        block: usize,
+        content: &[u8],
+    ) {
+        let sector_refs = self.block_sector_refs(data_area, block);
+        for (sector_ref, chunk) in sector_refs
+            .into_iter()
+            .zip(content.chunks(data_area.bytes_per_sector))
+        {
+            disk.get_sector_mut(sector_ref).set_bytes(0, chunk);
+        }
+    }
+}
+
+impl<G> Layout for Cpm<G>
+where
+    G: Geometry,
+    Cpm<G>: Default,
+{
+    /// Type to contain a single entry for [Layout::list_entries].
+    type FileEntryType = CpmEntry;
+    type StringType = String;
+
+    /// Name stored in the directory label, empty when the disk has no label.
+    fn get_disk_name(&self, disk: &Disk<Self>) -> String {
+        self.read_directory(disk)
+            .iter()
+            .find(|entry| entry[OFFSET_USER] == USER_LABEL)
+            .map(|entry| read_name(entry, OFFSET_NAME, LABEL_LENGTH))
+            .unwrap_or_default()
+    }
+
+    /// Store the name in the directory label, which is created when needed.
+    fn set_disk_name(&self, disk: &mut Disk<Self>, new_name: &String) {
+        let directory = self.read_directory(disk);
+        let index = directory
+            .iter()
+            .position(|entry| entry[OFFSET_USER] == USER_LABEL)
+            .or_else(|| {
+                directory
+                    .iter()
+                    .position(|entry| entry[OFFSET_USER] == USER_UNUSED)
+            });
+        if let Some(index) = index {
+            let mut entry = [0_u8; DIRECTORY_ENTRY_SIZE];
+            entry[OFFSET_USER] = USER_LABEL;
+            write_name(&mut entry, OFFSET_NAME, LABEL_LENGTH, new_name);
+            entry[OFFSET_LABEL_FLAGS] = LABEL_EXISTS;
+            self.write_directory_entry(disk, index, &entry);
+        }
+    }
+
+    /// Clear the disk and create an empty directory. The Commodore DOS header
+    /// on track 18 allocates all sectors, so Commodore DOS doesn't store files
+    /// on the disk. The boot sector identifies the disk as a Commodore 128
+    /// disk.
+    fn format_disk(&self, disk: &mut Disk<Self>) {
+        self.clear_disk(disk);
+        self.write_dos_header(disk);
+        if self.cpm_format.reserved_tracks > 0 {
+            disk.get_sector_mut(SECTOR_BOOT).set_bytes(0, BOOT_SECTOR);
+        }
+        let data_area = self.data_area();
+        let directory_size = self.num_directory_blocks() * self.cpm_format.block_size;
+        for block in 0..self.num_directory_blocks() {
+            self.write_block(disk, &data_area, block, &vec![USER_UNUSED; directory_size]);
+        }
+    }
+
+    fn clear_disk(&self, disk: &mut Disk<Self>) {
+        let sector_refs = get_all_sector_refs(self);
+        clear_sector_refs(disk, &sector_refs);
+    }
+
+    fn list_entries(&self, disk: &Disk<Self>) -> Vec<CpmEntry> {
+        let directory = self.read_directory(disk);
+        let mut result: Vec<CpmEntry> = Vec::new();
+        for entry in directory.iter().filter(|entry| is_file_entry(entry)) {
+            if result.iter().any(|file_entry| file_entry.matches(entry)) {
+                continue;
+            }
+            let mut file_entry = CpmEntry {
+                user: entry[OFFSET_USER],
+                name: read_name(entry, OFFSET_NAME, NAME_LENGTH),
+                extension: read_name(entry, OFFSET_EXTENSION, EXTENSION_LENGTH),
+                read_only: entry[OFFSET_EXTENSION] & ATTRIBUTE_BIT != 0,
+                system: entry[OFFSET_EXTENSION + 1] & ATTRIBUTE_BIT != 0,
+                size: 0,
+            };
+            if let Some(last) = self.find_extents(&directory, &file_entry).last() {
+                let last = &directory[*last];
+                let num_records =
+                    extent_number(last) * RECORDS_PER_EXTENT + last[OFFSET_RECORD_COUNT] as usize;
+                file_entry.size = num_records * RECORD_SIZE;
+            }
+            result.push(file_entry);
+        }
+        result
+    }
+
+    /// Return the contents of the given file, up to the end of its last
+    /// record.
+    fn read_file(&self, disk: &Disk<Self>, file_entry: &CpmEntry) -> Vec<u8> {
+        let directory = self.read_directory(disk);
+        let data_area = self.data_area();
+        let mut result = Vec::new();
+        for index in self.find_extents(&directory, file_entry) {
+            for block in self.read_block_numbers(&directory[index]) {
+                self.read_block(disk, &data_area, block, &mut result);
+            }
+        }
+        result.truncate(file_entry.size);
+        result
+    }
+
+    /// Create a new file and store it to disk, replacing the file with the
+    /// same user area and name. Nothing is stored when the user area is above
+    /// 15, or when the disk doesn't have enough unused blocks or directory
+    /// entries.
+    fn create_file(&self, disk: &mut Disk<Self>, file_entry: &CpmEntry, content: &[u8]) {
+        if file_entry.user > MAX_USER {
+            return;
+        }
+        let file_entry = file_entry.stored();
+        let mut content = content.to_vec();
+        content.resize(content.len().next_multiple_of(RECORD_SIZE), END_OF_FILE);
+        let num_records = content.len() / RECORD_SIZE;
+        let block_size = self.cpm_format.block_size;
+        let num_blocks = content.len().div_ceil(block_size);
+        let num_entries = num_blocks.div_ceil(self.blocks_per_entry()).max(1);
+
+        let mut directory = self.read_directory(disk);
+        let replaced_indices = self.find_extents(&directory, &file_entry);
+        for index in &replaced_indices {
+            directory[*index][OFFSET_USER] = USER_UNUSED;
+        }
+        let entry_indices: Vec<usize> = (0..directory.len())
+            .filter(|index| directory[*index][OFFSET_USER] == USER_UNUSED)
+            .take(num_entries)
+            .collect();
+        let blocks: Vec<usize> = self
+            .used_blocks(&directory)
+            .iter()
+            .enumerate()
+            .filter(|(_, used)| !**used)
+            .map(|(block, _)| block)
+            .take(num_blocks)
+            .collect();
+        if entry_indices.len() != num_entries || blocks.len() != num_blocks {
+            return;
+        }
+        for index in replaced_indices {
+            self.write_directory_entry(disk, index, &directory[index]);
+        }
+
+        let data_area = self.data_area();
+        for (block, chunk) in blocks.iter().zip(content.chunks(block_size)) {
+            self.write_block(disk, &data_area, *block, chunk);
+        }
+
+        let records_per_entry = self.records_per_entry();
+        let extents_per_entry = records_per_entry / RECORDS_PER_EXTENT;
+        for (entry_no, index) in entry_indices.into_iter().enumerate() {
+            let records = num_records
+                .saturating_sub(entry_no * records_per_entry)
+                .min(records_per_entry);
+            let extent =
+                entry_no * extents_per_entry + records.saturating_sub(1) / RECORDS_PER_EXTENT;
+            let record_count =
+                records - (extent - entry_no * extents_per_entry) * RECORDS_PER_EXTENT;
+
+            let mut entry = [0_u8; DIRECTORY_ENTRY_SIZE];
+            entry[OFFSET_USER] = file_entry.user;
+            write_name(&mut entry, OFFSET_NAME, NAME_LENGTH, &file_entry.name);
+            write_name(
+                &mut entry,
+                OFFSET_EXTENSION,
+                EXTENSION_LENGTH,
+                &file_entry.extension,
+            );
+            if file_entry.read_only {
+                entry[OFFSET_EXTENSION] |= ATTRIBUTE_BIT;
+            }
+            if file_entry.system {
+                entry[OFFSET_EXTENSION + 1] |= ATTRIBUTE_BIT;
+            }
+            entry[OFFSET_EXTENT_LOW] = (extent % EXTENTS_PER_LOW_BYTE) as u8;
+            entry[OFFSET_EXTENT_HIGH] = (extent / EXTENTS_PER_LOW_BYTE) as u8;
+            entry[OFFSET_RECORD_COUNT] = record_count as u8;
+            let first_block = entry_no * self.blocks_per_entry();
+            let last_block = (first_block + self.blocks_per_entry()).min(num_blocks);
+            self.write_block_numbers(&mut entry, &blocks[first_block..last_block]);
+            self.write_directory_entry(disk, index, &entry);
+        }
+    }
+
+    /// Mark all directory entries of the file unused, which releases its
+    /// blocks.
+    fn delete_file(&self, disk: &mut Disk<Self>, file_entry: &CpmEntry) {
+        let directory = self.read_directory(disk);
+        for index in self.find_extents(&directory, file_entry) {
+            let mut entry = directory[index];
+            entry[OFFSET_USER] = USER_UNUSED;
+            self.write_directory_entry(disk, index, &entry);
+        }
+    }
+
+    fn num_unused_sectors(&self, disk: &mut Disk<Self>) -> usize {
+        let directory = self.read_directory(disk);
+        let num_unused_blocks = self
+            .used_blocks(&directory)
+            .iter()
+            .filter(|used| !**used)
+            .count();
+        num_unused_blocks * self.sectors_per_block()
+    }
+}
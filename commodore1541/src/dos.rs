@@ -0,0 +1,727 @@
+//! Structures shared by the layouts that use Commodore DOS: chains of sectors
+//! and the directory listing.
+//!
+//! The functions in this module don't know where the BAM is stored. Layouts
+//! give access to their BAM by implementing [DosLayout].
+use d64::{Disk, Geometry, Sector, SectorNo, SectorRef, TrackNo};
+
+use crate::{FileEntry, FileListEntryRef, FileType};
+
+/// Indicates that marks the end of a chain of sectors.
+pub(crate) const SECTOR_END_OF_CHAIN: SectorRef = (0, 255);
+/// Header of a sector is 2 bytes. It contains the sector ref to the next sector, or SECTOR_END_OF_CHAIN for the last.
+pub(crate) const SECTOR_HEADER_SIZE: usize = 2;
+/// Size of each file list entry on disk.
+pub(crate) const FILE_LIST_ENTRY_SIZE: usize = 32;
+/// Number of file list entries that fit in a single sector.
+pub(crate) const FILE_LIST_ENTRIES_PER_SECTOR: usize = 8;
+/// Number of data sectors a single side sector of a relative file refers to.
+const SIDE_SECTOR_NUM_DATA_REFS: usize = 120;
+/// Number of side sectors in a group. Each side sector refers to all side
+/// sectors of its group.
+const SIDE_SECTORS_PER_GROUP: usize = 6;
+const SIDE_SECTOR_OFFSET_NUMBER: usize = 2;
+const SIDE_SECTOR_OFFSET_RECORD_LENGTH: usize = 3;
+const SIDE_SECTOR_OFFSET_GROUP: usize = 4;
+const SIDE_SECTOR_OFFSET_DATA: usize = 16;
+const SUPER_SIDE_SECTOR_MARKER: u8 = 0xfe;
+const SUPER_SIDE_SECTOR_OFFSET_MARKER: usize = 2;
+const SUPER_SIDE_SECTOR_OFFSET_GROUPS: usize = 3;
+/// Number of groups of side sectors a super side sector refers to.
+const SUPER_SIDE_SECTOR_NUM_GROUPS: usize = 126;
+
+/// Access to the BAM of a layout that uses Commodore DOS.
+///
+/// The file functions of this module ([create_file], [delete_file],
+/// [allocate_sectors]) use it to allocate and release sectors.
+pub(crate) trait DosLayout: Geometry + Default + Sized {
+    fn is_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) -> bool;
+    fn mark_sector_used(&self, disk: &mut Disk<Self>, sector_ref: SectorRef);
+    fn mark_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef);
+
+    /// Tracks that are reserved for the header, BAM and directory listing. No
+    /// file content is stored on them.
+    fn is_system_track(&self, _track_no: TrackNo) -> bool {
+        false
+    }
+
+    /// Distance between the sectors of a file on the same track.
+    fn interleave(&self) -> u8 {
+        1
+    }
+
+    /// Relative files refer to their groups of side sectors via a super side
+    /// sector (DOS 3.0 and later). Without it a relative file has at most a
+    /// single group of side sectors.
+    fn uses_super_side_sector(&self) -> bool {
+        false
+    }
+
+    /// Header sector of the directory listing new files are added to.
+    fn directory_header(&self, disk: &Disk<Self>) -> SectorRef;
+
+    /// Allocate a sector to extend the directory listing with.
+    fn allocate_file_list_sector(&self, disk: &mut Disk<Self>) -> Option<SectorRef> {
+        allocate_sectors(self, disk, 1).map(|sector_refs| sector_refs[0])
+    }
+}
+
+/// Set the next sector for the given sector in a chain of sectors.
+pub(crate) fn set_next_sector(sector: &mut Sector, sector_ref: SectorRef) {
+    sector.set_byte(0, sector_ref.0);
+    sector.set_byte(1, sector_ref.1);
+}
+
+/// Mark the given sector to be the last sector in a chain.
+pub(crate) fn end_sector_chain(sector: &mut Sector) {
+    set_next_sector(sector, SECTOR_END_OF_CHAIN);
+}
+
+pub(crate) fn get_next_sector<'a, L>(
+    disk: &'a Disk<L>,
+    sector: &Sector,
+) -> Option<(&'a Sector, SectorRef)>
+where
+    L: Geometry + Default,
+{
+    let track_no = *sector.get_byte(0);
+    if track_no == SECTOR_END_OF_CHAIN.0 {
+        None
+    } else {
+        let sector_no = *sector.get_byte(1);
+        let sector_ref = (track_no, sector_no);
+        Some((disk.get_sector(sector_ref), sector_ref))
+    }
+}
+
+/// Number of content bytes used by the given sector of a chain. The last
+/// sector of a chain stores the offset of its last used byte.
+fn get_sector_content_size(sector: &Sector) -> usize {
+    let content_size = sector.as_slice().len() - SECTOR_HEADER_SIZE;
+    if *sector.get_byte(0) == SECTOR_END_OF_CHAIN.0 {
+        (*sector.get_byte(1) as usize)
+            .saturating_sub(SECTOR_HEADER_SIZE - 1)
+            .min(content_size)
+    } else {
+        content_size
+    }
+}
+
+/// Append the content of the chain of sectors starting at sector_ref to file_content.
+/// Only the used bytes of the last sector are appended.
+///
+/// # Example
+///
+/// A file of 354 bytes stored the way the 1541 drive writes it: the first
+/// sector is full, the last sector uses 100 bytes and points to its last
+/// used byte (101).
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use std::path::Path;
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.read_from_path(Path::new("../disks/1541-empty.d64")).unwrap();
+/// let directory = disk.get_sector_mut((18, 1));
+/// directory.set_bytes(2, &[0x82, 17, 0]);
+/// directory.set_bytes(5, b"TEST\xa0\xa0\xa0\xa0\xa0\xa0\xa0\xa0\xa0\xa0\xa0\xa0");
+/// directory.set_bytes(30, &[2, 0]);
+/// let first = disk.get_sector_mut((17, 0));
+/// first.set_bytes(0, &[17, 10]);
+/// first.set_bytes(2, &[1; 254]);
+/// let last = disk.get_sector_mut((17, 10));
+/// last.set_bytes(0, &[0, 101]);
+/// last.set_bytes(2, &[2; 254]);
+///
+/// let entries = disk.list_entries();
+/// let content = disk.read_file(&entries[0]);
+/// assert_eq!(content.len(), 354);
+/// assert_eq!(content[253..255], [1, 2]);
+/// ```
+pub(crate) fn read_sector_chain<L>(
+    disk: &Disk<L>,
+    sector_ref: SectorRef,
+    file_content: &mut Vec<u8>,
+) where
+    L: Geometry + Default,
+{
+    if sector_ref.0 == SECTOR_END_OF_CHAIN.0 {
+        return;
+    }
+    let mut sector = disk.get_sector(sector_ref);
+    loop {
+        let content_size = get_sector_content_size(sector);
+        file_content.extend_from_slice(
+            &sector.as_slice()[SECTOR_HEADER_SIZE..SECTOR_HEADER_SIZE + content_size],
+        );
+        match get_next_sector(disk, sector) {
+            Some((next_sector, _)) => sector = next_sector,
+            None => break,
+        }
+    }
+}
+
+/// Get the chain of sectors starting from the given sector_ref, including the
+/// given sector itself. The end of chain marker isn't part of the result.
+///
+/// # Example
+///
+/// Deleting a file releases all of its sectors.
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.format();
+/// assert_eq!(disk.num_unused_sectors(), 664);
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("THREE")),
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &[0x42; 600]);
+/// assert_eq!(disk.num_unused_sectors(), 661);
+///
+/// let entries = disk.list_entries();
+/// disk.delete_file(&entries[0]);
+/// assert_eq!(disk.num_unused_sectors(), 664);
+/// ```
+pub(crate) fn get_sector_ref_chain<L>(disk: &Disk<L>, sector_ref: SectorRef) -> Vec<SectorRef>
+where
+    L: Geometry + Default,
+{
+    if sector_ref.0 == SECTOR_END_OF_CHAIN.0 {
+        return Vec::new();
+    }
+
+    let mut sector_refs = vec![sector_ref];
+    let mut sector = disk.get_sector(sector_ref);
+    while let Some(s) = get_next_sector(disk, sector) {
+        sector = s.0;
+        sector_refs.push(s.1);
+    }
+    sector_refs
+}
+
+/// Link the given sectors into a single chain, in the order they are given.
+pub(crate) fn chain_sectors<L>(disk: &mut Disk<L>, sectors: &[SectorRef])
+where
+    L: Geometry + Default,
+{
+    if sectors.is_empty() {
+        return;
+    }
+
+    for i in 0..sectors.len() - 1 {
+        let sector_ref = sectors[i];
+        let next_sector_ref = sectors[i + 1];
+        let sector = disk.get_sector_mut(sector_ref);
+        set_next_sector(sector, next_sector_ref);
+    }
+    let sector = disk.get_sector_mut(*sectors.last().unwrap());
+    end_sector_chain(sector);
+}
+
+/// Store the content in the given (already chained) sectors. The last sector
+/// of the chain records the number of bytes it uses, an empty file stores no
+/// bytes in its only sector.
+pub(crate) fn write_sector_chain<L>(disk: &mut Disk<L>, sectors: &[SectorRef], content: &[u8])
+where
+    L: Geometry + Default,
+{
+    let content_size = disk.layout().bytes_per_sector() as usize - SECTOR_HEADER_SIZE;
+    let mut chunks = content.chunks(content_size);
+    for sector_ref in sectors {
+        let chunk = chunks.next().unwrap_or_default();
+        let sector = disk.get_sector_mut(*sector_ref);
+        sector.set_bytes(SECTOR_HEADER_SIZE, chunk);
+        if *sector.get_byte(0) == SECTOR_END_OF_CHAIN.0 {
+            sector.set_byte(1, (chunk.len() + SECTOR_HEADER_SIZE - 1) as u8);
+        }
+    }
+}
+
+pub(crate) fn clear_sector_refs<L>(disk: &mut Disk<L>, sector_refs: &[SectorRef])
+where
+    L: Geometry + Default,
+{
+    for sector_ref in sector_refs {
+        let sector = disk.get_sector_mut(*sector_ref);
+        let len = sector.as_slice().len();
+        sector.fill(0, len, 0);
+    }
+}
+
+/// All sector refs that the given layout creates on its physical media.
+pub(crate) fn get_all_sector_refs<L>(layout: &L) -> Vec<SectorRef>
+where
+    L: Geometry,
+{
+    let mut sector_refs = Vec::new();
+    for track_no in 1..=layout.num_tracks() {
+        for sector_no in 0..layout.num_sectors(track_no) {
+            sector_refs.push((track_no, sector_no as SectorNo));
+        }
+    }
+    sector_refs
+}
+
+/// List the file entries of the directory listing that is chained after the
+/// given header sector.
+pub(crate) fn list_entries<L>(disk: &Disk<L>, header_ref: SectorRef) -> Vec<FileEntry>
+where
+    L: Geometry + Default,
+{
+    let mut result = Vec::new();
+    let mut sector = disk.get_sector(header_ref);
+    while let Some(s) = get_next_sector(disk, sector) {
+        sector = s.0;
+
+        let mut entry_bytes = [0_u8; FILE_LIST_ENTRY_SIZE];
+        for sector_entry in 0..FILE_LIST_ENTRIES_PER_SECTOR {
+            let file_entry_ref = (s.1, sector_entry);
+            sector.get_bytes(sector_entry * FILE_LIST_ENTRY_SIZE, &mut entry_bytes);
+            let entry = FileEntry::from_bytes(&entry_bytes, file_entry_ref);
+            if entry.file_type != FileType::Scratched {
+                result.push(entry);
+            }
+        }
+    }
+    result
+}
+
+pub(crate) fn find_scratched_file_list_entry<L>(
+    disk: &Disk<L>,
+    header_ref: SectorRef,
+) -> Option<FileListEntryRef>
+where
+    L: Geometry + Default,
+{
+    let mut sector = disk.get_sector(header_ref);
+    while let Some(s) = get_next_sector(disk, sector) {
+        sector = s.0;
+        let sector_ref = s.1;
+
+        let mut entry_bytes = [0_u8; FILE_LIST_ENTRY_SIZE];
+        for sector_entry in 0..FILE_LIST_ENTRIES_PER_SECTOR {
+            let file_entry_ref = (sector_ref, sector_entry);
+            sector.get_bytes(sector_entry * FILE_LIST_ENTRY_SIZE, &mut entry_bytes);
+            let entry = FileEntry::from_bytes(&entry_bytes, file_entry_ref);
+            if entry.file_type == FileType::Scratched {
+                return Some((sector_ref, sector_entry));
+            }
+        }
+    }
+    None
+}
+
+/// Get the last sector of the directory listing chained after the given header sector.
+pub(crate) fn get_last_file_list_sector<L>(disk: &Disk<L>, header_ref: SectorRef) -> SectorRef
+where
+    L: Geometry + Default,
+{
+    let mut sector = disk.get_sector(header_ref);
+    let mut sector_ref = header_ref;
+    while let Some(s) = get_next_sector(disk, sector) {
+        sector = s.0;
+        sector_ref = s.1;
+    }
+    sector_ref
+}
+
+/// Append a newly allocated sector to the directory listing chained after the given header sector.
+pub(crate) fn append_file_list_sector<L>(
+    disk: &mut Disk<L>,
+    header_ref: SectorRef,
+    new_sector_ref: SectorRef,
+) where
+    L: Geometry + Default,
+{
+    let sector_ref = get_last_file_list_sector(disk, header_ref);
+    let sector = disk.get_sector_mut(sector_ref);
+    set_next_sector(sector, new_sector_ref);
+
+    let new_sector = disk.get_sector_mut(new_sector_ref);
+    let len = new_sector.as_slice().len();
+    new_sector.fill(SECTOR_HEADER_SIZE, len, 0);
+    end_sector_chain(new_sector);
+}
+
+pub(crate) fn update_file_list_entry<L>(
+    disk: &mut Disk<L>,
+    entry_ref: FileListEntryRef,
+    file_entry: &FileEntry,
+) where
+    L: Geometry + Default,
+{
+    let sector = disk.get_sector_mut(entry_ref.0);
+    let offset = entry_ref.1 * FILE_LIST_ENTRY_SIZE;
+    file_entry.store(sector, offset);
+}
+
+pub(crate) fn scratch_file_list_entry<L>(disk: &mut Disk<L>, file_entry: &FileEntry)
+where
+    L: Geometry + Default,
+{
+    let sector = disk.get_sector_mut(file_entry.file_entry_ref.0);
+    let offset = file_entry.file_entry_ref.1 * FILE_LIST_ENTRY_SIZE;
+    file_entry.scratch(sector, offset);
+}
+
+/// Allocate sectors for file content. System tracks are skipped.
+///
+/// On each track the next sector is searched [DosLayout::interleave] sectors
+/// after the previously allocated one, wrapping around the track. Nothing is
+/// allocated when there aren't enough unused sectors.
+pub(crate) fn allocate_sectors<L>(
+    layout: &L,
+    disk: &mut Disk<L>,
+    num_sectors: usize,
+) -> Option<Vec<SectorRef>>
+where
+    L: DosLayout,
+{
+    let mut allocated_sectors = Vec::new();
+    for track_no in 1..=disk.num_tracks() {
+        if allocated_sectors.len() == num_sectors {
+            break;
+        }
+        if layout.is_system_track(track_no) {
+            continue;
+        }
+        let track_sectors = layout.num_sectors(track_no) as usize;
+        let mut next_sector_no = 0;
+        while allocated_sectors.len() < num_sectors {
+            let Some(sector_no) = (0..track_sectors)
+                .map(|distance| (next_sector_no + distance) % track_sectors)
+                .find(|sector_no| {
+                    layout.is_sector_unused(disk, (track_no, *sector_no as SectorNo))
+                })
+            else {
+                break;
+            };
+            let sector_ref = (track_no, sector_no as SectorNo);
+            layout.mark_sector_used(disk, sector_ref);
+            allocated_sectors.push(sector_ref);
+            next_sector_no = sector_no + layout.interleave() as usize;
+        }
+    }
+
+    if allocated_sectors.len() != num_sectors {
+        for sector_ref in &allocated_sectors {
+            layout.mark_sector_unused(disk, *sector_ref);
+        }
+        return None;
+    }
+    Some(allocated_sectors)
+}
+
+/// Allocate the first unused sector of the given track.
+pub(crate) fn allocate_track_sector<L>(
+    layout: &L,
+    disk: &mut Disk<L>,
+    track_no: TrackNo,
+) -> Option<SectorRef>
+where
+    L: DosLayout,
+{
+    let sector_ref = (0..layout.num_sectors(track_no))
+        .map(|sector_no| (track_no, sector_no as SectorNo))
+        .find(|sector_ref| layout.is_sector_unused(disk, *sector_ref))?;
+    layout.mark_sector_used(disk, sector_ref);
+    Some(sector_ref)
+}
+
+/// Return the contents of the given file.
+pub(crate) fn read_file<L>(disk: &Disk<L>, file_entry: &FileEntry) -> Vec<u8>
+where
+    L: Geometry + Default,
+{
+    let mut result = Vec::new();
+    read_sector_chain(disk, file_entry.start_sector, &mut result);
+    result
+}
+
+/// Create a new file and store it to disk. Nothing is stored when the disk is
+/// full.
+///
+/// Like CBM DOS, an empty file still uses a single sector. Relative files get
+/// side sectors listing their data sectors, these are part of
+/// [FileEntry::num_sectors].
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.format();
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("EMPTY")),
+///     file_type: FileType::Sequence,
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &[]);
+/// assert_eq!(disk.num_unused_sectors(), 663);
+///
+/// let entries = disk.list_entries();
+/// assert_eq!(entries[0].num_sectors, 1);
+/// assert!(disk.read_file(&entries[0]).is_empty());
+/// ```
+///
+/// A relative file of 300 records of 100 bytes uses 119 data sectors and a
+/// single side sector.
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.format();
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("RECORDS")),
+///     file_type: FileType::Relative,
+///     record_length: 100,
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &vec![0xff; 300 * 100]);
+/// assert_eq!(disk.num_unused_sectors(), 664 - 120);
+///
+/// let entries = disk.list_entries();
+/// assert_eq!(entries[0].num_sectors, 120);
+/// assert_eq!(disk.read_file(&entries[0]).len(), 300 * 100);
+/// let side_sector = disk.get_sector(entries[0].side_sector).as_slice();
+/// assert_eq!(side_sector[0..4], [0, 16 + 2 * 119 - 1, 0, 100]);
+/// assert_eq!(side_sector[4..6], [entries[0].side_sector.0, entries[0].side_sector.1]);
+/// assert_eq!(side_sector[16..18], [entries[0].start_sector.0, entries[0].start_sector.1]);
+///
+/// disk.delete_file(&entries[0]);
+/// assert_eq!(disk.num_unused_sectors(), 664);
+/// ```
+///
+/// The sectors of a file are released again when the directory listing can't
+/// be extended to store its entry.
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.format();
+/// for index in 0..8 {
+///     let entry = FileEntry {
+///         name: PetsciiString::from(&format!("FILE{index}")),
+///         ..FileEntry::default()
+///     };
+///     disk.create_file(&entry, &[]);
+/// }
+/// assert_eq!(disk.num_unused_sectors(), 656);
+///
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("LARGE")),
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &vec![0; 656 * 254]);
+/// assert_eq!(disk.list_entries().len(), 8);
+/// assert_eq!(disk.num_unused_sectors(), 656);
+/// ```
+pub(crate) fn create_file<L>(layout: &L, disk: &mut Disk<L>, file_entry: &FileEntry, content: &[u8])
+where
+    L: DosLayout,
+{
+    let content_size = layout.bytes_per_sector() as usize - SECTOR_HEADER_SIZE;
+    let num_sectors = content.chunks(content_size).len().max(1);
+    let num_side_sectors = if file_entry.file_type == FileType::Relative {
+        match num_side_sectors(layout, num_sectors) {
+            Some(num_side_sectors) => num_side_sectors,
+            None => return,
+        }
+    } else {
+        0
+    };
+    if let Some(sectors) = allocate_sectors(layout, disk, num_sectors + num_side_sectors) {
+        clear_sector_refs(disk, &sectors);
+        let (data_sectors, side_sectors) = sectors.split_at(num_sectors);
+        chain_sectors(disk, data_sectors);
+        write_sector_chain(disk, data_sectors, content);
+
+        let mut file_entry = file_entry.clone();
+        file_entry.start_sector = data_sectors[0];
+        file_entry.side_sector = SectorRef::default();
+        if !side_sectors.is_empty() {
+            file_entry.side_sector = write_side_sectors(
+                layout,
+                disk,
+                side_sectors,
+                data_sectors,
+                file_entry.record_length,
+            );
+        }
+        file_entry.num_sectors = sectors.len();
+        if create_file_list_entry(layout, disk, &file_entry).is_none() {
+            release_sectors(layout, disk, &sectors);
+        }
+    }
+}
+
+/// Mark the given sectors unused and clear their content.
+pub(crate) fn release_sectors<L>(layout: &L, disk: &mut Disk<L>, sector_refs: &[SectorRef])
+where
+    L: DosLayout,
+{
+    for sector_ref in sector_refs {
+        layout.mark_sector_unused(disk, *sector_ref);
+    }
+    clear_sector_refs(disk, sector_refs);
+}
+
+/// Release the sectors of the given file and scratch its directory entry.
+pub(crate) fn delete_file<L>(layout: &L, disk: &mut Disk<L>, file_entry: &FileEntry)
+where
+    L: DosLayout,
+{
+    let mut sectors_to_clear = get_sector_ref_chain(disk, file_entry.start_sector);
+    if file_entry.file_type == FileType::Relative {
+        sectors_to_clear.extend(get_sector_ref_chain(disk, file_entry.side_sector));
+    }
+    release_sectors(layout, disk, &sectors_to_clear);
+    scratch_file_list_entry(disk, file_entry);
+}
+
+/// Number of sectors needed for the side sectors of a relative file with the
+/// given number of data sectors, including the super side sector. None when
+/// the file is too large to be stored as a relative file.
+fn num_side_sectors<L>(layout: &L, num_data_sectors: usize) -> Option<usize>
+where
+    L: DosLayout,
+{
+    let num_side_sectors = num_data_sectors.div_ceil(SIDE_SECTOR_NUM_DATA_REFS);
+    if layout.uses_super_side_sector() {
+        let num_groups = num_side_sectors.div_ceil(SIDE_SECTORS_PER_GROUP);
+        (num_groups <= SUPER_SIDE_SECTOR_NUM_GROUPS).then_some(num_side_sectors + 1)
+    } else {
+        (num_side_sectors <= SIDE_SECTORS_PER_GROUP).then_some(num_side_sectors)
+    }
+}
+
+/// Store the side sectors of a relative file, referring to its data sectors.
+/// The sectors start with the super side sector when the layout uses one.
+///
+/// Returns the sector the directory entry refers to.
+///
+/// # Example
+///
+/// The 1581 refers to the side sectors via a super side sector.
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore1581>::new();
+/// disk.format();
+/// let unused = disk.num_unused_sectors();
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("RECORDS")),
+///     file_type: FileType::Relative,
+///     record_length: 254,
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &vec![1; 121 * 254]);
+/// let entries = disk.list_entries();
+/// assert_eq!(entries[0].num_sectors, 121 + 2 + 1);
+///
+/// let super_side_sector = disk.get_sector(entries[0].side_sector).as_slice();
+/// assert_eq!(super_side_sector[2], 0xfe);
+/// let first_side_sector = (super_side_sector[0], super_side_sector[1]);
+/// assert_eq!(super_side_sector[3..5], [first_side_sector.0, first_side_sector.1]);
+///
+/// let side_sector = disk.get_sector(first_side_sector).as_slice();
+/// let second_side_sector = (side_sector[0], side_sector[1]);
+/// assert_eq!(side_sector[2..4], [0, 254]);
+/// assert_eq!(side_sector[6..8], [second_side_sector.0, second_side_sector.1]);
+/// assert_eq!(disk.get_sector(second_side_sector).as_slice()[0..3], [0, 17, 1]);
+///
+/// disk.delete_file(&entries[0]);
+/// assert_eq!(disk.num_unused_sectors(), unused);
+/// ```
+fn write_side_sectors<L>(
+    layout: &L,
+    disk: &mut Disk<L>,
+    sectors: &[SectorRef],
+    data_sectors: &[SectorRef],
+    record_length: u8,
+) -> SectorRef
+where
+    L: DosLayout,
+{
+    let side_sectors = if layout.uses_super_side_sector() {
+        &sectors[1..]
+    } else {
+        sectors
+    };
+    chain_sectors(disk, side_sectors);
+    let groups = side_sectors.chunks(SIDE_SECTORS_PER_GROUP);
+    let data_refs = data_sectors.chunks(SIDE_SECTOR_NUM_DATA_REFS);
+    for (group_index, group) in groups.clone().enumerate() {
+        let group_data_refs = data_refs.clone().skip(group_index * SIDE_SECTORS_PER_GROUP);
+        for (number, (sector_ref, data_refs)) in group.iter().zip(group_data_refs).enumerate() {
+            let sector = disk.get_sector_mut(*sector_ref);
+            sector.set_byte(SIDE_SECTOR_OFFSET_NUMBER, number as u8);
+            sector.set_byte(SIDE_SECTOR_OFFSET_RECORD_LENGTH, record_length);
+            for (index, side_sector_ref) in group.iter().enumerate() {
+                let offset = SIDE_SECTOR_OFFSET_GROUP + index * 2;
+                sector.set_bytes(offset, &[side_sector_ref.0, side_sector_ref.1]);
+            }
+            for (index, data_ref) in data_refs.iter().enumerate() {
+                let offset = SIDE_SECTOR_OFFSET_DATA + index * 2;
+                sector.set_bytes(offset, &[data_ref.0, data_ref.1]);
+            }
+            if *sector.get_byte(0) == SECTOR_END_OF_CHAIN.0 {
+                let last_offset = SIDE_SECTOR_OFFSET_DATA + data_refs.len() * 2 - 1;
+                sector.set_byte(1, last_offset as u8);
+            }
+        }
+    }
+
+    if !layout.uses_super_side_sector() {
+        return side_sectors[0];
+    }
+    let super_side_sector = disk.get_sector_mut(sectors[0]);
+    set_next_sector(super_side_sector, side_sectors[0]);
+    super_side_sector.set_byte(SUPER_SIDE_SECTOR_OFFSET_MARKER, SUPER_SIDE_SECTOR_MARKER);
+    for (group_index, group) in groups.enumerate() {
+        let offset = SUPER_SIDE_SECTOR_OFFSET_GROUPS + group_index * 2;
+        super_side_sector.set_bytes(offset, &[group[0].0, group[0].1]);
+    }
+    sectors[0]
+}
+
+/// Store the file entry in the first scratched entry of the directory
+/// listing, or in a newly allocated sector appended to the listing.
+pub(crate) fn create_file_list_entry<L>(
+    layout: &L,
+    disk: &mut Disk<L>,
+    file_entry: &FileEntry,
+) -> Option<FileListEntryRef>
+where
+    L: DosLayout,
+{
+    let header_ref = layout.directory_header(disk);
+    let entry_ref = match find_scratched_file_list_entry(disk, header_ref) {
+        Some(entry_ref) => entry_ref,
+        None => {
+            let new_sector_ref = layout.allocate_file_list_sector(disk)?;
+            append_file_list_sector(disk, header_ref, new_sector_ref);
+            (new_sector_ref, 0)
+        }
+    };
+    update_file_list_entry(disk, entry_ref, file_entry);
+    Some(entry_ref)
+}
@@ -0,0 +1,275 @@
+//! Disk whose layout is selected at runtime.
+//!
+//! [Disk] is parameterised by its layout at compile time. Tools that open
+//! images of different drives can use [DynDisk], which holds one of the
+//! built-in Commodore DOS layouts behind a single API.
+use std::{
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use d64::{Disk, Geometry, ImageFile, Layout, Sector, SectorRef, TrackNo};
+use petscii::PetsciiString;
+
+use crate::{Commodore1541, Commodore1541Geometry, Commodore1571, Commodore1581, FileEntry};
+
+/// Built-in layouts that a [DynDisk] can hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskKind {
+    /// Commodore 1541 with 35 tracks (D64).
+    Commodore1541,
+    /// Commodore 1541 with 40 tracks (D64).
+    Commodore1541Extended,
+    /// Commodore 1571 (D71).
+    Commodore1571,
+    /// Commodore 1581 (D81).
+    Commodore1581,
+}
+
+/// Layout of a [DiskKind::Commodore1541Extended] disk.
+fn extended_layout() -> Commodore1541 {
+    Commodore1541::with_geometry(Commodore1541Geometry::EXTENDED)
+}
+
+/// Number of sectors created by the given layout.
+fn num_layout_sectors<L: Geometry>(layout: &L) -> usize {
+    (1..=layout.num_tracks())
+        .map(|track_no| layout.num_sectors(track_no) as usize)
+        .sum()
+}
+
+impl DiskKind {
+    /// All built-in layouts.
+    pub const ALL: [DiskKind; 4] = [
+        DiskKind::Commodore1541,
+        DiskKind::Commodore1541Extended,
+        DiskKind::Commodore1571,
+        DiskKind::Commodore1581,
+    ];
+
+    /// Number of sectors of a disk of this kind.
+    pub fn num_sectors(&self) -> usize {
+        match self {
+            DiskKind::Commodore1541 => num_layout_sectors(&Commodore1541::default()),
+            DiskKind::Commodore1541Extended => num_layout_sectors(&extended_layout()),
+            DiskKind::Commodore1571 => num_layout_sectors(&Commodore1571::default()),
+            DiskKind::Commodore1581 => num_layout_sectors(&Commodore1581::default()),
+        }
+    }
+
+    /// Size of the image of a disk of this kind, without error information.
+    pub fn image_size(&self) -> usize {
+        // All built-in layouts use sectors of 256 bytes.
+        self.num_sectors() * 256
+    }
+
+    /// Determine the kind of disk from the size of its image. Images that are
+    /// followed by one byte of error information per sector are recognized as
+    /// well.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use commodore1541::*;
+    ///
+    /// assert_eq!(DiskKind::from_image_size(174848), Some(DiskKind::Commodore1541));
+    /// assert_eq!(DiskKind::from_image_size(175531), Some(DiskKind::Commodore1541));
+    /// assert_eq!(DiskKind::from_image_size(196608), Some(DiskKind::Commodore1541Extended));
+    /// assert_eq!(DiskKind::from_image_size(349696), Some(DiskKind::Commodore1571));
+    /// assert_eq!(DiskKind::from_image_size(819200), Some(DiskKind::Commodore1581));
+    /// assert_eq!(DiskKind::from_image_size(1000), None);
+    /// ```
+    pub fn from_image_size(size: usize) -> Option<DiskKind> {
+        DiskKind::ALL.into_iter().find(|kind| {
+            size == kind.image_size() || size == kind.image_size() + kind.num_sectors()
+        })
+    }
+}
+
+/// Disk holding one of the built-in layouts, selected at runtime.
+///
+/// # Example
+///
+/// ```
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// for kind in DiskKind::ALL {
+///     let mut disk = DynDisk::new(kind);
+///     disk.format();
+///     disk.set_name(&PetsciiString::from(&String::from("ANYDISK")));
+///     let entry = FileEntry {
+///         name: PetsciiString::from(&String::from("HELLO")),
+///         file_type: FileType::Program,
+///         ..FileEntry::default()
+///     };
+///     disk.create_file(&entry, b"HELLO WORLD");
+///
+///     let disk = DynDisk::from_bytes(disk.as_bytes().to_vec()).unwrap();
+///     assert_eq!(disk.kind(), kind);
+///     assert_eq!(String::from(&disk.get_name()), "ANYDISK");
+///     let entries = disk.list_entries();
+///     assert_eq!(disk.read_file(&entries[0]), b"HELLO WORLD");
+/// }
+/// ```
+pub enum DynDisk {
+    Commodore1541(Disk<Commodore1541>),
+    /// Commodore 1541 with 40 tracks.
+    Commodore1541Extended(Disk<Commodore1541>),
+    Commodore1571(Disk<Commodore1571>),
+    Commodore1581(Disk<Commodore1581>),
+}
+
+/// Evaluate the expression with the disk of the variant bound to the given
+/// name.
+macro_rules! with_disk {
+    ($dyn_disk:expr, $disk:ident => $expr:expr) => {
+        match $dyn_disk {
+            DynDisk::Commodore1541($disk) => $expr,
+            DynDisk::Commodore1541Extended($disk) => $expr,
+            DynDisk::Commodore1571($disk) => $expr,
+            DynDisk::Commodore1581($disk) => $expr,
+        }
+    };
+}
+
+fn unknown_image_size(size: usize) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("image size {size} doesn't match a known disk"),
+    )
+}
+
+impl DynDisk {
+    /// Create an empty (unformatted) disk of the given kind.
+    pub fn new(kind: DiskKind) -> DynDisk {
+        match kind {
+            DiskKind::Commodore1541 => DynDisk::Commodore1541(Disk::new()),
+            DiskKind::Commodore1541Extended => {
+                DynDisk::Commodore1541Extended(Disk::with_layout(extended_layout()))
+            }
+            DiskKind::Commodore1571 => DynDisk::Commodore1571(Disk::new()),
+            DiskKind::Commodore1581 => DynDisk::Commodore1581(Disk::new()),
+        }
+    }
+
+    /// Create a disk that uses the given image as its content. The kind of
+    /// disk is determined by the size of the image, see
+    /// [DiskKind::from_image_size].
+    pub fn from_bytes(bytes: Vec<u8>) -> std::io::Result<DynDisk> {
+        let kind = DiskKind::from_image_size(bytes.len())
+            .ok_or_else(|| unknown_image_size(bytes.len()))?;
+        Ok(match kind {
+            DiskKind::Commodore1541 => DynDisk::Commodore1541(Disk::from_bytes(bytes)?),
+            DiskKind::Commodore1541Extended => DynDisk::Commodore1541Extended(
+                Disk::from_bytes_with_layout(extended_layout(), bytes)?,
+            ),
+            DiskKind::Commodore1571 => DynDisk::Commodore1571(Disk::from_bytes(bytes)?),
+            DiskKind::Commodore1581 => DynDisk::Commodore1581(Disk::from_bytes(bytes)?),
+        })
+    }
+
+    /// Load a disk image from file path. The kind of disk is determined by the
+    /// size of the (decompressed) image. The error table of the image is kept,
+    /// and stored again by [DynDisk::write_to_path].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use commodore1541::*;
+    /// use std::path::*;
+    ///
+    /// let disk = DynDisk::read_from_path(Path::new("../disks/1541-empty.d64")).unwrap();
+    /// assert_eq!(disk.kind(), DiskKind::Commodore1541);
+    /// assert_eq!(String::from(&disk.get_name()), "EMPTY");
+    ///
+    /// let path = std::env::temp_dir().join("dyn-disk-error-table.d64");
+    /// let mut bytes = disk.as_bytes().to_vec();
+    /// bytes.extend(vec![0x01; 683]);
+    /// bytes[174848] = 0x05;
+    /// std::fs::write(&path, &bytes).unwrap();
+    ///
+    /// let mut disk = DynDisk::read_from_path(&path).unwrap();
+    /// disk.write_to_path(&path).unwrap();
+    /// assert_eq!(std::fs::read(&path).unwrap(), bytes);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn read_from_path(filename: &Path) -> std::io::Result<DynDisk> {
+        let image = ImageFile::read(filename)?;
+        let size = image.as_bytes().len();
+        let kind = DiskKind::from_image_size(size).ok_or_else(|| unknown_image_size(size))?;
+        Ok(match kind {
+            DiskKind::Commodore1541 => DynDisk::Commodore1541(Disk::from_image_file(image)?),
+            DiskKind::Commodore1541Extended => DynDisk::Commodore1541Extended(
+                Disk::from_image_file_with_layout(extended_layout(), image)?,
+            ),
+            DiskKind::Commodore1571 => DynDisk::Commodore1571(Disk::from_image_file(image)?),
+            DiskKind::Commodore1581 => DynDisk::Commodore1581(Disk::from_image_file(image)?),
+        })
+    }
+
+    /// Store the disk image at the given file path.
+    pub fn write_to_path(&mut self, filename: &Path) -> std::io::Result<()> {
+        with_disk!(self, disk => disk.write_to_path(filename))
+    }
+
+    /// Kind of disk, identifying its layout.
+    pub fn kind(&self) -> DiskKind {
+        match self {
+            DynDisk::Commodore1541(_) => DiskKind::Commodore1541,
+            DynDisk::Commodore1541Extended(_) => DiskKind::Commodore1541Extended,
+            DynDisk::Commodore1571(_) => DiskKind::Commodore1571,
+            DynDisk::Commodore1581(_) => DiskKind::Commodore1581,
+        }
+    }
+
+    /// Content of all sectors of the disk, in the order they are stored in an
+    /// image.
+    pub fn as_bytes(&self) -> &[u8] {
+        with_disk!(self, disk => disk.as_bytes())
+    }
+
+    pub fn num_tracks(&self) -> TrackNo {
+        with_disk!(self, disk => disk.num_tracks())
+    }
+
+    pub fn get_sector(&self, sector_ref: SectorRef) -> &Sector {
+        with_disk!(self, disk => disk.get_sector(sector_ref))
+    }
+
+    pub fn get_sector_mut(&mut self, sector_ref: SectorRef) -> &mut Sector {
+        with_disk!(self, disk => disk.get_sector_mut(sector_ref))
+    }
+
+    pub fn get_name(&self) -> PetsciiString {
+        with_disk!(self, disk => disk.get_name())
+    }
+
+    pub fn set_name(&mut self, new_name: &PetsciiString) {
+        with_disk!(self, disk => disk.set_name(new_name))
+    }
+
+    pub fn format(&mut self) {
+        with_disk!(self, disk => disk.format())
+    }
+
+    pub fn list_entries(&self) -> Vec<FileEntry> {
+        with_disk!(self, disk => disk.layout().list_entries(disk))
+    }
+
+    pub fn read_file(&self, file_entry: &FileEntry) -> Vec<u8> {
+        with_disk!(self, disk => disk.read_file(file_entry))
+    }
+
+    pub fn create_file(&mut self, file_entry: &FileEntry, content: &[u8]) {
+        with_disk!(self, disk => disk.create_file(file_entry, content))
+    }
+
+    pub fn delete_file(&mut self, file_entry: &FileEntry) {
+        with_disk!(self, disk => disk.delete_file(file_entry))
+    }
+
+    pub fn num_unused_sectors(&mut self) -> usize {
+        with_disk!(self, disk => disk.num_unused_sectors())
+    }
+}
@@ -6,16 +6,23 @@ const OFFSET_START_SECTOR: usize = 3;
 const OFFSET_NAME: usize = 5;
 const NAME_LENGTH: usize = 16;
 const OFFSET_NAME_END: usize = OFFSET_NAME + NAME_LENGTH;
+const OFFSET_SIDE_SECTOR: usize = 21;
+const OFFSET_RECORD_LENGTH: usize = 23;
 const OFFSET_NUM_SECTORS: usize = 30;
 
-#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Default)]
 pub enum FileType {
     Scratched = 0x00,
     Deleted = 0x80,
     Sequence = 0x81,
+    #[default]
     Program = 0x82,
     User = 0x83,
     Relative = 0x84,
+    /// Partition inside a 1581 disk.
+    Partition = 0x85,
+    /// Subdirectory of a CMD native partition.
+    Directory = 0x86,
 }
 
 impl From<u8> for FileType {
@@ -27,6 +34,8 @@ impl From<u8> for FileType {
             0x82 => FileType::Program,
             0x83 => FileType::User,
             0x84 => FileType::Relative,
+            0x85 => FileType::Partition,
+            0x86 => FileType::Directory,
             _ => FileType::Scratched,
         }
     }
@@ -38,18 +47,17 @@ impl From<FileType> for u8 {
     }
 }
 
-impl Default for FileType {
-    fn default() -> FileType {
-        FileType::Program
-    }
-}
-
 #[derive(Debug, Clone, Default)]
 pub struct FileEntry {
     pub name: PetsciiString,
     pub file_type: FileType,
     pub num_sectors: usize,
+    /// Length of each record of a relative file. Zero for other file types.
+    pub record_length: u8,
     pub start_sector: SectorRef,
+    /// First side sector of a relative file, or its super side sector on
+    /// layouts that use one.
+    pub side_sector: SectorRef,
     pub file_entry_ref: FileListEntryRef,
 }
 
@@ -65,7 +73,9 @@ impl FileEntry {
             name,
             file_type,
             num_sectors: num_blocks,
+            record_length: bytes[OFFSET_RECORD_LENGTH],
             start_sector,
+            side_sector: (bytes[OFFSET_SIDE_SECTOR], bytes[OFFSET_SIDE_SECTOR + 1]),
             file_entry_ref,
         }
     }
@@ -76,6 +86,9 @@ impl FileEntry {
         sector.set_byte(offset + OFFSET_START_SECTOR + 1, self.start_sector.1);
         sector.fill(offset + OFFSET_NAME, offset + OFFSET_NAME_END, PETSCII_NBSP);
         sector.set_bytes(offset + OFFSET_NAME, self.name.as_slice());
+        sector.set_byte(offset + OFFSET_SIDE_SECTOR, self.side_sector.0);
+        sector.set_byte(offset + OFFSET_SIDE_SECTOR + 1, self.side_sector.1);
+        sector.set_byte(offset + OFFSET_RECORD_LENGTH, self.record_length);
         sector.set_byte(
             offset + OFFSET_NUM_SECTORS + 1,
             (self.num_sectors / 256) as u8,
@@ -0,0 +1,157 @@
+//! Geometries of the Commodore disk drives.
+//!
+//! The geometries only describe the tracks and sectors of a disk. The layouts
+//! of this crate add the Commodore DOS file system on top of them; other file
+//! systems (like CP/M) can use the same geometries. A disk with only a
+//! geometry provides raw access to its sectors.
+use d64::{Geometry, TrackNo};
+
+const BYTES_PER_SECTOR: u16 = 256;
+/// Number of tracks on a single side of a 5.25" disk.
+const TRACKS_PER_SIDE: TrackNo = 35;
+/// Number of tracks of an extended 5.25" disk.
+const NUM_EXTENDED_TRACKS: TrackNo = 40;
+
+/// Number of sectors of the given track of a 5.25" disk side, which depends on
+/// the speed zone of the track.
+fn num_sectors_per_side(track_on_side: TrackNo) -> u16 {
+    match track_on_side {
+        1..=17 => 21,
+        18..=24 => 19,
+        25..=30 => 18,
+        _ => 17,
+    }
+}
+
+/// Geometry of a Commodore 1541 disk: 35 tracks (or 40 for an extended disk)
+/// with 17 to 21 sectors of 256 bytes.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+///
+/// let disk = Disk::<Commodore1541Geometry>::new();
+/// assert_eq!(disk.as_bytes().len(), 174848);
+///
+/// let disk = Disk::with_layout(Commodore1541Geometry::EXTENDED);
+/// assert_eq!(disk.as_bytes().len(), 196608);
+///
+/// let geometry = Commodore1541Geometry::with_num_tracks(41);
+/// assert_eq!(geometry, Commodore1541Geometry::EXTENDED);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Commodore1541Geometry {
+    num_tracks: TrackNo,
+}
+
+impl Commodore1541Geometry {
+    /// Standard disk with 35 tracks.
+    pub const STANDARD: Commodore1541Geometry = Commodore1541Geometry {
+        num_tracks: TRACKS_PER_SIDE,
+    };
+    /// Extended disk with 40 tracks.
+    pub const EXTENDED: Commodore1541Geometry = Commodore1541Geometry {
+        num_tracks: NUM_EXTENDED_TRACKS,
+    };
+
+    /// Geometry with the given number of tracks. More than 40 tracks are
+    /// limited to 40.
+    pub const fn with_num_tracks(num_tracks: TrackNo) -> Commodore1541Geometry {
+        let num_tracks = if num_tracks > NUM_EXTENDED_TRACKS {
+            NUM_EXTENDED_TRACKS
+        } else {
+            num_tracks
+        };
+        Commodore1541Geometry { num_tracks }
+    }
+
+    /// Speed zone (0-3) used to record the given track.
+    pub fn speed_zone(&self, track: TrackNo) -> u8 {
+        match track {
+            1..=17 => 3,
+            18..=24 => 2,
+            25..=30 => 1,
+            _ => 0,
+        }
+    }
+}
+
+impl Default for Commodore1541Geometry {
+    fn default() -> Self {
+        Commodore1541Geometry::STANDARD
+    }
+}
+
+impl Geometry for Commodore1541Geometry {
+    fn num_tracks(&self) -> u8 {
+        self.num_tracks
+    }
+
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        if track == 0 || track > self.num_tracks {
+            return 0;
+        }
+        num_sectors_per_side(track)
+    }
+
+    fn bytes_per_sector(&self) -> u16 {
+        BYTES_PER_SECTOR
+    }
+}
+
+/// Geometry of a Commodore 1571 disk: both sides of the disk with the
+/// geometry of a 1541 disk. Tracks 36-70 are the tracks of the second side.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+///
+/// let geometry = Commodore1571Geometry::default();
+/// assert_eq!(geometry.num_sectors(1), 21);
+/// assert_eq!(geometry.num_sectors(36), 21);
+/// assert_eq!(geometry.num_sectors(70), 17);
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Commodore1571Geometry {}
+
+impl Geometry for Commodore1571Geometry {
+    fn num_tracks(&self) -> u8 {
+        2 * TRACKS_PER_SIDE
+    }
+
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        if track == 0 || track > self.num_tracks() {
+            return 0;
+        }
+        num_sectors_per_side((track - 1) % TRACKS_PER_SIDE + 1)
+    }
+
+    fn bytes_per_sector(&self) -> u16 {
+        BYTES_PER_SECTOR
+    }
+}
+
+/// Geometry of a Commodore 1581 disk: 80 tracks of 40 sectors of 256 bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Commodore1581Geometry {}
+
+impl Geometry for Commodore1581Geometry {
+    fn num_tracks(&self) -> u8 {
+        80
+    }
+
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        if track == 0 || track > self.num_tracks() {
+            return 0;
+        }
+        40
+    }
+
+    fn bytes_per_sector(&self) -> u16 {
+        BYTES_PER_SECTOR
+    }
+}
@@ -1,5 +1,6 @@
-use crate::{BlockAvailabilityMap, FileEntry, FileListEntryRef, FileType};
-use d64::{Disk, Layout, Sector, SectorRef, TrackNo};
+use crate::dos::*;
+use crate::{BlockAvailabilityMap, Commodore1541Geometry, FileEntry};
+use d64::{Disk, GcrLayout, Geometry, Layout, SectorRef, TrackNo, X64Layout};
 use petscii::{PetsciiString, PETSCII_A, PETSCII_NBSP, PETSCII_ONE, PETSCII_TWO, PETSCII_ZERO};
 
 /// Track number containing info about the disk, and files on the disk.
@@ -8,18 +9,22 @@ const TRACK_HEADER: TrackNo = 18;
 const SECTOR_DISK_HEADER: SectorRef = (TRACK_HEADER, 0);
 /// Default sector where to start the file list stored on the disk.
 const SECTOR_DISK_LISTING: SectorRef = (TRACK_HEADER, 1);
-/// Indicates that marks the end of a chain of sectors.
-const SECTOR_END_OF_CHAIN: SectorRef = (0, 255);
-const BYTES_PER_SECTOR: usize = 256;
-/// Header of a sector is 2 bytes. It contains the sector ref to the next sector, or SECTOR_END_OF_CHAIN for the last.
-const SECTOR_HEADER_SIZE: usize = 2;
-/// Size of actual content that can be stored in a single sector.
-const CONTENT_BYTES_PER_SECTOR: usize = BYTES_PER_SECTOR - SECTOR_HEADER_SIZE;
-/// Size of each file list entry on disk.
-const FILE_LIST_ENTRY_SIZE: usize = 32;
 const DISK_NAME_OFFSET_START: usize = 9 * 16;
 const DISK_NAME_LENGTH: usize = 16;
 const DISK_NAME_OFFSET_END: usize = DISK_NAME_OFFSET_START + DISK_NAME_LENGTH;
+const DISK_ID_OFFSET: usize = 162;
+/// Device type of X64 files.
+const X64_DEVICE_TYPE: u8 = 0;
+/// Number of tracks of a standard disk.
+const NUM_STANDARD_TRACKS: TrackNo = 35;
+/// Offset of the BAM entries of the extended tracks (36-40) in the header
+/// sector, as used by SpeedDOS.
+const EXTENDED_BAM_ENTRY_OFFSET: usize = 0xc0;
+/// Size of a BAM entry. One byte for the number of unused sectors followed by
+/// a bitmap of 3 bytes.
+const BAM_ENTRY_SIZE: usize = 4;
+/// Distance between the sectors of a file on the same track.
+const DEFAULT_INTERLEAVE: u8 = 1;
 
 /// Commodore 1541 disk-drive.
 ///
@@ -29,37 +34,69 @@ const DISK_NAME_OFFSET_END: usize = DISK_NAME_OFFSET_START + DISK_NAME_LENGTH;
 /// A Commodore 1541 can be programmed to store its data differently on the
 /// physical media. This programming isn't supported. When needed you have to
 /// implement your own Layout.
-#[derive(Default)]
-pub struct Commodore1541 {}
+///
+/// By default the layout uses the standard 35 tracks (D64 files). The number
+/// of tracks and the interleave used to store files can be configured, see
+/// [Commodore1541::new] and [Disk::with_layout].
+///
+/// # Example
+///
+/// An extended disk uses 40 tracks (D64 files of 196608 bytes). The extra
+/// tracks 36-40 contain 17 sectors each. Their BAM entries are stored at
+/// offset 0xC0 of the header sector, like SpeedDOS does.
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::with_layout(Commodore1541::with_geometry(Commodore1541Geometry::EXTENDED));
+/// disk.format();
+/// assert_eq!(disk.as_bytes().len(), 196608);
+/// assert_eq!(disk.num_unused_sectors(), 749);
+///
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("BIG")),
+///     file_type: FileType::Program,
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &vec![1; 700 * 254]);
+/// assert_eq!(disk.num_unused_sectors(), 49);
+/// let entries = disk.list_entries();
+/// assert_eq!(disk.read_file(&entries[0]).len(), 700 * 254);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Commodore1541 {
+    /// Geometry the file system is stored on, either 35 or 40 tracks.
+    geometry: Commodore1541Geometry,
+    /// Distance between the sectors of a file on the same track.
+    interleave: u8,
+}
 
-impl Layout for Commodore1541 {
-    /// Type to contain a single entry for [Layout::list_entries].
-    type FileEntryType = FileEntry;
-    type StringType = PetsciiString;
+impl Default for Commodore1541 {
+    fn default() -> Self {
+        Commodore1541::new(Commodore1541Geometry::STANDARD, DEFAULT_INTERLEAVE)
+    }
+}
 
+impl Geometry for Commodore1541 {
     fn num_tracks(&self) -> u8 {
-        35
+        self.geometry().num_tracks()
     }
 
-    fn num_sectors(&self, track: TrackNo) -> u8 {
-        if (1..=17).contains(&track) {
-            return 21;
-        }
-        if (18..=24).contains(&track) {
-            return 19;
-        }
-        if (25..=30).contains(&track) {
-            return 18;
-        }
-        if (31..=35).contains(&track) {
-            return 17;
-        }
-        0
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        self.geometry().num_sectors(track)
     }
 
     fn bytes_per_sector(&self) -> u16 {
-        BYTES_PER_SECTOR as u16
+        self.geometry().bytes_per_sector()
     }
+}
+
+impl Layout for Commodore1541 {
+    /// Type to contain a single entry for [Layout::list_entries].
+    type FileEntryType = FileEntry;
+    type StringType = PetsciiString;
 
     fn get_disk_name(&self, disk: &Disk<Self>) -> PetsciiString
     where
@@ -97,30 +134,15 @@ impl Layout for Commodore1541 {
     where
         Self: Sized,
     {
-        let sector_refs = self.get_all_sector_refs();
-        self.clear_sector_refs(disk, &sector_refs);
+        let sector_refs = get_all_sector_refs(self);
+        clear_sector_refs(disk, &sector_refs);
     }
 
     fn list_entries(&self, disk: &Disk<Self>) -> Vec<FileEntry>
     where
         Self: Sized,
     {
-        let mut result = Vec::new();
-        let mut sector = disk.get_sector(SECTOR_DISK_HEADER);
-        while let Some(s) = self.get_next_sector(disk, sector) {
-            sector = s.0;
-
-            let mut entry_bytes = [0_u8; FILE_LIST_ENTRY_SIZE];
-            for sector_entry in 0..8 {
-                let file_entry_ref = (s.1, sector_entry);
-                sector.get_bytes(sector_entry * FILE_LIST_ENTRY_SIZE, &mut entry_bytes);
-                let entry = FileEntry::from_bytes(&entry_bytes, file_entry_ref);
-                if entry.file_type != FileType::Scratched {
-                    result.push(entry);
-                }
-            }
-        }
-        result
+        list_entries(disk, SECTOR_DISK_HEADER)
     }
 
     /// Return the contents of the given file.
@@ -128,10 +150,7 @@ impl Layout for Commodore1541 {
     where
         Self: Sized,
     {
-        let mut result = Vec::new();
-        self.read_sector_chain(disk, file_entry.start_sector, &mut result);
-
-        result
+        read_file(disk, file_entry)
     }
 
     /// Create a new file and store it to disk.
@@ -139,273 +158,164 @@ impl Layout for Commodore1541 {
     where
         Self: Sized,
     {
-        let chunks = content.chunks(CONTENT_BYTES_PER_SECTOR);
-        let num_sectors = chunks.len();
-        if let Some(sectors) = self.allocate_sectors(disk, num_sectors) {
-            self.clear_sector_refs(disk, &sectors);
-            self.chain_sectors(disk, &sectors);
-
-            for (sector_ref, chunk) in sectors.iter().zip(chunks) {
-                let sector = disk.get_sector_mut(*sector_ref);
-                sector.set_bytes(SECTOR_HEADER_SIZE, chunk);
-            }
-
-            let mut file_entry = file_entry.clone();
-            file_entry.start_sector = sectors[0];
-            file_entry.num_sectors = num_sectors;
-            self.create_file_list_entry(disk, &file_entry);
-        }
+        create_file(self, disk, file_entry, content)
     }
 
     fn delete_file(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType)
     where
         Self: Sized,
     {
-        let sectors_to_clear = self.get_sector_ref_chain(disk, file_entry.start_sector);
-        self.mark_sector_refs_unused(disk, &sectors_to_clear);
-        self.clear_sector_refs(disk, &sectors_to_clear);
-        self.scratch_file_list_entry(disk, file_entry);
+        delete_file(self, disk, file_entry)
     }
 
     fn num_unused_sectors(&self, disk: &mut Disk<Self>) -> usize
     where
         Self: Sized,
     {
-        let bam = self.get_block_availability_map(disk);
-        bam.count_unused_sectors(1, self.num_tracks())
-            - bam.count_unused_track_sectors(TRACK_HEADER) as usize
+        let mut result = 0;
+        for track_no in 1..=self.num_tracks() {
+            if track_no == TRACK_HEADER {
+                continue;
+            }
+            let bam = self.get_block_availability_map(disk, track_no);
+            result += bam.count_unused_track_sectors(track_no) as usize;
+        }
+        result
     }
 }
 
-impl Commodore1541 {
-    fn get_block_availability_map<'a>(&self, disk: &'a mut Disk<Self>) -> BlockAvailabilityMap<'a> {
-        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
-        BlockAvailabilityMap::new(sector)
+impl DosLayout for Commodore1541 {
+    fn is_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) -> bool {
+        self.get_block_availability_map(disk, sector_ref.0)
+            .is_unused(sector_ref)
     }
 
     fn mark_sector_used(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
-        let mut bam = self.get_block_availability_map(disk);
-        bam.mark_used(sector_ref);
-    }
-
-    fn mark_sector_refs_unused(&self, disk: &mut Disk<Self>, sector_refs: &[SectorRef]) {
-        let mut bam = self.get_block_availability_map(disk);
-        for sector_ref in sector_refs {
-            bam.mark_unused(*sector_ref);
-        }
+        self.get_block_availability_map(disk, sector_ref.0)
+            .mark_used(sector_ref);
     }
 
-    // Initialize the disk ID default=01-2A
-    fn initialize_disk_id(&self, disk: &mut Disk<Self>) {
-        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
-        for offset in 160..171 {
-            sector.set_byte(offset, PETSCII_NBSP);
-        }
-        sector.set_byte(162, PETSCII_ZERO);
-        sector.set_byte(163, PETSCII_ONE);
-        sector.set_byte(165, PETSCII_TWO);
-        sector.set_byte(166, PETSCII_A);
+    fn mark_sector_unused(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
+        self.get_block_availability_map(disk, sector_ref.0)
+            .mark_unused(sector_ref);
     }
 
-    fn initialize_dos_version(&self, disk: &mut Disk<Self>) {
-        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
-        sector.set_byte(2, 65);
+    /// The header track is reserved for the directory listing.
+    fn is_system_track(&self, track_no: TrackNo) -> bool {
+        track_no == TRACK_HEADER
     }
 
-    fn initialize_bam(&self, disk: &mut Disk<Self>) {
-        let sector_refs = self.get_all_sector_refs();
-        self.mark_sector_refs_unused(disk, &sector_refs);
-
-        let mut bam = self.get_block_availability_map(disk);
-        bam.mark_used(SECTOR_DISK_HEADER);
+    fn interleave(&self) -> u8 {
+        self.interleave
     }
 
-    fn initialize_directory_listing(&self, disk: &mut Disk<Self>) {
-        let sector180 = disk.get_sector_mut(SECTOR_DISK_HEADER);
-        self.set_next_sector(sector180, SECTOR_DISK_LISTING);
-
-        let sector181 = disk.get_sector_mut(SECTOR_DISK_LISTING);
-        self.end_sector_chain(sector181);
-        self.mark_sector_used(disk, SECTOR_DISK_LISTING);
+    fn directory_header(&self, _disk: &Disk<Self>) -> SectorRef {
+        SECTOR_DISK_HEADER
     }
+}
 
-    /// Set the next sector for the given sector in a chain of sectors.
-    fn set_next_sector(&self, sector: &mut Sector, sector_ref: SectorRef) {
-        sector.set_byte(0, sector_ref.0);
-        sector.set_byte(1, sector_ref.1);
+impl GcrLayout for Commodore1541 {
+    fn speed_zone(&self, track: TrackNo) -> u8 {
+        self.geometry().speed_zone(track)
     }
 
-    fn get_next_sector<'a>(
-        &self,
-        disk: &'a Disk<Self>,
-        sector: &Sector,
-    ) -> Option<(&'a Sector, SectorRef)> {
-        let track_no = *sector.get_byte(0);
-        if track_no == SECTOR_END_OF_CHAIN.0 {
-            None
-        } else {
-            let sector_no = *sector.get_byte(1);
-            let sector_ref = (track_no, sector_no);
-            Some((disk.get_sector(sector_ref), sector_ref))
-        }
+    fn get_disk_id(&self, disk: &Disk<Self>) -> [u8; 2] {
+        let sector = disk.get_sector(SECTOR_DISK_HEADER);
+        [
+            *sector.get_byte(DISK_ID_OFFSET),
+            *sector.get_byte(DISK_ID_OFFSET + 1),
+        ]
     }
+}
 
-    /// Mark the given sector to be the last sector in a chain.
-    fn end_sector_chain(&self, sector: &mut Sector) {
-        self.set_next_sector(sector, SECTOR_END_OF_CHAIN);
+impl X64Layout for Commodore1541 {
+    fn x64_device_type(&self) -> u8 {
+        X64_DEVICE_TYPE
     }
+}
 
-    fn read_sector_chain(
-        &self,
-        disk: &Disk<Self>,
-        sector_ref: SectorRef,
-        file_content: &mut Vec<u8>,
-    ) {
-        if sector_ref.0 == SECTOR_END_OF_CHAIN.0 {
-            return;
-        }
-        let mut bytes = [0_u8; CONTENT_BYTES_PER_SECTOR];
-
-        let mut sector = disk.get_sector(sector_ref);
-        sector.get_bytes(SECTOR_HEADER_SIZE, &mut bytes);
-        file_content.extend_from_slice(&bytes);
-
-        while let Some(s) = self.get_next_sector(disk, sector) {
-            sector = s.0;
-            sector.get_bytes(SECTOR_HEADER_SIZE, &mut bytes);
-            file_content.extend_from_slice(&bytes);
+impl Commodore1541 {
+    /// Layout on the given geometry, storing consecutive sectors of a file the
+    /// given number of sectors apart when they are on the same track.
+    pub fn new(geometry: Commodore1541Geometry, interleave: u8) -> Self {
+        Commodore1541 {
+            geometry,
+            interleave: interleave.max(1),
         }
     }
 
-    /// Get the chain of sectors starting from the given sector_ref.
-    fn get_sector_ref_chain(&self, disk: &Disk<Self>, sector_ref: SectorRef) -> Vec<SectorRef> {
-        if sector_ref.0 == SECTOR_END_OF_CHAIN.0 {
-            return Vec::new();
-        }
-
-        let mut sector_refs = Vec::new();
-        let mut sector = disk.get_sector(sector_ref);
-        let mut next_sector_ref = (*sector.get_byte(0), *sector.get_byte(1));
-        sector_refs.push(next_sector_ref);
-        while next_sector_ref.0 != 0 {
-            sector = disk.get_sector(next_sector_ref);
-            next_sector_ref = (*sector.get_byte(0), *sector.get_byte(1));
-            sector_refs.push(next_sector_ref);
-        }
-        sector_refs
+    /// Layout on the given geometry, for example
+    /// [Commodore1541Geometry::EXTENDED] for 40 track disks.
+    pub fn with_geometry(geometry: Commodore1541Geometry) -> Self {
+        Commodore1541::new(geometry, DEFAULT_INTERLEAVE)
     }
 
-    fn get_all_sector_refs(&self) -> Vec<SectorRef> {
-        let mut sector_refs = Vec::new();
-        for track_no in 1..=self.num_tracks() {
-            for sector_no in 0..self.num_sectors(track_no) {
-                sector_refs.push((track_no, sector_no));
-            }
-        }
-        sector_refs
+    /// Layout that stores consecutive sectors of a file the given number of
+    /// sectors apart, when they are on the same track. The Commodore 1541 ROM
+    /// uses an interleave of 10, fast loaders often use smaller values.
+    pub fn with_interleave(interleave: u8) -> Self {
+        Commodore1541::new(Commodore1541Geometry::STANDARD, interleave)
     }
 
-    fn chain_sectors(&self, disk: &mut Disk<Self>, sectors: &[SectorRef]) {
-        if sectors.is_empty() {
-            return;
-        }
-
-        for i in 0..sectors.len() - 1 {
-            let sector_ref = sectors[i];
-            let next_sector_ref = sectors[i + 1];
-            let sector = disk.get_sector_mut(sector_ref);
-            self.set_next_sector(sector, next_sector_ref);
-        }
-        let sector = disk.get_sector_mut(*sectors.last().unwrap());
-        self.end_sector_chain(sector);
+    /// Distance between the sectors of a file on the same track.
+    pub fn interleave(&self) -> u8 {
+        self.interleave
     }
 
-    fn allocate_sectors(
-        &self,
-        disk: &mut Disk<Self>,
-        num_sectors: usize,
-    ) -> Option<Vec<SectorRef>> {
-        let mut bam = self.get_block_availability_map(disk);
-        bam.allocate_sectors(num_sectors)
+    /// Geometry the file system is stored on.
+    pub fn geometry(&self) -> Commodore1541Geometry {
+        self.geometry
     }
 
-    fn create_file_list_entry(&self, disk: &mut Disk<Self>, file_entry: &FileEntry) {
-        if let Some(entry_ref) = self.find_scratched_file_list_entry(disk) {
-            self.update_file_list_entry(disk, entry_ref, file_entry);
+    /// Get the BAM entries containing the availability of the given track.
+    fn get_block_availability_map<'a>(
+        &self,
+        disk: &'a mut Disk<Self>,
+        track_no: TrackNo,
+    ) -> BlockAvailabilityMap<'a> {
+        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        if track_no > NUM_STANDARD_TRACKS {
+            BlockAvailabilityMap::with_entries(
+                sector,
+                NUM_STANDARD_TRACKS + 1,
+                EXTENDED_BAM_ENTRY_OFFSET,
+                BAM_ENTRY_SIZE,
+            )
         } else {
-            if let Some(sector_ref) = self.create_file_list_sector(disk) {
-                self.update_file_list_entry(disk, (sector_ref, 0), file_entry);
-            }
+            BlockAvailabilityMap::new(sector)
         }
     }
 
-    fn find_scratched_file_list_entry(&self, disk: &mut Disk<Self>) -> Option<FileListEntryRef> {
-        let mut sector = disk.get_sector(SECTOR_DISK_HEADER);
-        while let Some(s) = self.get_next_sector(disk, sector) {
-            sector = s.0;
-            let sector_ref = s.1;
-
-            let mut entry_bytes = [0_u8; FILE_LIST_ENTRY_SIZE];
-            for sector_entry in 0..8 {
-                let file_entry_ref = (sector_ref, sector_entry);
-                sector.get_bytes(sector_entry * FILE_LIST_ENTRY_SIZE, &mut entry_bytes);
-                let entry = FileEntry::from_bytes(&entry_bytes, file_entry_ref);
-                if entry.file_type == FileType::Scratched {
-                    return Some((sector_ref, sector_entry));
-                }
-            }
+    // Initialize the disk ID default=01-2A
+    fn initialize_disk_id(&self, disk: &mut Disk<Self>) {
+        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        for offset in 160..171 {
+            sector.set_byte(offset, PETSCII_NBSP);
         }
-        None
+        sector.set_byte(DISK_ID_OFFSET, PETSCII_ZERO);
+        sector.set_byte(DISK_ID_OFFSET + 1, PETSCII_ONE);
+        sector.set_byte(165, PETSCII_TWO);
+        sector.set_byte(166, PETSCII_A);
     }
 
-    fn scratch_file_list_entry(&self, disk: &mut Disk<Self>, file_entry: &FileEntry) {
-        let sector = disk.get_sector_mut(file_entry.file_entry_ref.0);
-        let offset = file_entry.file_entry_ref.1 as usize * FILE_LIST_ENTRY_SIZE;
-        file_entry.scratch(sector, offset);
+    fn initialize_dos_version(&self, disk: &mut Disk<Self>) {
+        let sector = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        sector.set_byte(2, 65);
     }
 
-    fn create_file_list_sector(&self, disk: &mut Disk<Self>) -> Option<SectorRef> {
-        let mut bam = self.get_block_availability_map(disk);
-        if let Some(sector_refs) = bam.allocate_sectors(1) {
-            let mut sector = disk.get_sector(SECTOR_DISK_HEADER);
-            let mut sector_ref = SECTOR_DISK_HEADER;
-            while let Some(s) = self.get_next_sector(disk, sector) {
-                sector = s.0;
-                sector_ref = s.1;
-            }
-
-            let new_sector_ref = sector_refs[0];
-            let sector = disk.get_sector_mut(sector_ref);
-            self.set_next_sector(sector, new_sector_ref);
-
-            let new_sector = disk.get_sector_mut(new_sector_ref);
-            new_sector.fill(SECTOR_HEADER_SIZE, self.bytes_per_sector() as usize, 0);
-            self.end_sector_chain(new_sector);
-            Some(new_sector_ref)
-        } else {
-            None
+    fn initialize_bam(&self, disk: &mut Disk<Self>) {
+        for sector_ref in get_all_sector_refs(self) {
+            self.mark_sector_unused(disk, sector_ref);
         }
+        self.mark_sector_used(disk, SECTOR_DISK_HEADER);
     }
 
-    fn update_file_list_entry(
-        &self,
-        disk: &mut Disk<Self>,
-        entry_ref: FileListEntryRef,
-        file_entry: &FileEntry,
-    ) {
-        let sector = disk.get_sector_mut(entry_ref.0);
-        let offset = entry_ref.1 * FILE_LIST_ENTRY_SIZE;
-        file_entry.store(sector, offset);
-    }
-
-    fn clear_sector_refs(&self, disk: &mut Disk<Self>, sector_refs: &[SectorRef]) {
-        for sector_ref in sector_refs {
-            self.clear_sector_ref(disk, *sector_ref);
-        }
-    }
+    fn initialize_directory_listing(&self, disk: &mut Disk<Self>) {
+        let sector180 = disk.get_sector_mut(SECTOR_DISK_HEADER);
+        set_next_sector(sector180, SECTOR_DISK_LISTING);
 
-    fn clear_sector_ref(&self, disk: &mut Disk<Self>, sector_ref: SectorRef) {
-        let sector = disk.get_sector_mut(sector_ref);
-        sector.fill(0, self.bytes_per_sector() as usize, 0);
+        let sector181 = disk.get_sector_mut(SECTOR_DISK_LISTING);
+        end_sector_chain(sector181);
+        self.mark_sector_used(disk, SECTOR_DISK_LISTING);
     }
 }
@@ -1,8 +1,33 @@
 mod bam;
+mod cmd_fd;
+mod cmd_native;
+mod commodore1571;
+mod commodore1581;
+mod commodore8050;
+mod cpm;
 pub mod debug;
+mod dos;
+mod dyn_disk;
 mod file_entry;
+mod geometry;
 mod layout;
+mod lynx;
+mod partition_entry;
+mod pc64;
+mod zipcode;
 
 pub use bam::*;
+pub use cmd_fd::*;
+pub use cmd_native::*;
+pub use commodore1571::*;
+pub use commodore1581::*;
+pub use commodore8050::*;
+pub use cpm::*;
+pub use dyn_disk::*;
 pub use file_entry::*;
+pub use geometry::*;
 pub use layout::*;
+pub use lynx::*;
+pub use partition_entry::*;
+pub use pc64::*;
+pub use zipcode::*;
@@ -0,0 +1,388 @@
+//! Lynx archives (LNX files) bundle multiple files of a disk. An archive starts
+//! with a small BASIC program, followed by a directory and the content of the
+//! files, each aligned to blocks of 254 bytes.
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use d64::{Disk, Layout};
+use petscii::{PetsciiString, PETSCII_NBSP};
+
+use crate::{FileEntry, FileType};
+
+/// Number of content bytes of a sector.
+const BLOCK_SIZE: usize = 254;
+const NAME_LENGTH: usize = 16;
+const LOAD_ADDRESS: u16 = 0x0801;
+const CARRIAGE_RETURN: u8 = 0x0D;
+const SIGNATURE: &[u8] = b"*LYNX XV  BY WILL CORLEY";
+/// Line 10 of the BASIC program Lynx writes in front of the directory:
+/// `POKE53280,0:POKE53281,0:POKE646,PEEK(162):PRINT"<clr><down>...USE LYNX TO
+/// DISSOLVE THIS FILE":GOTO10`
+const BASIC_LINE: &[u8] = b"\x0a\x00\
+    \x9753280,0:\x9753281,0:\x97646,\xc2(162):\
+    \x99\"\x93\x11\x11\x11\x11\x11\x11\x11\x11\x11\x11       USE LYNX TO DISSOLVE THIS FILE\":\
+    \x8910\x00";
+
+/// Single file stored in a Lynx archive.
+#[derive(Debug, Clone, Default)]
+pub struct LynxEntry {
+    pub name: PetsciiString,
+    pub file_type: FileType,
+    /// Length of each record of a relative file. Zero for other file types.
+    pub record_length: u8,
+    pub data: Vec<u8>,
+}
+
+/// Content of a Lynx archive.
+///
+/// # Example
+///
+/// ```
+/// use commodore1541::*;
+/// use d64::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.format();
+/// let program = FileEntry {
+///     name: PetsciiString::from(&String::from("GAME")),
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&program, &vec![0x42; 600]);
+/// let records = FileEntry {
+///     name: PetsciiString::from(&String::from("SCORES")),
+///     file_type: FileType::Relative,
+///     record_length: 20,
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&records, &vec![0x13; 60]);
+///
+/// let entries = disk.list_entries();
+/// let archive = LynxArchive::read_from_disk(&disk, &entries);
+/// let mut bytes = Vec::new();
+/// archive.write_to_writer(&mut bytes).unwrap();
+/// assert_eq!(&bytes[0..2], &[0x01, 0x08]);
+///
+/// let archive = LynxArchive::read_from_reader(&mut bytes.as_slice()).unwrap();
+/// let mut copy = Disk::<Commodore1541>::new();
+/// copy.format();
+/// archive.write_to_disk(&mut copy);
+///
+/// let entries = copy.list_entries();
+/// assert_eq!(String::from(&entries[0].name), "GAME");
+/// assert_eq!(copy.read_file(&entries[0]), vec![0x42; 600]);
+/// assert_eq!(String::from(&entries[1].name), "SCORES");
+/// assert_eq!(entries[1].file_type, FileType::Relative);
+/// assert_eq!(entries[1].record_length, 20);
+/// assert_eq!(entries[1].num_sectors, 2);
+/// assert_eq!(copy.read_file(&entries[1]), vec![0x13; 60]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LynxArchive {
+    pub entries: Vec<LynxEntry>,
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn file_type_letter(file_type: FileType) -> Option<u8> {
+    match file_type {
+        FileType::Deleted => Some(b'D'),
+        FileType::Sequence => Some(b'S'),
+        FileType::Program => Some(b'P'),
+        FileType::User => Some(b'U'),
+        FileType::Relative => Some(b'R'),
+        _ => None,
+    }
+}
+
+fn file_type_from_letter(letter: u8) -> Option<FileType> {
+    match letter {
+        b'D' => Some(FileType::Deleted),
+        b'S' => Some(FileType::Sequence),
+        b'P' => Some(FileType::Program),
+        b'U' => Some(FileType::User),
+        b'R' => Some(FileType::Relative),
+        _ => None,
+    }
+}
+
+/// Number of blocks a file of the given length occupies.
+fn num_blocks(length: usize) -> usize {
+    length.div_ceil(BLOCK_SIZE).max(1)
+}
+
+/// Number of used bytes of the last block plus one, as stored in the second
+/// byte of the last sector of a file.
+fn last_block_usage(length: usize) -> usize {
+    if length == 0 {
+        1
+    } else {
+        (length - 1) % BLOCK_SIZE + 2
+    }
+}
+
+/// Strip the shifted spaces disk names are padded with.
+fn trim_name(name: &PetsciiString) -> PetsciiString {
+    let bytes = name.as_slice();
+    let length = bytes
+        .iter()
+        .rposition(|byte| *byte != PETSCII_NBSP)
+        .map_or(0, |position| position + 1);
+    PetsciiString::fixed_size(&bytes[..length])
+}
+
+/// Directory fields, each terminated by a carriage return.
+struct Fields<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn next_field(&mut self) -> std::io::Result<&'a [u8]> {
+        let length = self.bytes[self.offset..]
+            .iter()
+            .position(|byte| *byte == CARRIAGE_RETURN)
+            .ok_or_else(|| invalid_data("Lynx directory is truncated"))?;
+        let field = &self.bytes[self.offset..self.offset + length];
+        self.offset += length + 1;
+        Ok(field)
+    }
+
+    fn next_number(&mut self) -> std::io::Result<usize> {
+        parse_number(self.next_field()?)
+    }
+}
+
+/// Parse a number as printed by BASIC, surrounded by spaces.
+fn parse_number(field: &[u8]) -> std::io::Result<usize> {
+    let digits = field
+        .iter()
+        .skip_while(|byte| **byte == b' ')
+        .take_while(|byte| byte.is_ascii_digit());
+    let mut number = None;
+    for digit in digits {
+        number = Some(number.unwrap_or(0) * 10 + (digit - b'0') as usize);
+    }
+    number.ok_or_else(|| invalid_data("invalid number in Lynx directory"))
+}
+
+/// Offset of the first byte after the BASIC program, or zero when the archive
+/// doesn't start with a BASIC program.
+fn skip_basic_program(bytes: &[u8]) -> std::io::Result<usize> {
+    if bytes.len() < 2 || u16::from_le_bytes([bytes[0], bytes[1]]) != LOAD_ADDRESS {
+        return Ok(0);
+    }
+    let mut offset = 2;
+    loop {
+        let link = bytes
+            .get(offset..offset + 2)
+            .map(|link| u16::from_le_bytes([link[0], link[1]]))
+            .ok_or_else(|| invalid_data("Lynx BASIC program is truncated"))?;
+        if link == 0 {
+            return Ok(offset + 2);
+        }
+        let next_offset = (link as usize + 2).saturating_sub(LOAD_ADDRESS as usize);
+        if next_offset <= offset {
+            return Err(invalid_data("invalid line link in Lynx BASIC program"));
+        }
+        offset = next_offset;
+    }
+}
+
+fn basic_program() -> Vec<u8> {
+    let mut program = Vec::with_capacity(BASIC_LINE.len() + 6);
+    program.extend_from_slice(&LOAD_ADDRESS.to_le_bytes());
+    let next_line = LOAD_ADDRESS + 2 + BASIC_LINE.len() as u16;
+    program.extend_from_slice(&next_line.to_le_bytes());
+    program.extend_from_slice(BASIC_LINE);
+    program.extend_from_slice(&[0, 0]);
+    program
+}
+
+fn push_field(bytes: &mut Vec<u8>, field: &[u8]) {
+    bytes.extend_from_slice(field);
+    bytes.push(CARRIAGE_RETURN);
+}
+
+fn push_number(bytes: &mut Vec<u8>, number: usize) {
+    push_field(bytes, format!(" {number} ").as_bytes());
+}
+
+impl LynxArchive {
+    /// Load a Lynx archive from file path.
+    pub fn read_from_path(filename: &Path) -> std::io::Result<LynxArchive> {
+        let mut file = File::open(filename)?;
+        LynxArchive::read_from_reader(&mut file)
+    }
+
+    /// Load a Lynx archive from a reader.
+    ///
+    /// The content of the last file may be shorter than its directory entry
+    /// claims, as some tools strip the end of the archive; it is truncated to
+    /// the available bytes.
+    pub fn read_from_reader<R: Read>(reader: &mut R) -> std::io::Result<LynxArchive> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+
+        let mut offset = skip_basic_program(&bytes)?;
+        if bytes.get(offset) == Some(&CARRIAGE_RETURN) {
+            offset += 1;
+        }
+        let mut fields = Fields {
+            bytes: &bytes,
+            offset,
+        };
+        let signature = fields.next_field()?;
+        if !signature.windows(4).any(|window| window == b"LYNX") {
+            return Err(invalid_data("not a Lynx archive"));
+        }
+        let num_directory_blocks = parse_number(signature)?;
+        let num_entries = fields.next_number()?;
+
+        let mut directory = Vec::with_capacity(num_entries);
+        for _ in 0..num_entries {
+            let name = fields.next_field()?;
+            let name_length = name
+                .iter()
+                .rposition(|byte| *byte != PETSCII_NBSP)
+                .map_or(0, |position| position + 1)
+                .min(NAME_LENGTH);
+            let num_blocks = fields.next_number()?;
+            let file_type = fields
+                .next_field()?
+                .iter()
+                .find(|byte| **byte != b' ')
+                .and_then(|letter| file_type_from_letter(*letter))
+                .ok_or_else(|| invalid_data("unknown file type in Lynx directory"))?;
+            let record_length = if file_type == FileType::Relative {
+                fields.next_number()? as u8
+            } else {
+                0
+            };
+            let last_block_usage = fields.next_number()?;
+            directory.push((
+                PetsciiString::fixed_size(&name[..name_length]),
+                file_type,
+                record_length,
+                num_blocks,
+                last_block_usage,
+            ));
+        }
+
+        let mut offset = num_directory_blocks * BLOCK_SIZE;
+        let mut entries = Vec::with_capacity(num_entries);
+        for (name, file_type, record_length, num_blocks, last_block_usage) in directory {
+            let length = match num_blocks {
+                0 => 0,
+                _ => (num_blocks - 1) * BLOCK_SIZE + last_block_usage.saturating_sub(1),
+            };
+            let start = offset.min(bytes.len());
+            let end = (offset + length).min(bytes.len());
+            entries.push(LynxEntry {
+                name,
+                file_type,
+                record_length,
+                data: bytes[start..end].to_vec(),
+            });
+            offset += num_blocks * BLOCK_SIZE;
+        }
+
+        Ok(LynxArchive { entries })
+    }
+
+    pub fn write_to_path(&self, filename: &Path) -> std::io::Result<()> {
+        let mut file = File::create(filename)?;
+        self.write_to_writer(&mut file)
+    }
+
+    /// Store the archive, including the BASIC program Lynx writes in front of
+    /// the directory.
+    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut directory = Vec::new();
+        push_number(&mut directory, self.entries.len());
+        for entry in &self.entries {
+            let letter = file_type_letter(entry.file_type).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    "file type cannot be stored in a Lynx archive",
+                )
+            })?;
+            let mut name = [PETSCII_NBSP; NAME_LENGTH];
+            let name_length = entry.name.as_slice().len().min(NAME_LENGTH);
+            name[..name_length].copy_from_slice(&entry.name.as_slice()[..name_length]);
+            push_field(&mut directory, &name);
+            push_number(&mut directory, num_blocks(entry.data.len()));
+            push_field(&mut directory, &[letter]);
+            if entry.file_type == FileType::Relative {
+                push_number(&mut directory, entry.record_length as usize);
+            }
+            push_number(&mut directory, last_block_usage(entry.data.len()));
+        }
+
+        // The size of the header includes the number of blocks it occupies.
+        let program = basic_program();
+        let mut num_directory_blocks = 1;
+        let header = loop {
+            let mut header = program.clone();
+            header.push(CARRIAGE_RETURN);
+            let mut signature = format!(" {num_directory_blocks}  ").into_bytes();
+            signature.extend_from_slice(SIGNATURE);
+            push_field(&mut header, &signature);
+            header.extend_from_slice(&directory);
+            let required_blocks = header.len().div_ceil(BLOCK_SIZE);
+            if required_blocks <= num_directory_blocks {
+                header.resize(num_directory_blocks * BLOCK_SIZE, 0);
+                break header;
+            }
+            num_directory_blocks = required_blocks;
+        };
+        writer.write_all(&header)?;
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            writer.write_all(&entry.data)?;
+            if index + 1 < self.entries.len() {
+                let padding = num_blocks(entry.data.len()) * BLOCK_SIZE - entry.data.len();
+                writer.write_all(&vec![0; padding])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Create an archive with the given files of the disk.
+    pub fn read_from_disk<L>(disk: &Disk<L>, file_entries: &[FileEntry]) -> LynxArchive
+    where
+        L: Layout<FileEntryType = FileEntry> + Default,
+    {
+        let entries = file_entries
+            .iter()
+            .map(|file_entry| LynxEntry {
+                name: trim_name(&file_entry.name),
+                file_type: file_entry.file_type,
+                record_length: file_entry.record_length,
+                data: disk.read_file(file_entry),
+            })
+            .collect();
+        LynxArchive { entries }
+    }
+
+    /// Create the files of the archive on the disk.
+    pub fn write_to_disk<L>(&self, disk: &mut Disk<L>)
+    where
+        L: Layout<FileEntryType = FileEntry> + Default,
+    {
+        for entry in &self.entries {
+            let file_entry = FileEntry {
+                name: entry.name.clone(),
+                file_type: entry.file_type,
+                record_length: entry.record_length,
+                ..FileEntry::default()
+            };
+            disk.create_file(&file_entry, &entry.data);
+        }
+    }
+}
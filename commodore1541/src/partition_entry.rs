@@ -0,0 +1,112 @@
+use d64::Sector;
+use petscii::{PetsciiString, PETSCII_NBSP};
+
+use crate::FileListEntryRef;
+
+const OFFSET_PARTITION_TYPE: usize = 2;
+const OFFSET_NAME: usize = 5;
+const NAME_LENGTH: usize = 16;
+const OFFSET_NAME_END: usize = OFFSET_NAME + NAME_LENGTH;
+const OFFSET_START_BLOCK: usize = 0x15;
+const OFFSET_NUM_BLOCKS: usize = 0x1d;
+/// Start block and number of blocks are stored as 24 bit big endian numbers.
+const BLOCK_NUMBER_SIZE: usize = 3;
+
+/// Type of a partition as stored in the partition table of CMD drives.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub enum PartitionType {
+    /// Partition table entry isn't in use.
+    #[default]
+    None = 0x00,
+    Native = 0x01,
+    Commodore1541 = 0x02,
+    Commodore1571 = 0x03,
+    Commodore1581 = 0x04,
+    Commodore1581CpM = 0x05,
+    PrintBuffer = 0x06,
+    Foreign = 0x07,
+    /// Partition containing the partition table itself.
+    System = 0xff,
+}
+
+impl From<u8> for PartitionType {
+    fn from(src: u8) -> PartitionType {
+        match src {
+            0x01 => PartitionType::Native,
+            0x02 => PartitionType::Commodore1541,
+            0x03 => PartitionType::Commodore1571,
+            0x04 => PartitionType::Commodore1581,
+            0x05 => PartitionType::Commodore1581CpM,
+            0x06 => PartitionType::PrintBuffer,
+            0x07 => PartitionType::Foreign,
+            0xff => PartitionType::System,
+            _ => PartitionType::None,
+        }
+    }
+}
+
+impl From<PartitionType> for u8 {
+    fn from(src: PartitionType) -> u8 {
+        src as u8
+    }
+}
+
+/// Single entry of the partition table of CMD drives.
+#[derive(Debug, Clone, Default)]
+pub struct PartitionEntry {
+    pub name: PetsciiString,
+    pub partition_type: PartitionType,
+    /// First block of the partition. Blocks are 512 bytes.
+    pub start_block: usize,
+    /// Number of blocks (512 bytes) of the partition.
+    pub num_blocks: usize,
+    pub partition_entry_ref: FileListEntryRef,
+}
+
+fn read_block_number(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .fold(0, |result, byte| result * 256 + *byte as usize)
+}
+
+fn write_block_number(sector: &mut Sector, offset: usize, block_number: usize) {
+    for i in 0..BLOCK_NUMBER_SIZE {
+        let shift = (BLOCK_NUMBER_SIZE - 1 - i) * 8;
+        sector.set_byte(offset + i, (block_number >> shift) as u8);
+    }
+}
+
+impl PartitionEntry {
+    pub fn from_bytes(bytes: &[u8; 32], partition_entry_ref: FileListEntryRef) -> PartitionEntry {
+        let partition_type = PartitionType::from(bytes[OFFSET_PARTITION_TYPE]);
+        let name = PetsciiString::fixed_size(&bytes[OFFSET_NAME..OFFSET_NAME_END]);
+        let start_block =
+            read_block_number(&bytes[OFFSET_START_BLOCK..OFFSET_START_BLOCK + BLOCK_NUMBER_SIZE]);
+        let num_blocks =
+            read_block_number(&bytes[OFFSET_NUM_BLOCKS..OFFSET_NUM_BLOCKS + BLOCK_NUMBER_SIZE]);
+
+        PartitionEntry {
+            name,
+            partition_type,
+            start_block,
+            num_blocks,
+            partition_entry_ref,
+        }
+    }
+
+    pub fn store(&self, sector: &mut Sector, offset: usize) {
+        sector.set_byte(
+            offset + OFFSET_PARTITION_TYPE,
+            u8::from(self.partition_type),
+        );
+        sector.fill(offset + OFFSET_NAME, offset + OFFSET_NAME_END, PETSCII_NBSP);
+        let name = self.name.as_slice();
+        sector.set_bytes(offset + OFFSET_NAME, &name[..name.len().min(NAME_LENGTH)]);
+        write_block_number(sector, offset + OFFSET_START_BLOCK, self.start_block);
+        write_block_number(sector, offset + OFFSET_NUM_BLOCKS, self.num_blocks);
+    }
+
+    pub fn scratch(&self, sector: &mut Sector, offset: usize) {
+        sector.fill(offset + 2, offset + 32, 0);
+    }
+}
@@ -0,0 +1,200 @@
+//! PC64 containers (P00, S00, U00, R00 and D00 files) contain a single file of
+//! a disk, together with its original name and record length.
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use d64::{Disk, Layout};
+use petscii::{PetsciiString, PETSCII_NBSP};
+
+use crate::{FileEntry, FileType};
+
+const SIGNATURE: &[u8; 8] = b"C64File\0";
+const HEADER_SIZE: usize = 26;
+const OFFSET_NAME: usize = 8;
+const NAME_LENGTH: usize = 16;
+const OFFSET_RECORD_LENGTH: usize = 25;
+
+/// Single file stored in a PC64 container.
+///
+/// # Example
+///
+/// ```
+/// use commodore1541::*;
+/// use d64::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.format();
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("HELLO")),
+///     file_type: FileType::Sequence,
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, b"DATA");
+///
+/// let entries = disk.list_entries();
+/// let container = Pc64File::read_from_disk(&disk, &entries[0]);
+/// assert_eq!(container.extension().unwrap(), "S00");
+///
+/// let mut bytes = Vec::new();
+/// container.write_to_writer(&mut bytes).unwrap();
+/// assert_eq!(&bytes[8..14], b"HELLO\0");
+/// let container = Pc64File::read_from_reader(&mut bytes.as_slice(), FileType::Sequence).unwrap();
+///
+/// let mut copy = Disk::<Commodore1541>::new();
+/// copy.format();
+/// container.write_to_disk(&mut copy);
+/// let entries = copy.list_entries();
+/// assert_eq!(String::from(&entries[0].name), "HELLO");
+/// assert_eq!(entries[0].file_type, FileType::Sequence);
+/// assert_eq!(copy.read_file(&entries[0]), b"DATA");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Pc64File {
+    pub name: PetsciiString,
+    pub file_type: FileType,
+    /// Length of each record of a relative file. Zero for other file types.
+    pub record_length: u8,
+    pub data: Vec<u8>,
+}
+
+/// Letter used in the extension of PC64 containers for the given file type.
+pub fn pc64_extension_letter(file_type: FileType) -> Option<char> {
+    match file_type {
+        FileType::Program => Some('P'),
+        FileType::Sequence => Some('S'),
+        FileType::User => Some('U'),
+        FileType::Relative => Some('R'),
+        FileType::Deleted => Some('D'),
+        _ => None,
+    }
+}
+
+/// File type of a PC64 container with the given extension, for example "P00".
+///
+/// # Example
+///
+/// ```
+/// use commodore1541::*;
+///
+/// assert_eq!(pc64_file_type("P00"), Some(FileType::Program));
+/// assert_eq!(pc64_file_type("r12"), Some(FileType::Relative));
+/// assert_eq!(pc64_file_type("PRG"), None);
+/// ```
+pub fn pc64_file_type(extension: &str) -> Option<FileType> {
+    let mut chars = extension.chars();
+    let file_type = match chars.next()?.to_ascii_uppercase() {
+        'P' => FileType::Program,
+        'S' => FileType::Sequence,
+        'U' => FileType::User,
+        'R' => FileType::Relative,
+        'D' => FileType::Deleted,
+        _ => return None,
+    };
+    let number = chars.as_str();
+    if number.len() == 2 && number.chars().all(|ch| ch.is_ascii_digit()) {
+        Some(file_type)
+    } else {
+        None
+    }
+}
+
+impl Pc64File {
+    /// Extension of the container with sequence number 0, for example "P00".
+    /// None when the file type can't be stored in a PC64 container.
+    pub fn extension(&self) -> Option<String> {
+        pc64_extension_letter(self.file_type).map(|letter| format!("{letter}00"))
+    }
+
+    /// Load a container from file path. The file type is taken from the
+    /// extension of the file.
+    pub fn read_from_path(filename: &Path) -> std::io::Result<Pc64File> {
+        let file_type = filename
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(pc64_file_type)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "not a PC64 file extension"))?;
+        let mut file = File::open(filename)?;
+        Pc64File::read_from_reader(&mut file, file_type)
+    }
+
+    /// Load a container from a reader. The file type is stored in the extension
+    /// of the container and therefore needs to be given.
+    pub fn read_from_reader<R: Read>(
+        reader: &mut R,
+        file_type: FileType,
+    ) -> std::io::Result<Pc64File> {
+        let mut header = [0_u8; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        if &header[0..SIGNATURE.len()] != SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "not a PC64 file"));
+        }
+        let name = &header[OFFSET_NAME..OFFSET_NAME + NAME_LENGTH];
+        let name_length = name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(NAME_LENGTH);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        Ok(Pc64File {
+            name: PetsciiString::fixed_size(&name[..name_length]),
+            file_type,
+            record_length: header[OFFSET_RECORD_LENGTH],
+            data,
+        })
+    }
+
+    /// Store the container at the given file path. Use [Pc64File::extension]
+    /// to name the file.
+    pub fn write_to_path(&self, filename: &Path) -> std::io::Result<()> {
+        let mut file = File::create(filename)?;
+        self.write_to_writer(&mut file)
+    }
+
+    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut header = [0_u8; HEADER_SIZE];
+        header[0..SIGNATURE.len()].copy_from_slice(SIGNATURE);
+        // Names on a disk are padded with shifted spaces, PC64 pads them with zeros.
+        let name = self.name.as_slice();
+        let name_length = name
+            .iter()
+            .rposition(|byte| *byte != PETSCII_NBSP)
+            .map_or(0, |position| position + 1)
+            .min(NAME_LENGTH);
+        header[OFFSET_NAME..OFFSET_NAME + name_length].copy_from_slice(&name[..name_length]);
+        header[OFFSET_RECORD_LENGTH] = self.record_length;
+        writer.write_all(&header)?;
+        writer.write_all(&self.data)
+    }
+
+    /// Create a container with the content of the given file of the disk.
+    pub fn read_from_disk<L>(disk: &Disk<L>, file_entry: &FileEntry) -> Pc64File
+    where
+        L: Layout<FileEntryType = FileEntry> + Default,
+    {
+        Pc64File {
+            name: file_entry.name.clone(),
+            file_type: file_entry.file_type,
+            record_length: file_entry.record_length,
+            data: disk.read_file(file_entry),
+        }
+    }
+
+    /// Create a file on the disk with the content of this container.
+    pub fn write_to_disk<L>(&self, disk: &mut Disk<L>)
+    where
+        L: Layout<FileEntryType = FileEntry> + Default,
+    {
+        let file_entry = FileEntry {
+            name: self.name.clone(),
+            file_type: self.file_type,
+            record_length: self.record_length,
+            ..FileEntry::default()
+        };
+        disk.create_file(&file_entry, &self.data);
+    }
+}
@@ -0,0 +1,319 @@
+//! ZipCode splits a disk into four files, named `1!name` up to `4!name`. Each
+//! file contains the sectors of a range of tracks, compressed per sector.
+//!
+//! Six-pack archives (`1!!name` up to `6!!name`), which contain GCR encoded
+//! tracks instead of sectors, are not supported.
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use d64::{Disk, GcrLayout, SectorRef, TrackNo};
+
+/// Number of files a disk is split into.
+pub const NUM_ZIPCODE_PARTS: usize = 4;
+/// First track stored in each of the files.
+const FIRST_TRACKS: [TrackNo; NUM_ZIPCODE_PARTS] = [1, 9, 17, 26];
+/// Load address of the first file, which starts with the disk ID.
+const FIRST_PART_LOAD_ADDRESS: u16 = 0x03FE;
+/// Load address of the other files.
+const LOAD_ADDRESS: u16 = 0x0400;
+const SECTOR_SIZE: usize = 256;
+
+const ENCODING_MASK: u8 = 0xC0;
+const TRACK_MASK: u8 = 0x3F;
+/// Sector stored as is.
+const ENCODING_RAW: u8 = 0x00;
+/// Sector filled with a single byte.
+const ENCODING_FILL: u8 = 0x40;
+/// Sector compressed with run length encoding.
+const ENCODING_RLE: u8 = 0x80;
+/// Minimum length of a run to be worth run length encoding.
+const MIN_RUN_LENGTH: usize = 4;
+
+/// Disk split into the files of a ZipCode archive.
+///
+/// # Example
+///
+/// ```
+/// use commodore1541::*;
+/// use d64::*;
+/// use petscii::*;
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.format();
+/// let entry = FileEntry {
+///     name: PetsciiString::from(&String::from("GAME")),
+///     ..FileEntry::default()
+/// };
+/// let content = (0..2000).map(|index| (index / 7) as u8).collect::<Vec<u8>>();
+/// disk.create_file(&entry, &content);
+///
+/// let zipcode = ZipCode::encode(&disk);
+/// assert_eq!(zipcode.parts.len(), 4);
+/// assert_eq!(&zipcode.parts[0][0..2], &[0xFE, 0x03]);
+///
+/// let mut copy = Disk::<Commodore1541>::new();
+/// zipcode.decode(&mut copy).unwrap();
+/// assert_eq!(String::from(&copy.get_name()), "NONAME");
+/// let entries = copy.list_entries();
+/// assert_eq!(copy.read_file(&entries[0]), content);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ZipCode {
+    /// Content of the files, starting with their load address.
+    pub parts: Vec<Vec<u8>>,
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Path of a file of a ZipCode archive. The number of the part (1 based) is
+/// put in front of the file name, so `games/pack` becomes `games/1!pack`.
+///
+/// # Example
+///
+/// ```
+/// use commodore1541::*;
+/// use std::path::Path;
+///
+/// assert_eq!(zipcode_part_path(Path::new("games/pack"), 3), Path::new("games/3!pack"));
+/// ```
+pub fn zipcode_part_path(filename: &Path, part_no: usize) -> PathBuf {
+    let name = filename
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().to_string());
+    filename.with_file_name(format!("{part_no}!{name}"))
+}
+
+/// Whether a six-pack archive is stored next to the path of the first file of
+/// a four-file archive (`1!!name` instead of `1!name`).
+fn is_six_pack(first_part: &Path) -> bool {
+    first_part.file_name().is_some_and(|name| {
+        let name = name.to_string_lossy();
+        first_part
+            .with_file_name(name.replacen('!', "!!", 1))
+            .exists()
+    })
+}
+
+/// Order in which ZipCode stores the sectors of a track: 0, 11, 1, 12, 2, ...
+/// for a track with 21 sectors.
+fn sector_order(num_sectors: u8) -> impl Iterator<Item = u8> {
+    let half = num_sectors.div_ceil(2);
+    (0..num_sectors).map(move |index| {
+        if index % 2 == 0 {
+            index / 2
+        } else {
+            half + index / 2
+        }
+    })
+}
+
+/// Encode the content of a sector, returning the encoding and the bytes
+/// following the track and sector number.
+fn encode_sector(bytes: &[u8]) -> (u8, Vec<u8>) {
+    if bytes.iter().all(|byte| *byte == bytes[0]) {
+        return (ENCODING_FILL, vec![bytes[0]]);
+    }
+
+    // The escape byte must not occur in the sector.
+    let mut used = [false; 256];
+    for byte in bytes {
+        used[*byte as usize] = true;
+    }
+    if let Some(escape) = used.iter().position(|used| !used) {
+        let escape = escape as u8;
+        let mut compressed = Vec::with_capacity(bytes.len());
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let byte = bytes[offset];
+            let run_length = bytes[offset..]
+                .iter()
+                .take(u8::MAX as usize)
+                .take_while(|other| **other == byte)
+                .count();
+            if run_length >= MIN_RUN_LENGTH {
+                compressed.extend_from_slice(&[escape, run_length as u8, byte]);
+                offset += run_length;
+            } else {
+                compressed.push(byte);
+                offset += 1;
+            }
+        }
+        if compressed.len() + 2 < bytes.len() {
+            let mut result = Vec::with_capacity(compressed.len() + 2);
+            result.push(compressed.len() as u8);
+            result.push(escape);
+            result.extend_from_slice(&compressed);
+            return (ENCODING_RLE, result);
+        }
+    }
+
+    (ENCODING_RAW, bytes.to_vec())
+}
+
+/// Decode the sector at the start of the given bytes. Returns the sector,
+/// its content and the number of bytes consumed.
+fn decode_sector(bytes: &[u8]) -> std::io::Result<(SectorRef, Vec<u8>, usize)> {
+    let truncated = || invalid_data("ZipCode file is truncated");
+    if bytes.len() < 2 {
+        return Err(truncated());
+    }
+    let sector_ref = (bytes[0] & TRACK_MASK, bytes[1]);
+    match bytes[0] & ENCODING_MASK {
+        ENCODING_RAW => {
+            let content = bytes.get(2..2 + SECTOR_SIZE).ok_or_else(truncated)?;
+            Ok((sector_ref, content.to_vec(), 2 + SECTOR_SIZE))
+        }
+        ENCODING_FILL => {
+            let fill = *bytes.get(2).ok_or_else(truncated)?;
+            Ok((sector_ref, vec![fill; SECTOR_SIZE], 3))
+        }
+        ENCODING_RLE => {
+            let length = *bytes.get(2).ok_or_else(truncated)? as usize;
+            let escape = *bytes.get(3).ok_or_else(truncated)?;
+            let compressed = bytes.get(4..4 + length).ok_or_else(truncated)?;
+            let mut content = Vec::with_capacity(SECTOR_SIZE);
+            let mut offset = 0;
+            while offset < compressed.len() {
+                if compressed[offset] == escape {
+                    let run = compressed
+                        .get(offset + 1..offset + 3)
+                        .ok_or_else(truncated)?;
+                    content.extend(std::iter::repeat_n(run[1], run[0] as usize));
+                    offset += 3;
+                } else {
+                    content.push(compressed[offset]);
+                    offset += 1;
+                }
+            }
+            if content.len() != SECTOR_SIZE {
+                return Err(invalid_data("ZipCode sector has an invalid length"));
+            }
+            Ok((sector_ref, content, 4 + length))
+        }
+        _ => Err(invalid_data("unknown ZipCode sector encoding")),
+    }
+}
+
+impl ZipCode {
+    /// Split the disk into the files of a ZipCode archive. Tracks beyond track
+    /// 35 are stored in the last file.
+    pub fn encode<L>(disk: &Disk<L>) -> ZipCode
+    where
+        L: GcrLayout + Default,
+    {
+        let layout = disk.layout();
+        let disk_id = layout.get_disk_id(disk);
+        let mut parts = Vec::with_capacity(NUM_ZIPCODE_PARTS);
+        for (part_index, first_track) in FIRST_TRACKS.iter().enumerate() {
+            let last_track = match FIRST_TRACKS.get(part_index + 1) {
+                Some(next_track) => next_track - 1,
+                None => disk.num_tracks(),
+            };
+
+            let mut part = Vec::new();
+            if part_index == 0 {
+                part.extend_from_slice(&FIRST_PART_LOAD_ADDRESS.to_le_bytes());
+                part.extend_from_slice(&disk_id);
+            } else {
+                part.extend_from_slice(&LOAD_ADDRESS.to_le_bytes());
+            }
+            for track_no in *first_track..=last_track.min(disk.num_tracks()) {
+                for sector_no in sector_order(layout.num_sectors(track_no) as u8) {
+                    let sector = disk.get_sector((track_no, sector_no));
+                    let (encoding, bytes) = encode_sector(sector.as_slice());
+                    part.push(encoding | track_no);
+                    part.push(sector_no);
+                    part.extend_from_slice(&bytes);
+                }
+            }
+            parts.push(part);
+        }
+        ZipCode { parts }
+    }
+
+    /// Store the sectors of the archive on the disk.
+    ///
+    /// Sectors that are missing from the archive are left untouched.
+    pub fn decode<L>(&self, disk: &mut Disk<L>) -> std::io::Result<()>
+    where
+        L: GcrLayout + Default,
+    {
+        if self.parts.len() != NUM_ZIPCODE_PARTS {
+            return Err(invalid_data("a ZipCode archive consists of four files"));
+        }
+        disk.clear_raw_tracks();
+        disk.clear_sector_errors();
+        for (part_index, part) in self.parts.iter().enumerate() {
+            let (load_address, mut offset) = match part_index {
+                0 => (FIRST_PART_LOAD_ADDRESS, 4),
+                _ => (LOAD_ADDRESS, 2),
+            };
+            if part.len() < offset || u16::from_le_bytes([part[0], part[1]]) != load_address {
+                return Err(invalid_data("not a ZipCode file"));
+            }
+            while offset < part.len() {
+                let ((track_no, sector_no), content, length) = decode_sector(&part[offset..])?;
+                if track_no == 0
+                    || track_no > disk.num_tracks()
+                    || sector_no as u16 >= disk.layout().num_sectors(track_no)
+                {
+                    return Err(invalid_data("ZipCode sector is outside the disk"));
+                }
+                disk.get_sector_mut((track_no, sector_no))
+                    .set_bytes(0, &content);
+                offset += length;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load the four files of a ZipCode archive. The file name is given without
+    /// the number in front, see [zipcode_part_path].
+    ///
+    /// Six-pack archives are refused with [ErrorKind::Unsupported].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use commodore1541::*;
+    /// use std::io::ErrorKind;
+    ///
+    /// let filename = std::env::temp_dir().join("zipcode-sixpack");
+    /// std::fs::write(std::env::temp_dir().join("1!!zipcode-sixpack"), [0x00, 0x04]).unwrap();
+    ///
+    /// let error = ZipCode::read_from_path(&filename).unwrap_err();
+    /// assert_eq!(error.kind(), ErrorKind::Unsupported);
+    /// ```
+    pub fn read_from_path(filename: &Path) -> std::io::Result<ZipCode> {
+        let first_part = zipcode_part_path(filename, 1);
+        if !first_part.exists() && is_six_pack(&first_part) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "six-pack archives are not supported",
+            ));
+        }
+        let mut parts = Vec::with_capacity(NUM_ZIPCODE_PARTS);
+        for part_no in 1..=NUM_ZIPCODE_PARTS {
+            let mut file = File::open(zipcode_part_path(filename, part_no))?;
+            let mut part = Vec::new();
+            file.read_to_end(&mut part)?;
+            parts.push(part);
+        }
+        Ok(ZipCode { parts })
+    }
+
+    /// Store the four files of a ZipCode archive. The file name is given without
+    /// the number in front, see [zipcode_part_path].
+    pub fn write_to_path(&self, filename: &Path) -> std::io::Result<()> {
+        for (part_index, part) in self.parts.iter().enumerate() {
+            let mut file = File::create(zipcode_part_path(filename, part_index + 1))?;
+            file.write_all(part)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,200 @@
+//! Layout with a geometry that is defined at runtime.
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+use crate::{Geometry, TrackNo};
+
+/// Largest number of sectors of a track, limited by [crate::SectorNo].
+const MAX_SECTORS_PER_TRACK: usize = 256;
+/// Largest number of tracks, limited by [TrackNo].
+const MAX_TRACKS: usize = 255;
+
+fn invalid_data(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Geometry of a disk whose number of tracks, sectors per track and sector
+/// size are given at runtime, for example to research non-standard drives or
+/// protected disks.
+///
+/// The geometry has no file system. Use [crate::Disk::get_sector] and
+/// [crate::Disk::get_sector_mut] to access the content.
+///
+/// The geometry can be parsed from a text description. Each line contains a
+/// track or a range of tracks followed by their number of sectors, or
+/// `bytes_per_sector` followed by the size of a sector (256 when omitted).
+/// Tracks must be described in order, starting at track 1. Text after `#` is
+/// ignored.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+///
+/// let geometry: CustomGeometry = "
+///     bytes_per_sector 512 # three tracks with large sectors
+///     1-2 10
+///     3 8
+/// "
+/// .parse()
+/// .unwrap();
+/// assert_eq!(geometry.num_tracks(), 3);
+/// assert_eq!(geometry.num_sectors(3), 8);
+/// assert_eq!(geometry.to_string(), "bytes_per_sector 512\n1-2 10\n3 8\n");
+/// assert!("2-4 10".parse::<CustomGeometry>().is_err());
+///
+/// let mut disk = Disk::with_layout(geometry.clone());
+/// assert_eq!(disk.as_bytes().len(), 28 * 512);
+/// disk.get_sector_mut((3, 7)).set_byte(511, 42);
+///
+/// let bytes = disk.into_bytes();
+/// let disk = Disk::from_bytes_with_layout(geometry, bytes).unwrap();
+/// assert_eq!(*disk.get_sector((3, 7)).get_byte(511), 42);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomGeometry {
+    bytes_per_sector: u16,
+    /// Number of sectors of each track, starting with track 1.
+    sectors_per_track: Vec<u16>,
+}
+
+impl Default for CustomGeometry {
+    /// Geometry without any tracks.
+    fn default() -> Self {
+        CustomGeometry {
+            bytes_per_sector: 256,
+            sectors_per_track: Vec::new(),
+        }
+    }
+}
+
+impl CustomGeometry {
+    /// Create a geometry with the given sector size and number of sectors of
+    /// each track, starting with track 1.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    ///
+    /// let geometry = CustomGeometry::new(256, vec![21; 42]).unwrap();
+    /// assert_eq!(geometry.num_tracks(), 42);
+    /// assert!(CustomGeometry::new(0, vec![21]).is_err());
+    /// assert!(CustomGeometry::new(256, vec![300]).is_err());
+    /// ```
+    pub fn new(bytes_per_sector: u16, sectors_per_track: Vec<u16>) -> std::io::Result<Self> {
+        if bytes_per_sector == 0 {
+            return Err(invalid_data(String::from("sectors can't be empty")));
+        }
+        if sectors_per_track.len() > MAX_TRACKS {
+            return Err(invalid_data(format!(
+                "a disk can't have more than {MAX_TRACKS} tracks"
+            )));
+        }
+        if let Some(index) = sectors_per_track
+            .iter()
+            .position(|num_sectors| *num_sectors as usize > MAX_SECTORS_PER_TRACK)
+        {
+            return Err(invalid_data(format!(
+                "track {} has more than {MAX_SECTORS_PER_TRACK} sectors",
+                index + 1
+            )));
+        }
+        Ok(CustomGeometry {
+            bytes_per_sector,
+            sectors_per_track,
+        })
+    }
+}
+
+/// Parse a track or a range of tracks, like `18` or `1-17`.
+fn parse_tracks(text: &str) -> Option<(usize, usize)> {
+    match text.split_once('-') {
+        Some((first, last)) => Some((first.parse().ok()?, last.parse().ok()?)),
+        None => {
+            let track_no = text.parse().ok()?;
+            Some((track_no, track_no))
+        }
+    }
+}
+
+impl FromStr for CustomGeometry {
+    type Err = Error;
+
+    fn from_str(description: &str) -> std::io::Result<Self> {
+        let mut bytes_per_sector = 256;
+        let mut sectors_per_track = Vec::new();
+        for (line_index, line) in description.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let invalid_line =
+                || invalid_data(format!("invalid geometry on line {}", line_index + 1));
+            match words.as_slice() {
+                [] => {}
+                ["bytes_per_sector", size] => {
+                    bytes_per_sector = size.parse().map_err(|_| invalid_line())?;
+                }
+                [tracks, num_sectors] => {
+                    let (first, last) = parse_tracks(tracks).ok_or_else(invalid_line)?;
+                    let num_sectors = num_sectors.parse().map_err(|_| invalid_line())?;
+                    if first != sectors_per_track.len() + 1 || last < first {
+                        return Err(invalid_data(format!(
+                            "tracks on line {} don't follow track {}",
+                            line_index + 1,
+                            sectors_per_track.len()
+                        )));
+                    }
+                    sectors_per_track.resize(last, num_sectors);
+                }
+                _ => return Err(invalid_line()),
+            }
+        }
+        CustomGeometry::new(bytes_per_sector, sectors_per_track)
+    }
+}
+
+impl fmt::Display for CustomGeometry {
+    /// Write the description of the geometry, in the format accepted by
+    /// [CustomGeometry::from_str].
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "bytes_per_sector {}", self.bytes_per_sector)?;
+        let mut first = 0;
+        while first < self.sectors_per_track.len() {
+            let num_sectors = self.sectors_per_track[first];
+            let last = first
+                + self.sectors_per_track[first..]
+                    .iter()
+                    .take_while(|track_sectors| **track_sectors == num_sectors)
+                    .count()
+                - 1;
+            if first == last {
+                writeln!(f, "{} {num_sectors}", first + 1)?;
+            } else {
+                writeln!(f, "{}-{} {num_sectors}", first + 1, last + 1)?;
+            }
+            first = last + 1;
+        }
+        Ok(())
+    }
+}
+
+impl Geometry for CustomGeometry {
+    fn num_tracks(&self) -> u8 {
+        self.sectors_per_track.len() as u8
+    }
+
+    fn num_sectors(&self, track: TrackNo) -> u16 {
+        (track as usize)
+            .checked_sub(1)
+            .and_then(|index| self.sectors_per_track.get(index))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn bytes_per_sector(&self) -> u16 {
+        self.bytes_per_sector
+    }
+}
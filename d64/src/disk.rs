@@ -3,26 +3,53 @@
 //! Use [Disk] as the main API entry point.
 use std::{
     fs::File,
-    io::{Read, Write},
-    marker::PhantomData,
+    io::{Error, ErrorKind, Read, Write},
     path::Path,
+    sync::Arc,
 };
 
-use crate::{layout::Layout, Sector, SectorRef, Track, TrackNo};
+use crate::{
+    gzip,
+    layout::{DirectoryLayout, Geometry, Layout},
+    raw_track::RawTracks,
+    save::{FileStamp, ImageFile},
+    sector_change::OriginalSectors,
+    sector_data::{Device, LazySectors, SectorData},
+    sector_error::SectorErrors,
+    Sector, SectorError, SectorRef, ShortRead, Track, TrackMut, TrackNo,
+};
 
 /// Disk provides a API way how tracks and sectors are logically layed out.
 #[derive(Default)]
 pub struct Disk<L>
 where
-    L: Layout,
+    L: Geometry,
 {
-    layout: PhantomData<L>,
-    tracks: Vec<Track>,
+    /// Layout instance supplied when the disk was created. Shared, so it can be
+    /// passed to the layout operations together with the disk itself.
+    layout: Arc<L>,
+    /// Content of all sectors, stored track after track.
+    pub(crate) data: SectorData,
+    /// Offset of each track inside data, followed by the size of data.
+    track_offsets: Vec<usize>,
+    bytes_per_sector: usize,
+    /// Header sector of the current directory. None refers to the root
+    /// directory. Only used by layouts that implement [DirectoryLayout].
+    current_directory: Option<SectorRef>,
+    /// Raw tracks that are preserved next to the sectors. See [crate::RawTrack].
+    pub(crate) raw_tracks: RawTracks,
+    /// Errors found when the sectors were read from the physical media.
+    sector_errors: SectorErrors,
+    /// Content of the sectors that were borrowed for modification since the
+    /// disk was loaded or saved, as they were before the first modification.
+    pub(crate) original_sectors: OriginalSectors,
+    /// File the disk was loaded from or saved to.
+    pub(crate) file_stamp: Option<FileStamp>,
 }
 
 impl<L> Disk<L>
 where
-    L: Layout + Sized + Default,
+    L: Geometry + Sized + Default,
 {
     /// Create a new instance of a disk.
     ///
@@ -39,26 +66,256 @@ where
     /// let _disk = Disk::<Commodore1541>::new();
     /// ```
     pub fn new() -> Self {
-        let mut disk = Disk::<L>::default();
-        disk.initialize_layout();
-        disk
+        Disk::with_layout(L::default())
     }
 
-    fn initialize_layout(&mut self) {
-        self.tracks.clear();
-        let layout = L::default();
+    /// Create a new instance of a disk that uses the given layout instance,
+    /// for layouts that can be configured.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    ///
+    /// let mut disk = Disk::with_layout(Commodore1541::with_interleave(10));
+    /// disk.format();
+    /// let entry = FileEntry {
+    ///     name: PetsciiString::from(&String::from("SPREAD")),
+    ///     file_type: FileType::Program,
+    ///     ..FileEntry::default()
+    /// };
+    /// disk.create_file(&entry, &[1; 3 * 254]);
+    /// assert_eq!(disk.layout().interleave(), 10);
+    ///
+    /// let entries = disk.list_entries();
+    /// assert_eq!(entries[0].start_sector, (1, 0));
+    /// assert_eq!(&disk.get_sector((1, 0)).as_slice()[..2], &[1, 10]);
+    /// assert_eq!(&disk.get_sector((1, 10)).as_slice()[..2], &[1, 20]);
+    /// ```
+    pub fn with_layout(layout: L) -> Self {
         let num_tracks = layout.num_tracks();
-        let bytes_per_sector = layout.bytes_per_sector();
-        for track_no in 1..=num_tracks {
-            let mut track = Track::default();
-            let num_sectors = layout.num_sectors(track_no);
-            track.initialize(num_sectors, bytes_per_sector);
-            self.tracks.push(track);
+        let mut disk = Disk {
+            layout: Arc::new(layout),
+            ..Disk::default()
+        };
+        disk.initialize_layout(num_tracks);
+        disk
+    }
+
+    /// Layout instance used by this disk.
+    pub fn layout(&self) -> &L {
+        &self.layout
+    }
+
+    /// Shared handle to the layout, to call layout operations that need the
+    /// disk as well.
+    pub(crate) fn shared_layout(&self) -> Arc<L> {
+        Arc::clone(&self.layout)
+    }
+
+    /// Create a new instance of a disk that uses the given layout instance, with
+    /// a different number of tracks than the layout creates by default.
+    ///
+    /// Only useful for layouts that can be sized, like CMD native partitions.
+    /// Layouts with a fixed geometry should use [Disk::new] or
+    /// [Disk::with_layout].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let disk = Disk::with_num_tracks(CmdNative::default(), 4);
+    /// assert_eq!(disk.num_tracks(), 4);
+    /// ```
+    pub fn with_num_tracks(layout: L, num_tracks: TrackNo) -> Self {
+        let mut disk = Disk {
+            layout: Arc::new(layout),
+            ..Disk::default()
+        };
+        disk.initialize_layout(num_tracks);
+        disk
+    }
+
+    fn initialize_layout(&mut self, num_tracks: TrackNo) {
+        self.data = SectorData::default();
+        self.resize_tracks(num_tracks);
+    }
+
+    /// Add or remove tracks at the end of the disk. Existing tracks are kept.
+    pub(crate) fn resize_tracks(&mut self, num_tracks: TrackNo) {
+        self.bytes_per_sector = self.layout.bytes_per_sector() as usize;
+        self.track_offsets = track_offsets(self.layout(), num_tracks);
+        self.data
+            .vec_mut()
+            .resize(self.track_offsets[num_tracks as usize], 0);
+        self.original_sectors.clear();
+    }
+
+    /// Create a disk with the given number of tracks, whose sectors are read
+    /// from the device at the given offset when they are accessed for the
+    /// first time. See [crate::LazyDisk].
+    pub(crate) fn lazy(
+        layout: Arc<L>,
+        num_tracks: TrackNo,
+        device: Box<dyn Device>,
+        base_offset: u64,
+    ) -> Self {
+        let mut disk = Disk {
+            bytes_per_sector: layout.bytes_per_sector() as usize,
+            track_offsets: track_offsets(layout.as_ref(), num_tracks),
+            layout,
+            ..Disk::default()
+        };
+        disk.data = SectorData::Lazy(LazySectors::new(
+            device,
+            base_offset,
+            disk.all_sector_refs(),
+            disk.bytes_per_sector,
+        ));
+        disk
+    }
+
+    /// Create a disk that uses the given image as its content, without copying
+    /// the sectors.
+    ///
+    /// An error table after the last track, one byte per sector like in D64
+    /// files, is loaded into the sector errors (see [Disk::get_sector_error]).
+    /// Other bytes after the last track are ignored.
+    ///
+    /// The disk always owns its content. Borrowed or memory-mapped images
+    /// aren't supported; use [crate::LazyDisk] to access an image without
+    /// loading it.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let bytes = std::fs::read("../disks/1541-empty.d64").unwrap();
+    /// let disk = Disk::<Commodore1541>::from_bytes(bytes).unwrap();
+    /// assert_eq!(disk.num_tracks(), 35);
+    /// assert_eq!(disk.as_bytes().len(), 174848);
+    /// assert_eq!(disk.get_sector((18, 0)).as_slice(), &disk.as_bytes()[0x16500..0x16600]);
+    ///
+    /// let mut bytes = disk.into_bytes();
+    /// bytes.resize(174848 + 683, u8::from(SectorError::Ok));
+    /// bytes[174848 + 682] = u8::from(SectorError::DataChecksum);
+    /// let disk = Disk::<Commodore1541>::from_bytes(bytes).unwrap();
+    /// assert_eq!(disk.as_bytes().len(), 174848);
+    /// assert_eq!(disk.get_sector_error((35, 16)), SectorError::DataChecksum);
+    /// assert_eq!(disk.sector_errors().count(), 1);
+    /// ```
+    ///
+    /// Layouts that store their size inside the image only use the tracks of
+    /// that size, the image buffer itself is kept as is.
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::with_num_tracks(CmdNative::default(), 3);
+    /// disk.format();
+    /// let bytes = disk.as_bytes().to_vec();
+    /// let address = bytes.as_ptr();
+    ///
+    /// let disk = Disk::<CmdNative>::from_bytes(bytes).unwrap();
+    /// assert_eq!(disk.num_tracks(), 3);
+    /// assert_eq!(disk.as_bytes().as_ptr(), address);
+    /// ```
+    pub fn from_bytes(bytes: Vec<u8>) -> std::io::Result<Self> {
+        Disk::from_bytes_with_layout(L::default(), bytes)
+    }
+
+    /// Create a disk that uses the given layout instance and image as its
+    /// content. See [Disk::from_bytes].
+    pub fn from_bytes_with_layout(layout: L, bytes: Vec<u8>) -> std::io::Result<Self> {
+        Disk::from_bytes_with_shared_layout(Arc::new(layout), bytes)
+    }
+
+    /// Create a disk from an image file that has already been read. The disk
+    /// remembers the file it was loaded from, like [Disk::read_from_path].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use std::path::*;
+    ///
+    /// let image = ImageFile::read(Path::new("../disks/1541-empty.d64")).unwrap();
+    /// assert_eq!(image.as_bytes().len(), 174848);
+    /// let disk = Disk::<Commodore1541>::from_image_file(image).unwrap();
+    /// assert_eq!(disk.num_tracks(), 35);
+    /// ```
+    pub fn from_image_file(image: ImageFile) -> std::io::Result<Self> {
+        Disk::from_image_file_with_layout(L::default(), image)
+    }
+
+    /// Create a disk that uses the given layout instance and image file as its
+    /// content. See [Disk::from_image_file].
+    pub fn from_image_file_with_layout(layout: L, image: ImageFile) -> std::io::Result<Self> {
+        let (file_stamp, bytes) = image.into_parts();
+        let mut disk = Disk::from_bytes_with_layout(layout, bytes)?;
+        disk.file_stamp = Some(file_stamp);
+        Ok(disk)
+    }
+
+    pub(crate) fn from_bytes_with_shared_layout(
+        layout: Arc<L>,
+        bytes: Vec<u8>,
+    ) -> std::io::Result<Self> {
+        let mut disk = Disk {
+            bytes_per_sector: layout.bytes_per_sector() as usize,
+            track_offsets: track_offsets(layout.as_ref(), layout.num_tracks()),
+            layout,
+            data: SectorData::Loaded(bytes),
+            ..Disk::default()
+        };
+        if disk.num_tracks() > 0 && disk.data.len() >= disk.track_offsets[1] {
+            if let Some(num_tracks) = disk.layout.read_num_tracks(&disk.get_track(1)) {
+                disk.track_offsets = track_offsets(disk.layout(), num_tracks);
+            }
+        }
+        let size = disk.track_offsets[disk.num_tracks() as usize];
+        if disk.data.len() < size {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                disk.short_reads(disk.data.len())[0],
+            ));
         }
+        let error_table = disk.data.vec_mut().split_off(size);
+        disk.read_error_table(&error_table);
+        Ok(disk)
+    }
+
+    /// Content of all sectors of the disk, in the order they are stored in a
+    /// D64 file.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    /// Content of all sectors of the disk for modification.
+    ///
+    /// All sectors are considered to be modified, see [Disk::changes].
+    pub fn as_mut_bytes(&mut self) -> &mut [u8] {
+        self.remember_original_range(0..self.data.len());
+        self.data.as_mut_slice()
+    }
+
+    /// Consume the disk, returning the content of all sectors.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data.into_vec()
     }
 
     /// Load a disk image from file path.
     ///
+    /// Gzip compressed images (like `.d64.gz`) are decompressed transparently.
+    ///
     /// # Example
     ///
     /// ```
@@ -71,13 +328,39 @@ where
     /// disk.read_from_path(&path).unwrap();
     /// ```
     pub fn read_from_path(&mut self, filename: &Path) -> std::io::Result<()> {
-        let mut file = File::open(filename)?;
-        self.read_from_reader(&mut file)?;
+        let (file_stamp, bytes) = ImageFile::read(filename)?.into_parts();
+        let mut reader = bytes.as_slice();
+        self.read_from_reader(&mut reader)?;
+        self.read_error_table(reader);
+        self.file_stamp = Some(file_stamp);
         Ok(())
     }
 
+    /// Load the errors of the sectors from an error table stored after the
+    /// sectors. Tables that don't have one byte for each sector are ignored.
+    fn read_error_table(&mut self, error_table: &[u8]) {
+        let sector_refs = self.all_sector_refs();
+        if error_table.len() == sector_refs.len() {
+            for (sector_ref, error) in sector_refs.into_iter().zip(error_table) {
+                self.set_sector_error(sector_ref, SectorError::from(*error));
+            }
+        }
+    }
+
+    /// Error table of the disk, one byte for each sector.
+    pub(crate) fn error_table(&self) -> Vec<u8> {
+        self.all_sector_refs()
+            .into_iter()
+            .map(|sector_ref| u8::from(self.get_sector_error(sector_ref)))
+            .collect()
+    }
+
     /// Load a disk image from a reader.
     ///
+    /// When the layout stores its size inside the image (see
+    /// [Geometry::read_num_tracks]) the number of tracks of the disk is adjusted
+    /// after the first track has been read.
+    ///
     /// # Example
     ///
     /// ```
@@ -92,22 +375,158 @@ where
     /// disk.read_from_reader(&mut file).unwrap();
     /// ```
     pub fn read_from_reader<R: Read>(&mut self, reader: &mut R) -> std::io::Result<()> {
-        for track in &mut self.tracks {
-            track.read_from_reader(reader)?;
+        let num_bytes_read = self.read_image(reader)?;
+        if num_bytes_read < self.data.len() {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                self.short_reads(num_bytes_read)[0],
+            ));
         }
         Ok(())
     }
 
+    /// Load a disk image from a reader, recovering images that are truncated.
+    ///
+    /// Sectors that are missing from the image are filled with zeros. Returns
+    /// the sectors that could not be read completely.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// let bytes = disk.as_bytes()[..174848 - 300].to_vec();
+    ///
+    /// let mut recovered = Disk::<Commodore1541>::new();
+    /// let warnings = recovered.read_truncated_from_reader(&mut bytes.as_slice()).unwrap();
+    /// assert_eq!(warnings.len(), 2);
+    /// assert_eq!(warnings[0].sector_ref, (35, 15));
+    /// assert_eq!(warnings[0].bytes_read, 212);
+    /// assert_eq!(warnings[1].sector_ref, (35, 16));
+    /// assert_eq!(warnings[1].bytes_read, 0);
+    /// assert_eq!(String::from(&recovered.get_name()), "NONAME");
+    /// ```
+    pub fn read_truncated_from_reader<R: Read>(
+        &mut self,
+        reader: &mut R,
+    ) -> std::io::Result<Vec<ShortRead>> {
+        let num_bytes_read = self.read_image(reader)?;
+        Ok(self.short_reads(num_bytes_read))
+    }
+
+    /// Read the sectors of the image. Returns the number of bytes that could be
+    /// read; the remaining bytes are filled with zeros.
+    fn read_image<R: Read>(&mut self, reader: &mut R) -> std::io::Result<usize> {
+        self.current_directory = None;
+        self.raw_tracks.clear();
+        self.sector_errors.clear();
+        self.original_sectors.clear();
+        if self.num_tracks() == 0 {
+            return Ok(0);
+        }
+        let first_track_size = self.track_offsets[1];
+        let mut num_bytes_read = read_fully(reader, &mut self.data.vec_mut()[..first_track_size])?;
+        if num_bytes_read == first_track_size {
+            if let Some(num_tracks) = self.layout.read_num_tracks(&self.get_track(1)) {
+                self.resize_tracks(num_tracks);
+            }
+        }
+        let data = self.data.vec_mut();
+        num_bytes_read += read_fully(reader, &mut data[num_bytes_read..])?;
+        data[num_bytes_read..].fill(0);
+        Ok(num_bytes_read)
+    }
+
+    /// Sectors that are not complete when only the given number of bytes of
+    /// the image could be read.
+    fn short_reads(&self, num_bytes_read: usize) -> Vec<ShortRead> {
+        self.all_sector_refs()
+            .into_iter()
+            .filter_map(|sector_ref| {
+                let offset = self.sector_offset(sector_ref);
+                let bytes_read = num_bytes_read.saturating_sub(offset);
+                (bytes_read < self.bytes_per_sector).then_some(ShortRead {
+                    sector_ref,
+                    bytes_read,
+                    bytes_per_sector: self.bytes_per_sector,
+                })
+            })
+            .collect()
+    }
+
+    /// Store the disk image at the given file path.
+    ///
+    /// The error table is stored after the sectors when any sector has an
+    /// error. The image is gzip compressed when the file name ends with
+    /// `.gz`. See
+    /// [Disk::write_changes_to_path] to only write the modified sectors.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// let path = std::env::temp_dir().join("d64-write-to-path.d64.gz");
+    /// disk.write_to_path(&path).unwrap();
+    /// assert!(std::fs::metadata(&path).unwrap().len() < 4096);
+    ///
+    /// let mut loaded = Disk::<Commodore1541>::new();
+    /// loaded.read_from_path(&path).unwrap();
+    /// assert_eq!(loaded.num_unused_sectors(), 664);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
     pub fn write_to_path(&mut self, filename: &Path) -> std::io::Result<()> {
+        let bytes = self.file_content(filename);
         let mut file = File::create(filename)?;
-        self.write_to_writer(&mut file)?;
+        file.write_all(&bytes)?;
+        self.file_stamp = Some(FileStamp::new(filename, &bytes));
+        self.original_sectors.clear();
         Ok(())
     }
+
+    /// Content of the file when the disk is stored at the given file path.
+    pub(crate) fn file_content(&self, filename: &Path) -> Vec<u8> {
+        let mut content = self.as_bytes().to_vec();
+        if !self.sector_errors.is_empty() {
+            content.extend(self.error_table());
+        }
+        if filename
+            .extension()
+            .is_some_and(|extension| extension == "gz")
+        {
+            gzip::compress(&content)
+        } else {
+            content
+        }
+    }
+
+    /// Store the sectors of the disk, without the error table.
     pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        for track in &self.tracks {
-            track.write_to_writer(writer)?;
+        writer.write_all(self.as_bytes())
+    }
+
+    /// Number of tracks of this disk.
+    pub fn num_tracks(&self) -> TrackNo {
+        self.track_offsets.len().saturating_sub(1) as TrackNo
+    }
+
+    /// References to all sectors of this disk, in the order they are stored in
+    /// the image.
+    pub(crate) fn all_sector_refs(&self) -> Vec<SectorRef> {
+        let mut result = Vec::new();
+        for track_no in 1..=self.num_tracks() {
+            let track = self.track_range(track_no);
+            for sector_no in 0..(track.end - track.start) / self.bytes_per_sector {
+                result.push((track_no, sector_no as u8));
+            }
         }
-        Ok(())
+        result
     }
 
     /// Get a specific sector of this disk.
@@ -122,12 +541,47 @@ where
     /// let sector = disk.get_sector((18, 0));
     /// ```
     pub fn get_sector(&self, sector_ref: SectorRef) -> &Sector {
-        self.get_track(sector_ref.0).get_sector(sector_ref.1)
+        let offset = self.sector_offset(sector_ref);
+        Sector::from_slice(self.data.get(offset..offset + self.bytes_per_sector))
+    }
+
+    /// Remember the content of the sector before it is modified for the first
+    /// time since the disk was loaded or saved.
+    pub(crate) fn remember_original(&mut self, sector_ref: SectorRef) {
+        let offset = self.sector_offset(sector_ref);
+        self.remember_original_range(offset..offset + self.bytes_per_sector);
     }
 
-    fn get_track(&self, track_no: TrackNo) -> &Track {
+    /// Remember the content of all sectors of the given range of the image.
+    pub(crate) fn remember_original_range(&mut self, range: std::ops::Range<usize>) {
+        let first_sector_index = range.start / self.bytes_per_sector;
+        self.original_sectors.remember(
+            self.data.get(range),
+            self.bytes_per_sector,
+            first_sector_index,
+        );
+    }
+
+    /// Offset of the given sector inside [Disk::as_bytes].
+    pub(crate) fn sector_offset(&self, sector_ref: SectorRef) -> usize {
+        let track = self.track_range(sector_ref.0);
+        let offset = track.start + sector_ref.1 as usize * self.bytes_per_sector;
+        assert!(
+            offset < track.end,
+            "sector {sector_ref:?} is outside the disk"
+        );
+        offset
+    }
+
+    pub(crate) fn track_range(&self, track_no: TrackNo) -> std::ops::Range<usize> {
         let index = (track_no - 1) as usize;
-        &self.tracks[index]
+        self.track_offsets[index]..self.track_offsets[index + 1]
+    }
+
+    /// Get a specific track of this disk.
+    pub fn get_track(&self, track_no: TrackNo) -> Track<'_> {
+        let range = self.track_range(track_no);
+        Track::from_slice(self.data.get(range), self.bytes_per_sector)
     }
     /// Get a specific sector for modification of this disk.
     ///
@@ -141,15 +595,65 @@ where
     /// let sector = disk.get_sector_mut((18, 0));
     /// ```
     pub fn get_sector_mut(&mut self, sector_ref: SectorRef) -> &mut Sector {
-        self.get_track_mut(sector_ref.0)
-            .get_sector_mut(sector_ref.1)
+        self.remember_original(sector_ref);
+        let offset = self.sector_offset(sector_ref);
+        Sector::from_slice_mut(self.data.get_mut(offset..offset + self.bytes_per_sector))
     }
 
-    fn get_track_mut(&mut self, track_no: TrackNo) -> &mut Track {
-        let index = (track_no - 1) as usize;
-        &mut self.tracks[index]
+    /// Get a specific track of this disk for modification.
+    pub fn get_track_mut(&mut self, track_no: TrackNo) -> TrackMut<'_> {
+        let range = self.track_range(track_no);
+        self.remember_original_range(range.clone());
+        TrackMut::from_slice(self.data.get_mut(range), self.bytes_per_sector)
     }
 
+    /// Get the error of a sector that was found when the sector was read from
+    /// the physical media.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.set_sector_error((1, 0), SectorError::DataChecksum);
+    /// assert_eq!(disk.get_sector_error((1, 0)), SectorError::DataChecksum);
+    /// assert_eq!(disk.get_sector_error((1, 1)), SectorError::Ok);
+    /// ```
+    pub fn get_sector_error(&self, sector_ref: SectorRef) -> SectorError {
+        self.sector_errors
+            .get(&sector_ref)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Set the error of a sector.
+    pub fn set_sector_error(&mut self, sector_ref: SectorRef, sector_error: SectorError) {
+        if sector_error == SectorError::Ok {
+            self.sector_errors.remove(&sector_ref);
+        } else {
+            self.sector_errors.insert(sector_ref, sector_error);
+        }
+    }
+
+    /// Remove all errors of the disk.
+    pub fn clear_sector_errors(&mut self) {
+        self.sector_errors.clear();
+    }
+
+    /// All sectors that have an error, ordered by sector.
+    pub fn sector_errors(&self) -> impl Iterator<Item = (SectorRef, SectorError)> + '_ {
+        self.sector_errors
+            .iter()
+            .map(|(sector_ref, sector_error)| (*sector_ref, *sector_error))
+    }
+}
+
+impl<L> Disk<L>
+where
+    L: Layout + Sized + Default,
+{
     /// Get the name of the disk
     ///
     /// # Example
@@ -166,7 +670,7 @@ where
     /// assert_eq!(String::from(&disk.get_name()), "EMPTY");
     /// ```
     pub fn get_name(&self) -> L::StringType {
-        L::default().get_disk_name(self)
+        self.shared_layout().get_disk_name(self)
     }
     /// Set the name of the disk
     ///
@@ -182,7 +686,7 @@ where
     /// assert_eq!(String::from(&disk.get_name()), "HELLO");
     /// ```
     pub fn set_name(&mut self, new_name: &L::StringType) {
-        L::default().set_disk_name(self, new_name)
+        self.shared_layout().set_disk_name(self, new_name)
     }
 
     /// Format the disk
@@ -197,7 +701,10 @@ where
     /// disk.format();
     /// ```
     pub fn format(&mut self) {
-        L::default().format_disk(self);
+        self.current_directory = None;
+        self.raw_tracks.clear();
+        self.sector_errors.clear();
+        self.shared_layout().format_disk(self);
     }
 
     /// List file entries of disk.
@@ -215,7 +722,7 @@ where
     /// let entries = disk.list_entries();
     /// ```
     pub fn list_entries(&mut self) -> Vec<L::FileEntryType> {
-        L::default().list_entries(self)
+        self.shared_layout().list_entries(self)
     }
 
     /// Count number of unused sectors
@@ -232,12 +739,12 @@ where
     /// assert_eq!(disk.num_unused_sectors(), 664);
     /// ```
     pub fn num_unused_sectors(&mut self) -> usize {
-        L::default().num_unused_sectors(self)
+        self.shared_layout().num_unused_sectors(self)
     }
 
     /// Read the contents of the given file.
     pub fn read_file(&self, file_entry: &L::FileEntryType) -> Vec<u8> {
-        L::default().read_file(self, file_entry)
+        self.shared_layout().read_file(self, file_entry)
     }
 
     /// Count number of unused sectors
@@ -262,6 +769,143 @@ where
     /// assert_eq!(disk.num_unused_sectors(), 663);
     /// ```
     pub fn create_file(&mut self, file_entry: &L::FileEntryType, content: &[u8]) {
-        L::default().create_file(self, file_entry, content);
+        self.shared_layout().create_file(self, file_entry, content);
+    }
+
+    /// Delete the given file and release its sectors.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    ///
+    /// let entry = FileEntry {
+    ///     name: PetsciiString::from(&String::from("HELLO")),
+    ///     file_type: FileType::Program,
+    ///     ..FileEntry::default()
+    /// };
+    /// disk.create_file(&entry, b"TEST");
+    /// let entries = disk.list_entries();
+    /// disk.delete_file(&entries[0]);
+    ///
+    /// assert!(disk.list_entries().is_empty());
+    /// assert_eq!(disk.num_unused_sectors(), 664);
+    /// ```
+    pub fn delete_file(&mut self, file_entry: &L::FileEntryType) {
+        self.shared_layout().delete_file(self, file_entry);
+    }
+}
+
+impl<L> Disk<L>
+where
+    L: DirectoryLayout + Sized + Default,
+{
+    /// Header sector of the current directory, None when the current directory
+    /// is the root directory.
+    pub fn current_directory(&self) -> Option<SectorRef> {
+        self.current_directory
+    }
+
+    /// Change the current directory. Only intended to be used by layouts; use
+    /// [Disk::enter_directory] and [Disk::leave_directory] to navigate.
+    pub fn set_current_directory(&mut self, current_directory: Option<SectorRef>) {
+        self.current_directory = current_directory;
+    }
+
+    /// Make the given subdirectory the current directory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    ///
+    /// let mut disk = Disk::with_num_tracks(CmdNative::default(), 4);
+    /// disk.format();
+    /// disk.create_directory(&PetsciiString::from(&String::from("GAMES")));
+    ///
+    /// let directory = disk.list_entries()[0].clone();
+    /// disk.enter_directory(&directory);
+    /// assert!(disk.list_entries().is_empty());
+    /// ```
+    pub fn enter_directory(&mut self, file_entry: &L::FileEntryType) {
+        self.shared_layout().enter_directory(self, file_entry);
+    }
+
+    /// Make the parent of the current directory the current directory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    ///
+    /// let mut disk = Disk::with_num_tracks(CmdNative::default(), 4);
+    /// disk.format();
+    /// disk.create_directory(&PetsciiString::from(&String::from("GAMES")));
+    ///
+    /// let directory = disk.list_entries()[0].clone();
+    /// disk.enter_directory(&directory);
+    /// disk.leave_directory();
+    /// assert_eq!(disk.current_directory(), None);
+    /// ```
+    pub fn leave_directory(&mut self) {
+        self.shared_layout().leave_directory(self);
+    }
+
+    /// Create a new subdirectory inside the current directory.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    ///
+    /// let mut disk = Disk::with_num_tracks(CmdNative::default(), 4);
+    /// disk.format();
+    /// disk.create_directory(&PetsciiString::from(&String::from("GAMES")));
+    ///
+    /// let entries = disk.list_entries();
+    /// assert_eq!(entries[0].file_type, FileType::Directory);
+    /// ```
+    pub fn create_directory(&mut self, name: &L::StringType) {
+        self.shared_layout().create_directory(self, name);
+    }
+}
+
+/// Offset of each track inside an image with the given number of tracks,
+/// followed by the size of the image.
+pub(crate) fn track_offsets<L: Geometry>(layout: &L, num_tracks: TrackNo) -> Vec<usize> {
+    let bytes_per_sector = layout.bytes_per_sector() as usize;
+    let mut offsets = Vec::with_capacity(num_tracks as usize + 1);
+    let mut offset = 0;
+    offsets.push(offset);
+    for track_no in 1..=num_tracks {
+        offset += layout.num_sectors(track_no) as usize * bytes_per_sector;
+        offsets.push(offset);
+    }
+    offsets
+}
+
+/// Read until the buffer is full or the end of the reader is reached. Returns
+/// the number of bytes read; bytes that could not be read are left untouched.
+pub(crate) fn read_fully<R: Read>(reader: &mut R, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut num_bytes_read = 0;
+    while num_bytes_read < buffer.len() {
+        match reader.read(&mut buffer[num_bytes_read..]) {
+            Ok(0) => break,
+            Ok(num_bytes) => num_bytes_read += num_bytes,
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            Err(error) => return Err(error),
+        }
     }
+    Ok(num_bytes_read)
 }
@@ -0,0 +1,330 @@
+//! G64 files contain the GCR encoded tracks of a disk, as they pass the head
+//! of the disk drive.
+//!
+//! Use [G64Image] to read and write G64 files, and [Disk::encode_g64] and
+//! [Disk::decode_g64] to convert between the tracks and the sectors of a disk.
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use crate::{half_track_no, Disk, GcrLayout, HalfTrackNo, RawTrack, TrackNo};
+
+const SIGNATURE: &[u8; 8] = b"GCR-1541";
+const VERSION: u8 = 0;
+const HEADER_SIZE: usize = 12;
+/// Half track stored as the first track of a G64 file.
+const FIRST_HALF_TRACK: usize = 2;
+/// Number of half tracks of a G64 file created by [G64Image::new].
+pub const DEFAULT_NUM_HALF_TRACKS: usize = 84;
+/// Maximum track size of a G64 file created by [G64Image::new].
+pub const DEFAULT_MAX_TRACK_SIZE: u16 = 7928;
+/// Half tracks of a G64 file created by [G64Image::new]: track 1 up to half
+/// track 42.5.
+pub const G64_HALF_TRACKS: RangeInclusive<HalfTrackNo> = FIRST_HALF_TRACK as HalfTrackNo
+    ..=(FIRST_HALF_TRACK + DEFAULT_NUM_HALF_TRACKS - 1) as HalfTrackNo;
+
+/// Content of a G64 file.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+///
+/// let mut image = G64Image::new();
+/// image.set_track(1, RawTrack::from_bytes(vec![0x55; 6000], 3)).unwrap();
+/// assert!(image.set_half_track(1, RawTrack::default()).is_err());
+///
+/// let mut bytes = Vec::new();
+/// image.write_to_writer(&mut bytes).unwrap();
+///
+/// let image = G64Image::read_from_reader(&mut bytes.as_slice()).unwrap();
+/// assert_eq!(image.get_track(1).unwrap().num_bits(), 6000 * 8);
+/// assert!(image.get_track(2).is_none());
+/// ```
+#[derive(Debug, Clone)]
+pub struct G64Image {
+    /// Size reserved for each track in the file.
+    pub max_track_size: u16,
+    /// Tracks indexed by half track. Index 0 is half track 2 (track 1), index
+    /// 1 is half track 3.
+    pub half_tracks: Vec<Option<RawTrack>>,
+}
+
+impl Default for G64Image {
+    fn default() -> G64Image {
+        G64Image::new()
+    }
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ]) as usize
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn invalid_half_track(half_track_no: usize) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("half track {half_track_no} cannot be stored in a G64 file"),
+    )
+}
+
+impl G64Image {
+    /// Create an image without any tracks.
+    pub fn new() -> G64Image {
+        G64Image {
+            max_track_size: DEFAULT_MAX_TRACK_SIZE,
+            half_tracks: vec![None; DEFAULT_NUM_HALF_TRACKS],
+        }
+    }
+
+    /// Get the track with the given (full) track number.
+    pub fn get_track(&self, track_no: TrackNo) -> Option<&RawTrack> {
+        self.get_half_track(half_track_no(track_no)?)
+    }
+
+    /// Store the track with the given (full) track number.
+    pub fn set_track(&mut self, track_no: TrackNo, track: RawTrack) -> std::io::Result<()> {
+        let half_track_no =
+            half_track_no(track_no).ok_or_else(|| invalid_half_track(track_no as usize * 2))?;
+        self.set_half_track(half_track_no, track)
+    }
+
+    /// Get the track with the given half track number.
+    pub fn get_half_track(&self, half_track_no: HalfTrackNo) -> Option<&RawTrack> {
+        let index = (half_track_no as usize).checked_sub(FIRST_HALF_TRACK)?;
+        self.half_tracks.get(index)?.as_ref()
+    }
+
+    /// Store the track with the given half track number. Fails for half
+    /// tracks below the first track.
+    pub fn set_half_track(
+        &mut self,
+        half_track_no: HalfTrackNo,
+        track: RawTrack,
+    ) -> std::io::Result<()> {
+        let index = (half_track_no as usize)
+            .checked_sub(FIRST_HALF_TRACK)
+            .ok_or_else(|| invalid_half_track(half_track_no as usize))?;
+        if index >= self.half_tracks.len() {
+            self.half_tracks.resize(index + 1, None);
+        }
+        self.half_tracks[index] = Some(track);
+        Ok(())
+    }
+
+    /// All tracks of the image, ordered by half track.
+    pub fn tracks(&self) -> impl Iterator<Item = (HalfTrackNo, &RawTrack)> {
+        self.half_tracks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, track)| {
+                track
+                    .as_ref()
+                    .map(|track| ((index + FIRST_HALF_TRACK) as HalfTrackNo, track))
+            })
+    }
+
+    /// Load a G64 image from file path.
+    pub fn read_from_path(filename: &Path) -> std::io::Result<G64Image> {
+        let mut file = File::open(filename)?;
+        G64Image::read_from_reader(&mut file)
+    }
+
+    /// Load a G64 image from a reader.
+    ///
+    /// Speed zones that vary within a track are not supported; the speed zone of
+    /// the start of the track is used.
+    pub fn read_from_reader<R: Read>(reader: &mut R) -> std::io::Result<G64Image> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() < HEADER_SIZE || &bytes[0..8] != SIGNATURE {
+            return Err(invalid_data("not a G64 file"));
+        }
+        let num_half_tracks = bytes[9] as usize;
+        let max_track_size = read_u16(&bytes, 10);
+        let speed_zone_table = HEADER_SIZE + num_half_tracks * 4;
+        if bytes.len() < speed_zone_table + num_half_tracks * 4 {
+            return Err(invalid_data("G64 track table is truncated"));
+        }
+
+        let mut half_tracks = Vec::with_capacity(num_half_tracks);
+        for index in 0..num_half_tracks {
+            let track_offset = read_u32(&bytes, HEADER_SIZE + index * 4);
+            if track_offset == 0 {
+                half_tracks.push(None);
+                continue;
+            }
+            if bytes.len() < track_offset + 2 {
+                return Err(invalid_data("G64 track is truncated"));
+            }
+            let track_size = read_u16(&bytes, track_offset) as usize;
+            let data_offset = track_offset + 2;
+            if bytes.len() < data_offset + track_size {
+                return Err(invalid_data("G64 track is truncated"));
+            }
+
+            let speed_zone = match read_u32(&bytes, speed_zone_table + index * 4) {
+                speed_zone @ 0..=3 => speed_zone as u8,
+                speed_zone_offset => bytes.get(speed_zone_offset).map_or(0, |zones| zones >> 6),
+            };
+            half_tracks.push(Some(RawTrack::from_bytes(
+                bytes[data_offset..data_offset + track_size].to_vec(),
+                speed_zone,
+            )));
+        }
+
+        Ok(G64Image {
+            max_track_size,
+            half_tracks,
+        })
+    }
+
+    pub fn write_to_path(&self, filename: &Path) -> std::io::Result<()> {
+        let mut file = File::create(filename)?;
+        self.write_to_writer(&mut file)
+    }
+
+    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let num_half_tracks = self.half_tracks.len();
+        let max_track_size = self
+            .half_tracks
+            .iter()
+            .flatten()
+            .map(|track| track.as_bytes().len())
+            .fold(self.max_track_size as usize, usize::max);
+        if num_half_tracks > u8::MAX as usize || max_track_size > u16::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "too many or too large tracks for a G64 file",
+            ));
+        }
+
+        let mut header = Vec::with_capacity(HEADER_SIZE + num_half_tracks * 8);
+        header.extend_from_slice(SIGNATURE);
+        header.push(VERSION);
+        header.push(num_half_tracks as u8);
+        header.extend_from_slice(&(max_track_size as u16).to_le_bytes());
+
+        let mut track_offset = HEADER_SIZE + num_half_tracks * 8;
+        for half_track in &self.half_tracks {
+            if half_track.is_some() {
+                header.extend_from_slice(&(track_offset as u32).to_le_bytes());
+                track_offset += 2 + max_track_size;
+            } else {
+                header.extend_from_slice(&0_u32.to_le_bytes());
+            }
+        }
+        for half_track in &self.half_tracks {
+            let speed_zone = half_track.as_ref().map_or(0, |track| track.speed_zone());
+            header.extend_from_slice(&(speed_zone as u32).to_le_bytes());
+        }
+        writer.write_all(&header)?;
+
+        for track in self.half_tracks.iter().flatten() {
+            let data = track.as_bytes();
+            writer.write_all(&(data.len() as u16).to_le_bytes())?;
+            writer.write_all(data)?;
+            writer.write_all(&vec![0; max_track_size - data.len()])?;
+        }
+        Ok(())
+    }
+}
+
+impl<L> Disk<L>
+where
+    L: GcrLayout + Default,
+{
+    /// Encode the disk into GCR encoded tracks.
+    ///
+    /// Raw tracks that are preserved on the disk are stored as is, the other
+    /// tracks are encoded from the sectors of the disk.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    ///
+    /// let image = disk.encode_g64().unwrap();
+    /// assert_eq!(image.get_track(1).unwrap().speed_zone(), 3);
+    /// assert_eq!(image.get_track(35).unwrap().speed_zone(), 0);
+    ///
+    /// let mut decoded = Disk::<Commodore1541>::new();
+    /// decoded.decode_g64(&image).unwrap();
+    /// assert_eq!(String::from(&decoded.get_name()), "NONAME");
+    /// assert_eq!(decoded.num_unused_sectors(), 664);
+    /// assert_eq!(decoded.raw_tracks().count(), 0);
+    /// ```
+    pub fn encode_g64(&self) -> std::io::Result<G64Image> {
+        let mut image = G64Image::new();
+        for (half_track_no, raw_track) in self.raw_tracks() {
+            image.set_half_track(half_track_no, raw_track.clone())?;
+        }
+        for track_no in 1..=self.num_tracks() {
+            image.set_track(track_no, self.encode_raw_track(track_no))?;
+        }
+        Ok(image)
+    }
+
+    /// Decode the GCR encoded tracks into this disk.
+    ///
+    /// Sectors that cannot be found on their track are left untouched. Tracks
+    /// that cannot be represented by sectors are preserved as raw tracks, see
+    /// [Disk::import_raw_track].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// let mut image = disk.encode_g64().unwrap();
+    /// image
+///     .set_half_track(37, RawTrack::from_bits(vec![0x55; 7000], 55_999, 2))
+///     .unwrap();
+    ///
+    /// let mut decoded = Disk::<Commodore1541>::new();
+    /// decoded.decode_g64(&image).unwrap();
+    /// assert_eq!(decoded.get_raw_track(37).unwrap().num_bits(), 55_999);
+    /// assert!(decoded.encode_g64().unwrap().get_half_track(37).is_some());
+    /// ```
+    pub fn decode_g64(&mut self, image: &G64Image) -> std::io::Result<()> {
+        self.clear_raw_tracks();
+        self.clear_sector_errors();
+        for (half_track_no, raw_track) in image.tracks() {
+            self.import_raw_track(half_track_no, raw_track.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Load the sectors of a G64 file.
+    pub fn read_g64_from_path(&mut self, filename: &Path) -> std::io::Result<()> {
+        let image = G64Image::read_from_path(filename)?;
+        self.decode_g64(&image)
+    }
+
+    /// Store the disk as a G64 file.
+    pub fn write_g64_to_path(&self, filename: &Path) -> std::io::Result<()> {
+        self.encode_g64()?.write_to_path(filename)
+    }
+}
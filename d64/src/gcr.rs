@@ -0,0 +1,413 @@
+//! Group Coded Recording (GCR) as used by the Commodore disk drives.
+//!
+//! Disk drives don't store the bytes of a sector directly on the disk. Every
+//! 4 bits are encoded as 5 bits, making sure that the disk never contains more
+//! than two consecutive 0 bits. Ten or more consecutive 1 bits can't be
+//! produced by the encoding, and are used as sync marks in front of each
+//! block.
+//!
+//! Each sector is stored as two blocks: a header block (containing the track,
+//! sector and disk id) and a data block (containing the content of the
+//! sector).
+
+/// GCR code for each nibble.
+const GCR_ENCODE: [u8; 16] = [
+    0x0a, 0x0b, 0x12, 0x13, 0x0e, 0x0f, 0x16, 0x17, 0x09, 0x19, 0x1a, 0x1b, 0x0d, 0x1d, 0x1e, 0x15,
+];
+/// Marks a GCR code that isn't valid in [GCR_DECODE].
+const GCR_INVALID: u8 = 0xff;
+/// Nibble for each GCR code.
+const GCR_DECODE: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x08, 0x00, 0x01, 0xff, 0x0c, 0x04, 0x05,
+    0xff, 0xff, 0x02, 0x03, 0xff, 0x0f, 0x06, 0x07, 0xff, 0x09, 0x0a, 0x0b, 0xff, 0x0d, 0x0e, 0xff,
+];
+
+/// Byte used to fill sync marks.
+pub const SYNC_BYTE: u8 = 0xff;
+/// Number of sync bytes written in front of each block.
+pub const SYNC_LENGTH: usize = 5;
+/// Minimum number of consecutive 1 bits that are detected as a sync mark.
+pub const SYNC_MIN_BITS: usize = 10;
+/// Byte used to fill gaps between blocks.
+pub const GAP_BYTE: u8 = 0x55;
+/// Number of gap bytes between a header block and its data block.
+pub const HEADER_GAP_LENGTH: usize = 9;
+
+/// First byte of a header block.
+pub const HEADER_BLOCK_ID: u8 = 0x08;
+/// First byte of a data block.
+pub const DATA_BLOCK_ID: u8 = 0x07;
+/// Size of a decoded header block.
+pub const HEADER_BLOCK_SIZE: usize = 8;
+/// Size of the sector content inside a data block.
+pub const DATA_SIZE: usize = 256;
+/// Size of a decoded data block: id, content, checksum and two padding bytes.
+pub const DATA_BLOCK_SIZE: usize = DATA_SIZE + 4;
+/// Size of an encoded header block.
+pub const GCR_HEADER_BLOCK_SIZE: usize = HEADER_BLOCK_SIZE * 5 / 4;
+/// Size of an encoded data block.
+pub const GCR_DATA_BLOCK_SIZE: usize = DATA_BLOCK_SIZE * 5 / 4;
+/// Bytes used by a single sector on the disk, excluding the gap after the sector.
+pub const GCR_SECTOR_SIZE: usize =
+    SYNC_LENGTH + GCR_HEADER_BLOCK_SIZE + HEADER_GAP_LENGTH + SYNC_LENGTH + GCR_DATA_BLOCK_SIZE;
+/// Number of bytes that fit on a single track for each speed zone.
+pub const TRACK_CAPACITY: [usize; 4] = [6250, 6666, 7142, 7692];
+
+/// Encode bytes to GCR. Each 4 bytes are encoded into 5 bytes.
+///
+/// The length of the bytes should be a multiple of 4.
+///
+/// # Example
+///
+/// ```
+/// use d64::gcr::*;
+///
+/// let encoded = encode_gcr(&[0x08, 0x00, 0x01, 0x12]);
+/// assert_eq!(encoded, vec![0x52, 0x54, 0xa5, 0x2d, 0x72]);
+/// assert_eq!(decode_gcr(&encoded), Some(vec![0x08, 0x00, 0x01, 0x12]));
+/// ```
+pub fn encode_gcr(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len() * 5 / 4);
+    for chunk in bytes.chunks(4) {
+        let mut bits = 0_u64;
+        for byte in chunk {
+            bits = (bits << 10)
+                | ((GCR_ENCODE[(byte >> 4) as usize] as u64) << 5)
+                | GCR_ENCODE[(byte & 0x0f) as usize] as u64;
+        }
+        for i in (0..chunk.len() * 5 / 4).rev() {
+            result.push((bits >> (i * 8)) as u8);
+        }
+    }
+    result
+}
+
+/// Decode GCR encoded bytes. Each 5 bytes are decoded into 4 bytes.
+///
+/// Returns None when the bytes contain an invalid GCR code.
+pub fn decode_gcr(gcr: &[u8]) -> Option<Vec<u8>> {
+    let reader = BitReader::new(gcr, gcr.len() * 8);
+    reader.read_bytes(0, gcr.len() * 4 / 5)
+}
+
+/// Encode the header block of a sector.
+///
+/// The disk id is stored in reversed order.
+pub fn encode_header_block(track: u8, sector: u8, disk_id: [u8; 2]) -> Vec<u8> {
+    let checksum = sector ^ track ^ disk_id[1] ^ disk_id[0];
+    encode_gcr(&[
+        HEADER_BLOCK_ID,
+        checksum,
+        sector,
+        track,
+        disk_id[1],
+        disk_id[0],
+        0x0f,
+        0x0f,
+    ])
+}
+
+/// Encode the data block of a sector.
+pub fn encode_data_block(data: &[u8]) -> Vec<u8> {
+    let mut block = Vec::with_capacity(DATA_BLOCK_SIZE);
+    block.push(DATA_BLOCK_ID);
+    block.extend_from_slice(data);
+    block.resize(DATA_SIZE + 1, 0);
+    block.push(checksum(&block[1..]));
+    block.extend_from_slice(&[0, 0]);
+    encode_gcr(&block)
+}
+
+/// Encode a complete sector: sync, header block, gap, sync and data block.
+pub fn encode_sector(track: u8, sector: u8, disk_id: [u8; 2], data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(GCR_SECTOR_SIZE);
+    result.resize(SYNC_LENGTH, SYNC_BYTE);
+    result.extend(encode_header_block(track, sector, disk_id));
+    result.resize(result.len() + HEADER_GAP_LENGTH, GAP_BYTE);
+    result.resize(result.len() + SYNC_LENGTH, SYNC_BYTE);
+    result.extend(encode_data_block(data));
+    result
+}
+
+/// Encode a complete track. The sectors are evenly spread over the capacity of
+/// the speed zone.
+///
+/// # Example
+///
+/// ```
+/// use d64::gcr::*;
+///
+/// let sectors = vec![[0_u8; 256]; 21];
+/// let sector_refs = sectors.iter().map(|sector| &sector[..]).collect::<Vec<&[u8]>>();
+/// let track = encode_track(1, [0x30, 0x31], 3, &sector_refs);
+/// assert_eq!(track.len(), TRACK_CAPACITY[3]);
+/// assert_eq!(decode_sectors(&track, track.len() * 8).len(), 21);
+/// ```
+pub fn encode_track(track: u8, disk_id: [u8; 2], speed_zone: u8, sectors: &[&[u8]]) -> Vec<u8> {
+    let capacity = TRACK_CAPACITY[speed_zone as usize & 3];
+    let gap_length = if sectors.is_empty() {
+        0
+    } else {
+        capacity.saturating_sub(sectors.len() * GCR_SECTOR_SIZE) / sectors.len()
+    };
+
+    let mut result = Vec::with_capacity(capacity);
+    for (sector_no, data) in sectors.iter().enumerate() {
+        result.extend(encode_sector(track, sector_no as u8, disk_id, data));
+        result.resize(result.len() + gap_length, GAP_BYTE);
+    }
+    if result.len() < capacity {
+        result.resize(capacity, GAP_BYTE);
+    }
+    result
+}
+
+/// XOR checksum as used by header and data blocks.
+pub fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |result, byte| result ^ byte)
+}
+
+/// Decoded header block of a sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectorHeader {
+    pub track: u8,
+    pub sector: u8,
+    /// Disk id in the order it is stored in the BAM.
+    pub disk_id: [u8; 2],
+    /// Does the checksum of the header block match its content.
+    pub checksum_ok: bool,
+}
+
+/// A block that is found after a sync mark.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GcrBlock {
+    Header(SectorHeader),
+    Data {
+        data: Vec<u8>,
+        /// Does the checksum of the data block match its content.
+        checksum_ok: bool,
+    },
+    /// Block could not be decoded or has an unknown block id.
+    Unknown,
+}
+
+/// Decode all blocks of a track.
+///
+/// The track is handled as a circular bit stream of num_bits bits. Blocks
+/// start directly after a sync mark and don't need to be byte aligned.
+///
+/// # Example
+///
+/// ```
+/// use d64::gcr::*;
+///
+/// let track = encode_sector(1, 3, [0x30, 0x31], &[0xaa; 256]);
+/// let blocks = decode_blocks(&track, track.len() * 8);
+/// assert_eq!(blocks.len(), 2);
+/// assert_eq!(
+///     blocks[0],
+///     GcrBlock::Header(SectorHeader {
+///         track: 1,
+///         sector: 3,
+///         disk_id: [0x30, 0x31],
+///         checksum_ok: true
+///     })
+/// );
+/// ```
+pub fn decode_blocks(track: &[u8], num_bits: usize) -> Vec<GcrBlock> {
+    let reader = BitReader::new(track, num_bits);
+    reader
+        .find_sync_marks()
+        .into_iter()
+        .map(|position| reader.read_block(position))
+        .collect()
+}
+
+/// Sector found on a track: a header block and the data block following it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedSector {
+    pub header: SectorHeader,
+    /// Content of the data block. None when no data block follows the header.
+    pub data: Option<Vec<u8>>,
+    /// Does the checksum of the data block match its content.
+    pub data_checksum_ok: bool,
+}
+
+/// Decode the sectors of a track: each header block followed by a data block.
+pub fn decode_sectors(track: &[u8], num_bits: usize) -> Vec<DecodedSector> {
+    let mut result: Vec<DecodedSector> = Vec::new();
+    let mut last_header: Option<SectorHeader> = None;
+    let mut blocks = decode_blocks(track, num_bits);
+    // The track is circular; a data block at the start of the track belongs to
+    // the header block at the end.
+    if let Some(first_header) = blocks
+        .iter()
+        .position(|block| matches!(block, GcrBlock::Header(_)))
+    {
+        blocks.rotate_left(first_header);
+    }
+    for block in blocks {
+        match block {
+            GcrBlock::Header(header) => {
+                if let Some(previous_header) = last_header.replace(header) {
+                    result.push(DecodedSector {
+                        header: previous_header,
+                        data: None,
+                        data_checksum_ok: false,
+                    });
+                }
+            }
+            GcrBlock::Data { data, checksum_ok } => {
+                if let Some(header) = last_header.take() {
+                    result.push(DecodedSector {
+                        header,
+                        data: Some(data),
+                        data_checksum_ok: checksum_ok,
+                    });
+                }
+            }
+            GcrBlock::Unknown => {}
+        }
+    }
+    if let Some(header) = last_header {
+        result.push(DecodedSector {
+            header,
+            data: None,
+            data_checksum_ok: false,
+        });
+    }
+    result
+}
+
+/// Find the number of bits of a single revolution in a stream that contains
+/// more than one revolution of a track, as captured by nibblers.
+///
+/// The revolution is detected by the first header block that is found again
+/// later in the stream. None when no header block repeats.
+///
+/// # Example
+///
+/// ```
+/// use d64::gcr::*;
+///
+/// let sectors = vec![[0_u8; 256]; 17];
+/// let sector_refs = sectors.iter().map(|sector| &sector[..]).collect::<Vec<&[u8]>>();
+/// let track = encode_track(35, [0x30, 0x31], 0, &sector_refs);
+/// let mut stream = track.repeat(2);
+/// stream.truncate(8192);
+/// assert_eq!(find_revolution(&stream, stream.len() * 8), Some(track.len() * 8));
+/// ```
+pub fn find_revolution(stream: &[u8], num_bits: usize) -> Option<usize> {
+    let reader = BitReader::new(stream, num_bits);
+    let headers = reader
+        .find_sync_marks()
+        .into_iter()
+        .filter_map(|position| match reader.read_block(position) {
+            GcrBlock::Header(header) => Some((position, header)),
+            _ => None,
+        })
+        .collect::<Vec<(usize, SectorHeader)>>();
+    let (first_position, first_header) = headers.first()?;
+    headers
+        .iter()
+        .skip(1)
+        .find(|(_, header)| header == first_header)
+        .map(|(position, _)| position - first_position)
+}
+
+/// Reads bits from a track as a circular bit stream.
+pub(crate) struct BitReader<'a> {
+    bytes: &'a [u8],
+    num_bits: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8], num_bits: usize) -> BitReader<'a> {
+        BitReader {
+            bytes,
+            num_bits: num_bits.min(bytes.len() * 8),
+        }
+    }
+
+    pub(crate) fn bit(&self, position: usize) -> u8 {
+        let position = position % self.num_bits;
+        (self.bytes[position / 8] >> (7 - position % 8)) & 1
+    }
+
+    fn bits(&self, position: usize, count: usize) -> u16 {
+        (0..count).fold(0, |result, i| (result << 1) | self.bit(position + i) as u16)
+    }
+
+    /// Read count decoded bytes starting at the given bit position.
+    fn read_bytes(&self, position: usize, count: usize) -> Option<Vec<u8>> {
+        (0..count)
+            .map(|i| self.read_byte(position + i * 10))
+            .collect()
+    }
+
+    fn read_byte(&self, position: usize) -> Option<u8> {
+        let high = GCR_DECODE[self.bits(position, 5) as usize];
+        let low = GCR_DECODE[self.bits(position + 5, 5) as usize];
+        if high == GCR_INVALID || low == GCR_INVALID {
+            None
+        } else {
+            Some((high << 4) | low)
+        }
+    }
+
+    /// Bit positions directly after each sync mark.
+    pub(crate) fn find_sync_marks(&self) -> Vec<usize> {
+        self.find_syncs()
+            .into_iter()
+            .map(|(position, _)| position)
+            .collect()
+    }
+
+    /// Bit positions directly after each sync mark, together with the number
+    /// of one bits of the sync mark.
+    pub(crate) fn find_syncs(&self) -> Vec<(usize, usize)> {
+        let mut result = Vec::new();
+        if self.num_bits == 0 {
+            return result;
+        }
+        let start = match (0..self.num_bits).find(|position| self.bit(*position) == 0) {
+            Some(start) => start,
+            None => return result,
+        };
+        let mut ones = 0;
+        for position in start..=start + self.num_bits {
+            if self.bit(position) == 1 {
+                ones += 1;
+            } else {
+                if ones >= SYNC_MIN_BITS {
+                    result.push((position % self.num_bits, ones));
+                }
+                ones = 0;
+            }
+        }
+        result.sort_unstable();
+        result
+    }
+
+    pub(crate) fn read_block(&self, position: usize) -> GcrBlock {
+        match self.read_byte(position) {
+            Some(HEADER_BLOCK_ID) => match self.read_bytes(position, HEADER_BLOCK_SIZE) {
+                Some(block) => GcrBlock::Header(SectorHeader {
+                    track: block[3],
+                    sector: block[2],
+                    disk_id: [block[5], block[4]],
+                    checksum_ok: checksum(&block[1..6]) == 0,
+                }),
+                None => GcrBlock::Unknown,
+            },
+            Some(DATA_BLOCK_ID) => match self.read_bytes(position, DATA_BLOCK_SIZE) {
+                Some(block) => GcrBlock::Data {
+                    checksum_ok: checksum(&block[1..DATA_SIZE + 2]) == 0,
+                    data: block[1..DATA_SIZE + 1].to_vec(),
+                },
+                None => GcrBlock::Unknown,
+            },
+            _ => GcrBlock::Unknown,
+        }
+    }
+}
@@ -0,0 +1,509 @@
+//! Gzip and deflate compression, as used by compressed images like `.d64.gz`.
+//!
+//! Decompression supports all deflate block types. Compression uses a single
+//! block with the fixed Huffman codes, which is sufficient for the repeating
+//! content of disk images.
+//!
+//! # Example
+//!
+//! ```
+//! use d64::gzip;
+//!
+//! let content = b"ABABABABABABABAB0000000000000000".repeat(100);
+//! let compressed = gzip::compress(&content);
+//! assert!(gzip::is_gzip(&compressed));
+//! assert!(compressed.len() < content.len() / 10);
+//! assert_eq!(gzip::decompress(&compressed).unwrap(), content);
+//! ```
+use std::io::{Error, ErrorKind};
+
+const SIGNATURE: [u8; 2] = [0x1f, 0x8b];
+const METHOD_DEFLATE: u8 = 8;
+const HEADER_SIZE: usize = 10;
+const TRAILER_SIZE: usize = 8;
+const OS_UNKNOWN: u8 = 0xff;
+
+const FLAG_HEADER_CRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+
+const BLOCK_STORED: u32 = 0;
+const BLOCK_FIXED: u32 = 1;
+const BLOCK_DYNAMIC: u32 = 2;
+
+const MAX_CODE_LENGTH: usize = 15;
+const END_OF_BLOCK: u16 = 256;
+const NUM_FIXED_LITERALS: usize = 288;
+const NUM_FIXED_DISTANCES: usize = 30;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which the code lengths of the code length code are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const WINDOW_SIZE: usize = 32768;
+const HASH_BITS: usize = 15;
+const MAX_CHAIN: usize = 64;
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// CRC32 (IEEE 802.3) checksum as used by gzip and P64 files.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(0xffff_ffff_u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Does the given content start with a gzip header.
+pub fn is_gzip(bytes: &[u8]) -> bool {
+    bytes.len() >= HEADER_SIZE && bytes[0..2] == SIGNATURE && bytes[2] == METHOD_DEFLATE
+}
+
+/// Decompress gzip content. Content consisting of multiple gzip members is
+/// concatenated.
+pub fn decompress(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let member = &bytes[offset..];
+        if !is_gzip(member) {
+            return Err(invalid_data("not a gzip file"));
+        }
+        let flags = member[3];
+        let mut header_size = HEADER_SIZE;
+        if flags & FLAG_EXTRA != 0 {
+            let extra = member
+                .get(header_size..header_size + 2)
+                .ok_or_else(|| invalid_data("gzip header is truncated"))?;
+            header_size += 2 + u16::from_le_bytes([extra[0], extra[1]]) as usize;
+        }
+        for flag in [FLAG_NAME, FLAG_COMMENT] {
+            if flags & flag != 0 {
+                let length = member
+                    .get(header_size..)
+                    .and_then(|rest| rest.iter().position(|byte| *byte == 0))
+                    .ok_or_else(|| invalid_data("gzip header is truncated"))?;
+                header_size += length + 1;
+            }
+        }
+        if flags & FLAG_HEADER_CRC != 0 {
+            header_size += 2;
+        }
+        let compressed = member
+            .get(header_size..)
+            .ok_or_else(|| invalid_data("gzip header is truncated"))?;
+
+        let mut reader = BitReader::new(compressed);
+        let content = inflate_blocks(&mut reader)?;
+        let trailer_offset = header_size + reader.offset;
+        let trailer = member
+            .get(trailer_offset..trailer_offset + TRAILER_SIZE)
+            .ok_or_else(|| invalid_data("gzip trailer is truncated"))?;
+        let checksum = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if checksum != crc32(&content) || size != content.len() as u32 {
+            return Err(invalid_data("gzip checksum mismatch"));
+        }
+        result.extend_from_slice(&content);
+        offset += trailer_offset + TRAILER_SIZE;
+    }
+    Ok(result)
+}
+
+/// Compress the content into a single gzip member.
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len() / 4 + HEADER_SIZE + TRAILER_SIZE);
+    result.extend_from_slice(&SIGNATURE);
+    result.extend_from_slice(&[METHOD_DEFLATE, 0, 0, 0, 0, 0, 0, OS_UNKNOWN]);
+    result.extend_from_slice(&deflate(bytes));
+    result.extend_from_slice(&crc32(bytes).to_le_bytes());
+    result.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    result
+}
+
+/// Decompress a raw deflate stream.
+pub fn inflate(bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    inflate_blocks(&mut BitReader::new(bytes))
+}
+
+/// Compress into a raw deflate stream.
+pub fn deflate(bytes: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    writer.write_bits(1, 1);
+    writer.write_bits(BLOCK_FIXED, 2);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; bytes.len()];
+    let hash = |offset: usize| {
+        let value = (bytes[offset] as usize) << 16
+            | (bytes[offset + 1] as usize) << 8
+            | bytes[offset + 2] as usize;
+        (value.wrapping_mul(0x9E37_79B1) >> 8) & ((1 << HASH_BITS) - 1)
+    };
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        if offset + MIN_MATCH <= bytes.len() {
+            let key = hash(offset);
+            let max_length = MAX_MATCH.min(bytes.len() - offset);
+            let mut candidate = head[key];
+            let mut chain = 0;
+            while candidate != usize::MAX && offset - candidate <= WINDOW_SIZE && chain < MAX_CHAIN
+            {
+                let length = bytes[candidate..candidate + max_length]
+                    .iter()
+                    .zip(&bytes[offset..offset + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if length > best_length {
+                    best_length = length;
+                    best_distance = offset - candidate;
+                    if length == max_length {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+
+        let step = if best_length >= MIN_MATCH {
+            write_length(&mut writer, best_length);
+            write_distance(&mut writer, best_distance);
+            best_length
+        } else {
+            write_fixed_literal(&mut writer, bytes[offset] as u16);
+            1
+        };
+        let end = (offset + step).min(bytes.len().saturating_sub(MIN_MATCH - 1));
+        for (position, previous) in previous.iter_mut().enumerate().take(end).skip(offset) {
+            let key = hash(position);
+            *previous = head[key];
+            head[key] = position;
+        }
+        offset += step;
+    }
+
+    write_fixed_literal(&mut writer, END_OF_BLOCK);
+    writer.finish()
+}
+
+fn write_length(writer: &mut BitWriter, length: usize) {
+    let index = LENGTH_BASE
+        .iter()
+        .rposition(|base| *base as usize <= length)
+        .unwrap();
+    write_fixed_literal(writer, 257 + index as u16);
+    writer.write_bits(
+        (length - LENGTH_BASE[index] as usize) as u32,
+        LENGTH_EXTRA[index] as u32,
+    );
+}
+
+fn write_distance(writer: &mut BitWriter, distance: usize) {
+    let index = DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base as usize <= distance)
+        .unwrap();
+    writer.write_code(index as u32, 5);
+    writer.write_bits(
+        (distance - DISTANCE_BASE[index] as usize) as u32,
+        DISTANCE_EXTRA[index] as u32,
+    );
+}
+
+fn write_fixed_literal(writer: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+/// Writes bits starting with the least significant bit of each byte.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit_buffer: u32,
+    num_bits: u32,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, num_bits: u32) {
+        self.bit_buffer |= value << self.num_bits;
+        self.num_bits += num_bits;
+        while self.num_bits >= 8 {
+            self.bytes.push(self.bit_buffer as u8);
+            self.bit_buffer >>= 8;
+            self.num_bits -= 8;
+        }
+    }
+
+    /// Huffman codes are stored starting with their most significant bit.
+    fn write_code(&mut self, code: u32, num_bits: u32) {
+        let reversed = code.reverse_bits() >> (32 - num_bits);
+        self.write_bits(reversed, num_bits);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.num_bits > 0 {
+            self.bytes.push(self.bit_buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Reads bits starting with the least significant bit of each byte.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// Offset of the next byte to load into the bit buffer.
+    offset: usize,
+    bit_buffer: u32,
+    num_bits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> BitReader<'a> {
+        BitReader {
+            bytes,
+            offset: 0,
+            bit_buffer: 0,
+            num_bits: 0,
+        }
+    }
+
+    fn read_bits(&mut self, num_bits: u32) -> std::io::Result<u32> {
+        while self.num_bits < num_bits {
+            let byte = *self
+                .bytes
+                .get(self.offset)
+                .ok_or_else(|| invalid_data("deflate stream is truncated"))?;
+            self.bit_buffer |= (byte as u32) << self.num_bits;
+            self.offset += 1;
+            self.num_bits += 8;
+        }
+        let value = self.bit_buffer & ((1_u64 << num_bits) - 1) as u32;
+        self.bit_buffer >>= num_bits;
+        self.num_bits -= num_bits;
+        Ok(value)
+    }
+
+    /// Skip the remaining bits of the current byte.
+    fn align_to_byte(&mut self) {
+        self.bit_buffer = 0;
+        self.num_bits = 0;
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    /// Number of codes of each length.
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    /// Symbols ordered by their code.
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> std::io::Result<Huffman> {
+        let mut counts = [0_u16; MAX_CODE_LENGTH + 1];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left = 1_i32;
+        for count in &counts[1..] {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(invalid_data("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0_u16; MAX_CODE_LENGTH + 2];
+        for length in 1..=MAX_CODE_LENGTH {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; offsets[MAX_CODE_LENGTH + 1] as usize];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> std::io::Result<u16> {
+        let mut code = 0_i32;
+        let mut first = 0_i32;
+        let mut index = 0_i32;
+        for count in &self.counts[1..] {
+            code |= reader.read_bits(1)? as i32;
+            let count = *count as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid Huffman code"))
+    }
+}
+
+fn fixed_codes() -> std::io::Result<(Huffman, Huffman)> {
+    let mut lengths = [0_u8; NUM_FIXED_LITERALS];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..].fill(8);
+    Ok((
+        Huffman::new(&lengths)?,
+        Huffman::new(&[5; NUM_FIXED_DISTANCES])?,
+    ))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> std::io::Result<(Huffman, Huffman)> {
+    let num_literals = reader.read_bits(5)? as usize + 257;
+    let num_distances = reader.read_bits(5)? as usize + 1;
+    let num_code_lengths = reader.read_bits(4)? as usize + 4;
+
+    let mut code_lengths = [0_u8; 19];
+    for index in CODE_LENGTH_ORDER.iter().take(num_code_lengths) {
+        code_lengths[*index] = reader.read_bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let mut lengths = Vec::with_capacity(num_literals + num_distances);
+    while lengths.len() < num_literals + num_distances {
+        let symbol = code_length_code.decode(reader)?;
+        let (length, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                let previous = *lengths
+                    .last()
+                    .ok_or_else(|| invalid_data("repeat without previous code length"))?;
+                (previous, 3 + reader.read_bits(2)?)
+            }
+            17 => (0, 3 + reader.read_bits(3)?),
+            _ => (0, 11 + reader.read_bits(7)?),
+        };
+        for _ in 0..repeat {
+            lengths.push(length);
+        }
+    }
+    if lengths.len() != num_literals + num_distances {
+        return Err(invalid_data("too many code lengths"));
+    }
+    Ok((
+        Huffman::new(&lengths[..num_literals])?,
+        Huffman::new(&lengths[num_literals..])?,
+    ))
+}
+
+fn inflate_blocks(reader: &mut BitReader) -> std::io::Result<Vec<u8>> {
+    let mut result = Vec::new();
+    loop {
+        let is_final = reader.read_bits(1)? == 1;
+        match reader.read_bits(2)? {
+            BLOCK_STORED => {
+                reader.align_to_byte();
+                let header = reader
+                    .bytes
+                    .get(reader.offset..reader.offset + 4)
+                    .ok_or_else(|| invalid_data("deflate stream is truncated"))?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                let complement = u16::from_le_bytes([header[2], header[3]]);
+                if length != !complement {
+                    return Err(invalid_data("invalid stored block length"));
+                }
+                let start = reader.offset + 4;
+                let content = reader
+                    .bytes
+                    .get(start..start + length as usize)
+                    .ok_or_else(|| invalid_data("deflate stream is truncated"))?;
+                result.extend_from_slice(content);
+                reader.offset = start + length as usize;
+            }
+            BLOCK_FIXED => {
+                let (literals, distances) = fixed_codes()?;
+                inflate_block(reader, &literals, &distances, &mut result)?;
+            }
+            BLOCK_DYNAMIC => {
+                let (literals, distances) = dynamic_codes(reader)?;
+                inflate_block(reader, &literals, &distances, &mut result)?;
+            }
+            _ => return Err(invalid_data("invalid deflate block type")),
+        }
+        if is_final {
+            reader.align_to_byte();
+            return Ok(result);
+        }
+    }
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    result: &mut Vec<u8>,
+) -> std::io::Result<()> {
+    loop {
+        let symbol = literals.decode(reader)?;
+        match symbol {
+            0..=255 => result.push(symbol as u8),
+            END_OF_BLOCK => return Ok(()),
+            _ => {
+                let index = (symbol - 257) as usize;
+                if index >= LENGTH_BASE.len() {
+                    return Err(invalid_data("invalid deflate length"));
+                }
+                let length = LENGTH_BASE[index] as usize
+                    + reader.read_bits(LENGTH_EXTRA[index] as u32)? as usize;
+                let index = distances.decode(reader)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    return Err(invalid_data("invalid deflate distance"));
+                }
+                let distance = DISTANCE_BASE[index] as usize
+                    + reader.read_bits(DISTANCE_EXTRA[index] as u32)? as usize;
+                if distance > result.len() {
+                    return Err(invalid_data("deflate distance before start of stream"));
+                }
+                let start = result.len() - distance;
+                for position in start..start + length {
+                    result.push(result[position]);
+                }
+            }
+        }
+    }
+}
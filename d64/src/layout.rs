@@ -1,20 +1,40 @@
-use crate::{Disk, TrackNo};
+use crate::{Disk, Track, TrackNo};
 
-/// Layout trait to map how a specific device uses its physical media (Disk).
+/// Physical geometry of a disk: the tracks and sectors that are created on the
+/// physical media.
 ///
-/// The layout trait can be implemented by a specific device struct. Common
-/// features like tracks, sectors are exposed by this trait to have a common
-/// API.
-pub trait Layout {
-    type FileEntryType;
-    type StringType;
-
+/// A [Disk] only needs the geometry to store its sectors. File systems are
+/// added by implementing [Layout], which allows the same geometry to be used
+/// by different file systems, and a file system to be used on different
+/// geometries.
+pub trait Geometry {
     /// Number of tracks that are created on the physical media.
     fn num_tracks(&self) -> u8;
     /// Number of sectors that are created on the physical media for a certain track.
-    fn num_sectors(&self, track: TrackNo) -> u8;
+    fn num_sectors(&self, track: TrackNo) -> u16;
     /// Bytes that are stored in a single sector.
     fn bytes_per_sector(&self) -> u16;
+    /// Number of tracks of an image, read from the header stored in the first
+    /// track of the image.
+    ///
+    /// Layouts that store their size inside the image (CMD native partitions)
+    /// use this to determine the geometry when the image is loaded. Returns
+    /// None when the layout has a fixed geometry or the header doesn't contain
+    /// a valid number of tracks; the disk then keeps its number of tracks.
+    fn read_num_tracks(&self, _first_track: &Track) -> Option<TrackNo> {
+        None
+    }
+}
+
+/// Layout trait to map how a specific device uses its physical media (Disk).
+///
+/// The layout trait can be implemented by a specific device struct. It adds
+/// the file system (names, files and free space) on top of the [Geometry] of
+/// the disk.
+pub trait Layout: Geometry {
+    type FileEntryType;
+    type StringType;
+
     /// Extract the human readable name of the given disk.
     fn get_disk_name(&self, disk: &Disk<Self>) -> Self::StringType
     where
@@ -56,3 +76,49 @@ pub trait Layout {
     where
         Self: Sized;
 }
+
+/// Layouts that organize their files in a hierarchy of directories.
+///
+/// The [Disk] keeps track of the current directory. Operations on the disk
+/// ([Disk::list_entries], [Disk::create_file] and others) act on the current
+/// directory.
+pub trait DirectoryLayout: Layout {
+    /// Make the given subdirectory the current directory.
+    fn enter_directory(&self, disk: &mut Disk<Self>, file_entry: &Self::FileEntryType)
+    where
+        Self: Sized;
+
+    /// Make the parent of the current directory the current directory.
+    fn leave_directory(&self, disk: &mut Disk<Self>)
+    where
+        Self: Sized;
+
+    /// Create a new subdirectory inside the current directory.
+    fn create_directory(&self, disk: &mut Disk<Self>, name: &Self::StringType)
+    where
+        Self: Sized;
+}
+
+/// Layouts that can be stored as GCR encoded tracks (G64 files).
+pub trait GcrLayout: Layout {
+    /// Speed zone (0-3) used to record the given track. Higher speed zones are
+    /// used for the outer tracks and store more bytes per track.
+    fn speed_zone(&self, track: TrackNo) -> u8;
+
+    /// Disk id that is stored in the header block of each sector.
+    fn get_disk_id(&self, disk: &Disk<Self>) -> [u8; 2]
+    where
+        Self: Sized;
+}
+
+/// Layouts that can be stored in X64 files.
+pub trait X64Layout: Geometry {
+    /// Device type stored in the header of X64 files, for example 0 for the
+    /// Commodore 1541.
+    fn x64_device_type(&self) -> u8;
+
+    /// Number of disk sides stored in the header of X64 files.
+    fn x64_num_sides(&self) -> u8 {
+        1
+    }
+}
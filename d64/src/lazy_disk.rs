@@ -0,0 +1,452 @@
+//! Disk that reads and writes its sectors directly on a block device, instead
+//! of loading the whole image into memory.
+use std::{
+    any::Any,
+    io::{Error, ErrorKind, Read, Seek, Write},
+    marker::PhantomData,
+    ops::Deref,
+    sync::Arc,
+};
+
+use crate::{
+    sector_data::{DeviceGuard, LazySectors},
+    DirectoryLayout, Disk, Geometry, Layout, SectorRef, Track, TrackNo,
+};
+
+/// Disk image stored on a block device, for example a file or a region of
+/// another container. Sectors are fetched from the device when they are
+/// accessed for the first time; the offsets of the sectors are computed from
+/// the geometry of the layout.
+///
+/// The file operations of the layout only read the sectors they use. The
+/// sectors an operation modifies are written to the device when the operation
+/// is done. When a sector can't be read, the operation returns the error and
+/// its modifications are reverted.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use std::io::Cursor;
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.format();
+/// let device = Cursor::new(disk.into_bytes());
+///
+/// let mut lazy_disk = LazyDisk::<Commodore1541, _>::open(device).unwrap();
+/// assert_eq!(lazy_disk.num_tracks(), 35);
+/// let mut sector = lazy_disk.read_sector((18, 0)).unwrap();
+/// assert_eq!(&sector[0x90..0x96], b"NONAME");
+///
+/// sector[0x90..0x96].copy_from_slice(b"LAZY\xa0\xa0");
+/// lazy_disk.write_sector((18, 0), &sector).unwrap();
+///
+/// let disk = Disk::<Commodore1541>::from_bytes(lazy_disk.into_inner().into_inner()).unwrap();
+/// assert_eq!(String::from(&disk.get_name()), "LAZY");
+/// ```
+///
+/// Files are listed, read and written without reading the rest of the image.
+/// Here the device ends after the directory track.
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+/// use petscii::*;
+/// use std::io::Cursor;
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.format();
+/// let mut bytes = disk.into_bytes();
+/// bytes.truncate(0x16500 + 19 * 256);
+///
+/// let mut lazy_disk = LazyDisk::<Commodore1541, _>::open(Cursor::new(bytes)).unwrap();
+/// assert_eq!(lazy_disk.num_unused_sectors().unwrap(), 664);
+/// let file_entry = FileEntry {
+///     name: PetsciiString::from(&String::from("HELLO")),
+///     file_type: FileType::Program,
+///     ..FileEntry::default()
+/// };
+/// lazy_disk.create_file(&file_entry, b"HELLO WORLD").unwrap();
+///
+/// let entries = lazy_disk.list_entries().unwrap();
+/// assert_eq!(String::from(&entries[0].name), "HELLO");
+/// assert_eq!(lazy_disk.read_file(&entries[0]).unwrap(), b"HELLO WORLD");
+///
+/// // A file on a track that isn't stored on the device can't be read.
+/// let mut entry = entries[0].clone();
+/// entry.start_sector = (20, 0);
+/// let error = lazy_disk.read_file(&entry).unwrap_err();
+/// assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+///
+/// // Only the sectors of the image were written to the device.
+/// assert_eq!(lazy_disk.get_ref().get_ref().len(), 0x16500 + 19 * 256);
+/// ```
+pub struct LazyDisk<L, D>
+where
+    L: Geometry,
+{
+    /// Disk that reads its sectors from the device.
+    disk: Disk<L>,
+    /// Offset of the image inside the device.
+    base_offset: u64,
+    device: PhantomData<fn() -> D>,
+}
+
+impl<L, D> LazyDisk<L, D>
+where
+    L: Geometry + Default,
+    D: Read + Write + Seek + Send + 'static,
+{
+    /// Open the image stored at the start of the device.
+    ///
+    /// When the layout stores its size inside the image (see
+    /// [Geometry::read_num_tracks]) the first track is read to determine the
+    /// number of tracks.
+    pub fn open(device: D) -> std::io::Result<Self> {
+        LazyDisk::open_at(device, 0)
+    }
+
+    /// Open the image stored at the given offset of the device.
+    pub fn open_at(device: D, base_offset: u64) -> std::io::Result<Self> {
+        LazyDisk::open_with_layout(L::default(), device, base_offset)
+    }
+
+    /// Open the image stored at the given offset of the device, using the
+    /// given layout instance.
+    pub fn open_with_layout(layout: L, device: D, base_offset: u64) -> std::io::Result<Self> {
+        let num_tracks = layout.num_tracks();
+        let mut lazy_disk =
+            LazyDisk::with_num_tracks(Arc::new(layout), num_tracks, device, base_offset);
+        if lazy_disk.num_tracks() > 0 {
+            let first_track = lazy_disk.read_track(1)?;
+            let first_track = Track::from_slice(&first_track, lazy_disk.bytes_per_sector());
+            if let Some(num_tracks) = lazy_disk.layout().read_num_tracks(&first_track) {
+                let layout = lazy_disk.disk.shared_layout();
+                let device = lazy_disk.into_inner();
+                lazy_disk = LazyDisk::with_num_tracks(layout, num_tracks, device, base_offset);
+            }
+        }
+        Ok(lazy_disk)
+    }
+
+    fn with_num_tracks(layout: Arc<L>, num_tracks: TrackNo, device: D, base_offset: u64) -> Self {
+        LazyDisk {
+            disk: Disk::lazy(layout, num_tracks, Box::new(device), base_offset),
+            base_offset,
+            device: PhantomData,
+        }
+    }
+
+    /// Layout instance used by this disk.
+    pub fn layout(&self) -> &L {
+        self.disk.layout()
+    }
+
+    /// Number of tracks of this disk.
+    pub fn num_tracks(&self) -> TrackNo {
+        self.disk.num_tracks()
+    }
+
+    /// Number of sectors of the given track.
+    pub fn num_sectors(&self, track_no: TrackNo) -> usize {
+        self.track_range(track_no).map_or(0, |range| {
+            (range.end - range.start) / self.bytes_per_sector()
+        })
+    }
+
+    /// Number of bytes stored in a single sector.
+    pub fn bytes_per_sector(&self) -> usize {
+        self.layout().bytes_per_sector() as usize
+    }
+
+    /// Size of the image in bytes.
+    pub fn image_size(&self) -> usize {
+        self.disk.data.len()
+    }
+
+    fn track_range(&self, track_no: TrackNo) -> Option<std::ops::Range<usize>> {
+        (1..=self.num_tracks())
+            .contains(&track_no)
+            .then(|| self.disk.track_range(track_no))
+    }
+
+    /// Offset of the given sector inside the device.
+    pub fn sector_offset(&self, sector_ref: SectorRef) -> std::io::Result<u64> {
+        let range = self
+            .track_range(sector_ref.0)
+            .filter(|range| {
+                range.start + (sector_ref.1 as usize + 1) * self.bytes_per_sector() <= range.end
+            })
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("sector {sector_ref:?} is outside the disk"),
+                )
+            })?;
+        Ok(self.base_offset
+            + (range.start + sector_ref.1 as usize * self.bytes_per_sector()) as u64)
+    }
+
+    /// Read the content of a sector from the device.
+    pub fn read_sector(&mut self, sector_ref: SectorRef) -> std::io::Result<Vec<u8>> {
+        let mut buffer = vec![0; self.bytes_per_sector()];
+        self.read_sector_into(sector_ref, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Read the content of a sector from the device into the given buffer. The
+    /// buffer must have the size of a sector.
+    pub fn read_sector_into(
+        &mut self,
+        sector_ref: SectorRef,
+        buffer: &mut [u8],
+    ) -> std::io::Result<()> {
+        self.check_sector_size(buffer.len())?;
+        self.sector_offset(sector_ref)?;
+        buffer.copy_from_slice(self.disk.get_sector(sector_ref).as_slice());
+        self.check_read()
+    }
+
+    /// Write the content of a sector to the device. The content must have the
+    /// size of a sector.
+    pub fn write_sector(&mut self, sector_ref: SectorRef, content: &[u8]) -> std::io::Result<()> {
+        self.check_sector_size(content.len())?;
+        self.sector_offset(sector_ref)?;
+        self.operate(|disk| disk.get_sector_mut(sector_ref).set_bytes(0, content))
+    }
+
+    fn check_sector_size(&self, size: usize) -> std::io::Result<()> {
+        if size != self.bytes_per_sector() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "buffer doesn't have the size of a sector",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Read the content of all sectors of a track from the device.
+    pub fn read_track(&mut self, track_no: TrackNo) -> std::io::Result<Vec<u8>> {
+        if self.track_range(track_no).is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("track {track_no} is outside the disk"),
+            ));
+        }
+        let track = self.disk.get_track(track_no).as_slice().to_vec();
+        self.check_read()?;
+        Ok(track)
+    }
+
+    /// Return the error of the sectors that couldn't be read, after reverting
+    /// the modifications that were made since.
+    fn check_read(&mut self) -> std::io::Result<()> {
+        if self.disk.data.read_failed() {
+            self.disk.revert_changes();
+        }
+        self.disk.data.take_error().map_or(Ok(()), Err)
+    }
+
+    /// Run an operation on the disk and write the sectors it modified to the
+    /// device.
+    fn operate<T>(&mut self, operation: impl FnOnce(&mut Disk<L>) -> T) -> std::io::Result<T> {
+        let result = operation(&mut self.disk);
+        self.check_read()?;
+        for change in self.disk.changes() {
+            self.lazy_sectors().write(change.offset, &change.content)?;
+        }
+        self.disk.clear_changes();
+        Ok(result)
+    }
+
+    fn lazy_sectors(&mut self) -> &mut LazySectors {
+        self.disk
+            .data
+            .lazy_mut()
+            .expect("sectors of a lazy disk are read from its device")
+    }
+
+    /// Load the whole image into memory. All sectors are read from the device;
+    /// use [LazyDisk::store] to write the disk back.
+    pub fn load(&mut self) -> std::io::Result<Disk<L>> {
+        let bytes = self.disk.as_bytes().to_vec();
+        self.check_read()?;
+        Disk::from_bytes_with_shared_layout(self.disk.shared_layout(), bytes)
+    }
+
+    /// Write all sectors of the given disk to the device.
+    pub fn store(&mut self, disk: &Disk<L>) -> std::io::Result<()> {
+        if disk.as_bytes().len() != self.image_size() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "disk doesn't have the geometry of the device",
+            ));
+        }
+        let lazy_sectors = self.lazy_sectors();
+        lazy_sectors.forget();
+        lazy_sectors.write(0, disk.as_bytes())
+    }
+
+    /// Flush pending writes of the device.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.lazy_sectors().device_mut().flush()
+    }
+
+    /// The device. Sectors can't be read while it is borrowed.
+    pub fn get_ref(&self) -> impl Deref<Target = D> + '_ {
+        let lazy_sectors = self
+            .disk
+            .data
+            .lazy()
+            .expect("sectors of a lazy disk are read from its device");
+        DeviceRef {
+            guard: lazy_sectors.device(),
+            device: PhantomData,
+        }
+    }
+
+    /// The device for modification. Sectors that were already read are read
+    /// again after the device was borrowed.
+    pub fn get_mut(&mut self) -> &mut D {
+        let lazy_sectors = self.lazy_sectors();
+        lazy_sectors.forget();
+        let device: &mut dyn Any = lazy_sectors.device_mut();
+        device
+            .downcast_mut()
+            .expect("device has the type of the disk")
+    }
+
+    /// Consume the disk, returning the device.
+    pub fn into_inner(mut self) -> D {
+        let lazy_sectors = std::mem::take(&mut self.disk.data)
+            .into_lazy()
+            .expect("sectors of a lazy disk are read from its device");
+        let device: Box<dyn Any> = lazy_sectors.into_device();
+        *device.downcast().expect("device has the type of the disk")
+    }
+}
+
+impl<L, D> LazyDisk<L, D>
+where
+    L: Layout + Default,
+    D: Read + Write + Seek + Send + 'static,
+{
+    /// Get the name of the disk. See [Disk::get_name].
+    pub fn get_name(&mut self) -> std::io::Result<L::StringType> {
+        self.operate(|disk| disk.get_name())
+    }
+
+    /// Set the name of the disk. See [Disk::set_name].
+    pub fn set_name(&mut self, new_name: &L::StringType) -> std::io::Result<()> {
+        self.operate(|disk| disk.set_name(new_name))
+    }
+
+    /// Format the disk. See [Disk::format].
+    pub fn format(&mut self) -> std::io::Result<()> {
+        self.operate(|disk| disk.format())
+    }
+
+    /// List the files of the disk. See [Disk::list_entries].
+    pub fn list_entries(&mut self) -> std::io::Result<Vec<L::FileEntryType>> {
+        self.operate(|disk| disk.list_entries())
+    }
+
+    /// Number of sectors that aren't used. See [Disk::num_unused_sectors].
+    pub fn num_unused_sectors(&mut self) -> std::io::Result<usize> {
+        self.operate(|disk| disk.num_unused_sectors())
+    }
+
+    /// Read the content of a file. See [Disk::read_file].
+    pub fn read_file(&mut self, file_entry: &L::FileEntryType) -> std::io::Result<Vec<u8>> {
+        self.operate(|disk| disk.read_file(file_entry))
+    }
+
+    /// Create a file. See [Disk::create_file].
+    pub fn create_file(
+        &mut self,
+        file_entry: &L::FileEntryType,
+        content: &[u8],
+    ) -> std::io::Result<()> {
+        self.operate(|disk| disk.create_file(file_entry, content))
+    }
+
+    /// Delete a file. See [Disk::delete_file].
+    pub fn delete_file(&mut self, file_entry: &L::FileEntryType) -> std::io::Result<()> {
+        self.operate(|disk| disk.delete_file(file_entry))
+    }
+}
+
+impl<L, D> LazyDisk<L, D>
+where
+    L: DirectoryLayout + Default,
+    D: Read + Write + Seek + Send + 'static,
+{
+    /// Header sector of the current directory. See [Disk::current_directory].
+    pub fn current_directory(&self) -> Option<SectorRef> {
+        self.disk.current_directory()
+    }
+
+    /// Make the given subdirectory the current directory. See
+    /// [Disk::enter_directory].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    /// use std::io::Cursor;
+    ///
+    /// let mut disk = Disk::with_num_tracks(CmdNative::default(), 4);
+    /// disk.format();
+    /// let mut bytes = vec![0xff; 1000];
+    /// bytes.extend(disk.into_bytes());
+    ///
+    /// let mut lazy_disk = LazyDisk::<CmdNative, _>::open_at(Cursor::new(bytes), 1000).unwrap();
+    /// assert_eq!(lazy_disk.num_tracks(), 4);
+    /// lazy_disk.create_directory(&PetsciiString::from(&String::from("GAMES"))).unwrap();
+    /// let directory = lazy_disk.list_entries().unwrap()[0].clone();
+    /// lazy_disk.enter_directory(&directory).unwrap();
+    /// assert!(lazy_disk.list_entries().unwrap().is_empty());
+    ///
+    /// let bytes = lazy_disk.into_inner().into_inner();
+    /// let mut disk = Disk::<CmdNative>::from_bytes(bytes[1000..].to_vec()).unwrap();
+    /// assert_eq!(String::from(&disk.list_entries()[0].name), "GAMES");
+    /// ```
+    pub fn enter_directory(&mut self, file_entry: &L::FileEntryType) -> std::io::Result<()> {
+        self.operate(|disk| disk.enter_directory(file_entry))
+    }
+
+    /// Make the parent of the current directory the current directory. See
+    /// [Disk::leave_directory].
+    pub fn leave_directory(&mut self) -> std::io::Result<()> {
+        self.operate(|disk| disk.leave_directory())
+    }
+
+    /// Create a subdirectory inside the current directory. See
+    /// [Disk::create_directory].
+    pub fn create_directory(&mut self, name: &L::StringType) -> std::io::Result<()> {
+        self.operate(|disk| disk.create_directory(name))
+    }
+}
+
+/// Shared access to the device of a [LazyDisk].
+struct DeviceRef<'a, D> {
+    guard: DeviceGuard<'a>,
+    device: PhantomData<&'a D>,
+}
+
+impl<D> Deref for DeviceRef<'_, D>
+where
+    D: 'static,
+{
+    type Target = D;
+
+    fn deref(&self) -> &D {
+        let device: &dyn Any = &*self.guard;
+        device
+            .downcast_ref()
+            .expect("device has the type of the disk")
+    }
+}
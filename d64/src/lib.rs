@@ -12,11 +12,21 @@
 //! sectors of the disk as created by the default ROM of the Commodore 1541
 //! and compatible drives.
 //!
+//! Layouts implementing [X64Layout] can also be stored as X64 files, which
+//! prefix the D64 data with a header. See [Disk::read_x64_from_reader].
+//!
 //! # What about the G64 file?
 //!
-//! G64 file is not supported by this crate. The G64 compared to the D64 file
-//! format wraps sectors with sync headers/footers. Some copy protection and
-//! improved disk formats utilized those sync headers.
+//! The G64 compared to the D64 file format wraps sectors with sync
+//! headers/footers. Some copy protection and improved disk formats utilized
+//! those sync headers.
+//!
+//! Layouts implementing [GcrLayout] can be converted to and from G64 files
+//! using [Disk::encode_g64] and [Disk::decode_g64]. The [gcr] module contains
+//! the GCR encoding used by the Commodore 1541. Raw NIB dumps can be decoded
+//! using [Disk::decode_nib], pulse level P64 images using [Disk::decode_p64]
+//! and [Disk::encode_p64]. [ProtectionReport] finds tracks that deviate from
+//! the standard format, for example because of copy protection.
 //!
 //! # How to use this crate?
 //!
@@ -37,14 +47,41 @@
 //!
 //! disk.write_to_path(&Path::new("../volume1.d64")).unwrap();
 //! ```
+mod custom_geometry;
 mod disk;
+mod g64;
+pub mod gcr;
+pub mod gzip;
 mod layout;
+mod lazy_disk;
+mod nib;
+mod p64;
+mod protection;
+mod raw_track;
+mod save;
 mod sector;
+mod sector_change;
+mod sector_data;
+mod sector_error;
 mod sector_ref;
+mod short_read;
 mod track;
+mod x64;
 
+pub use custom_geometry::*;
 pub use disk::*;
+pub use g64::*;
 pub use layout::*;
+pub use lazy_disk::*;
+pub use nib::*;
+pub use p64::*;
+pub use protection::*;
+pub use raw_track::*;
+pub use save::{backup_path, ImageFile, SaveOptions};
 pub use sector::*;
+pub use sector_change::*;
+pub use sector_error::*;
 pub use sector_ref::*;
+pub use short_read::*;
 pub use track::*;
+pub use x64::*;
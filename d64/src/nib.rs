@@ -0,0 +1,149 @@
+//! NIB files contain the raw GCR stream of each (half) track of a disk, as
+//! captured by nibtools.
+//!
+//! Use [NibImage] to read NIB files, and [Disk::decode_nib] to decode the
+//! tracks into the sectors of a disk.
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Error, ErrorKind, Read},
+    path::Path,
+};
+
+use crate::{gcr, Disk, GcrLayout, HalfTrackNo, RawTrack, SectorError};
+
+const SIGNATURE: &[u8; 13] = b"MNIB-1541-RAW";
+const OFFSET_VERSION: usize = 13;
+const OFFSET_TRACK_ENTRIES: usize = 0x10;
+const HEADER_SIZE: usize = 0x100;
+/// Number of bytes captured for each track.
+pub const NIB_TRACK_SIZE: usize = 0x2000;
+/// Bits of the density byte that contain the speed zone.
+const DENSITY_SPEED_ZONE_MASK: u8 = 0x03;
+
+/// Content of a NIB file.
+#[derive(Debug, Clone, Default)]
+pub struct NibImage {
+    /// Version of the NIB file.
+    pub version: u8,
+    /// Tracks of the image indexed by half track.
+    ///
+    /// Each track contains a single revolution when it could be detected,
+    /// otherwise all captured bytes.
+    pub half_tracks: BTreeMap<HalfTrackNo, RawTrack>,
+}
+
+impl NibImage {
+    /// Load a NIB image from file path.
+    pub fn read_from_path(filename: &Path) -> std::io::Result<NibImage> {
+        let mut file = File::open(filename)?;
+        NibImage::read_from_reader(&mut file)
+    }
+
+    /// Load a NIB image from a reader.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    ///
+    /// let sectors = vec![[0_u8; 256]; 17];
+    /// let sector_refs = sectors.iter().map(|sector| &sector[..]).collect::<Vec<&[u8]>>();
+    /// let track = gcr::encode_track(35, [0x30, 0x31], 0, &sector_refs);
+    ///
+    /// let mut bytes = b"MNIB-1541-RAW\x03\x00\x00".to_vec();
+    /// bytes.extend_from_slice(&[70, 0]);
+    /// bytes.resize(0x100, 0);
+    /// bytes.extend(track.repeat(2).into_iter().take(NIB_TRACK_SIZE));
+    ///
+    /// let image = NibImage::read_from_reader(&mut bytes.as_slice()).unwrap();
+    /// assert_eq!(image.version, 3);
+    /// assert_eq!(image.half_tracks[&70].num_bits(), track.len() * 8);
+    /// ```
+    pub fn read_from_reader<R: Read>(reader: &mut R) -> std::io::Result<NibImage> {
+        let mut header = [0_u8; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        if &header[0..SIGNATURE.len()] != SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "not a NIB file"));
+        }
+
+        let mut half_tracks = BTreeMap::new();
+        for entry in header[OFFSET_TRACK_ENTRIES..].chunks_exact(2) {
+            let half_track_no = entry[0];
+            if half_track_no == 0 {
+                break;
+            }
+            let mut data = vec![0_u8; NIB_TRACK_SIZE];
+            reader.read_exact(&mut data)?;
+
+            let speed_zone = entry[1] & DENSITY_SPEED_ZONE_MASK;
+            let raw_track = match gcr::find_revolution(&data, data.len() * 8) {
+                Some(num_bits) => {
+                    data.truncate(num_bits.div_ceil(8));
+                    RawTrack::from_bits(data, num_bits, speed_zone)
+                }
+                None => RawTrack::from_bytes(data, speed_zone),
+            };
+            half_tracks.insert(half_track_no, raw_track);
+        }
+
+        Ok(NibImage {
+            version: header[OFFSET_VERSION],
+            half_tracks,
+        })
+    }
+}
+
+impl<L> Disk<L>
+where
+    L: GcrLayout + Default,
+{
+    /// Decode the tracks of a NIB image into this disk.
+    ///
+    /// Sectors that cannot be found or that contain errors are recorded in the
+    /// error table of the disk, see [Disk::get_sector_error]. Tracks that cannot
+    /// be represented by sectors are preserved as raw tracks, see
+    /// [Disk::import_raw_track].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// let mut image = NibImage::default();
+    /// for track_no in 1..=35 {
+    ///     image.half_tracks.insert(half_track_no(track_no).unwrap(), disk.encode_raw_track(track_no));
+    /// }
+    /// image.half_tracks.remove(&half_track_no(35).unwrap());
+    ///
+    /// let mut decoded = Disk::<Commodore1541>::new();
+    /// decoded.decode_nib(&image).unwrap();
+    /// assert_eq!(String::from(&decoded.get_name()), "NONAME");
+    /// assert_eq!(decoded.get_sector_error((18, 0)), SectorError::Ok);
+    /// assert_eq!(decoded.get_sector_error((35, 0)), SectorError::NoSync);
+    /// assert_eq!(decoded.sector_errors().count(), 17);
+    /// ```
+    pub fn decode_nib(&mut self, image: &NibImage) -> std::io::Result<()> {
+        self.clear_raw_tracks();
+        self.clear_sector_errors();
+        for track_no in 1..=self.num_tracks() {
+            let num_sectors = self.layout().num_sectors(track_no);
+            for sector_no in 0..num_sectors {
+                self.set_sector_error((track_no, sector_no as u8), SectorError::NoSync);
+            }
+        }
+        for (half_track_no, raw_track) in &image.half_tracks {
+            self.import_raw_track(*half_track_no, raw_track.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Load the sectors of a NIB file.
+    pub fn read_nib_from_path(&mut self, filename: &Path) -> std::io::Result<()> {
+        let image = NibImage::read_from_path(filename)?;
+        self.decode_nib(&image)
+    }
+}
@@ -0,0 +1,528 @@
+//! P64 files contain the flux transitions (pulses) of each half track of a
+//! disk, as they pass the head of the disk drive.
+//!
+//! Use [P64Image] to read and write P64 files, and [Disk::encode_p64] and
+//! [Disk::decode_p64] to convert between the pulses and the sectors of a
+//! disk. Pulses are converted into GCR bits using the bit rate of the speed
+//! zone of the track; see [PulseStream::to_raw_track].
+//!
+//! The range encoded pulse streams haven't been compared with P64 files
+//! written by other programs yet.
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use crate::{gzip::crc32, half_track_no, Disk, GcrLayout, HalfTrackNo, RawTrack};
+
+const SIGNATURE: &[u8; 8] = b"P64-1541";
+const VERSION: u32 = 0;
+const FLAG_WRITE_PROTECTED: u32 = 1;
+const HEADER_SIZE: usize = 24;
+const CHUNK_HEADER_SIZE: usize = 12;
+const CHUNK_HALF_TRACK: &[u8; 3] = b"HTP";
+const CHUNK_DONE: &[u8; 4] = b"DONE";
+
+/// Number of pulse positions of a single rotation (16 MHz at 300 RPM).
+pub const P64_SAMPLES_PER_ROTATION: u32 = 3_200_000;
+/// Strength of a pulse that is always detected.
+pub const P64_PULSE_STRENGTH_STRONG: u32 = 0xffff_ffff;
+
+/// Single flux transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pulse {
+    /// Position within the rotation. See [P64_SAMPLES_PER_ROTATION].
+    pub position: u32,
+    /// Probability the pulse is detected.
+    pub strength: u32,
+}
+
+/// Pulses of a single half track, ordered by position.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PulseStream {
+    pub pulses: Vec<Pulse>,
+}
+
+/// Number of samples of a single bit cell in the given speed zone.
+fn samples_per_bit(speed_zone: u8) -> u32 {
+    4 * (16 - (speed_zone as u32 & 3))
+}
+
+impl PulseStream {
+    /// Create strong pulses for each one bit of the raw track. Bits are placed
+    /// at the bit rate of the speed zone of the raw track. When the raw track
+    /// doesn't fit in a single rotation at that bit rate, the bits are evenly
+    /// spread over the rotation.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    ///
+    /// let raw_track = RawTrack::from_bytes(vec![0x55; 6250], 0);
+    /// let pulse_stream = PulseStream::from_raw_track(&raw_track);
+    /// assert_eq!(pulse_stream.pulses.len(), 6250 * 4);
+    /// assert_eq!(pulse_stream.to_raw_track(0), raw_track);
+    /// ```
+    pub fn from_raw_track(raw_track: &RawTrack) -> PulseStream {
+        let num_bits = raw_track.num_bits() as u64;
+        let samples_per_bit = samples_per_bit(raw_track.speed_zone()) as u64;
+        let rotation = P64_SAMPLES_PER_ROTATION as u64;
+        let bytes = raw_track.as_bytes();
+        let pulses = (0..num_bits)
+            .filter(|bit| (bytes[*bit as usize / 8] >> (7 - bit % 8)) & 1 == 1)
+            .map(|bit| {
+                let position = if num_bits * samples_per_bit <= rotation {
+                    bit * samples_per_bit
+                } else {
+                    bit * rotation / num_bits
+                };
+                Pulse {
+                    position: position as u32,
+                    strength: P64_PULSE_STRENGTH_STRONG,
+                }
+            })
+            .collect();
+        PulseStream { pulses }
+    }
+
+    /// Convert the pulses into GCR bits using the bit rate of the given speed
+    /// zone.
+    pub fn to_raw_track(&self, speed_zone: u8) -> RawTrack {
+        let samples_per_bit = samples_per_bit(speed_zone);
+        let num_bits = P64_SAMPLES_PER_ROTATION.div_ceil(samples_per_bit) as usize;
+        let mut data = vec![0_u8; num_bits.div_ceil(8)];
+        for pulse in &self.pulses {
+            let bit =
+                ((pulse.position + samples_per_bit / 2) / samples_per_bit) as usize % num_bits;
+            data[bit / 8] |= 0x80 >> (bit % 8);
+        }
+        RawTrack::from_bits(data, num_bits, speed_zone)
+    }
+}
+
+/// Content of a P64 file.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+///
+/// let mut image = P64Image::default();
+/// let pulses = vec![
+///     Pulse { position: 100, strength: P64_PULSE_STRENGTH_STRONG },
+///     Pulse { position: 164, strength: P64_PULSE_STRENGTH_STRONG },
+///     Pulse { position: 3000, strength: 0x8000_0000 },
+/// ];
+/// image.half_tracks.insert(2, PulseStream { pulses });
+///
+/// let mut bytes = Vec::new();
+/// image.write_to_writer(&mut bytes).unwrap();
+///
+/// let read_image = P64Image::read_from_reader(&mut bytes.as_slice()).unwrap();
+/// assert_eq!(read_image.half_tracks, image.half_tracks);
+///
+/// // Pulses of half track 2: number of pulses, size of the range encoded
+/// // data, followed by the data.
+/// assert_eq!(&bytes[24..28], b"HTP\x02");
+/// assert_eq!(&bytes[36..44], &[3, 0, 0, 0, 24, 0, 0, 0]);
+/// assert_eq!(
+///     &bytes[44..68],
+///     &[
+///         77, 255, 255, 255, 128, 0, 0, 0, 25, 132, 94, 33, 234, 130, 55, 97, 136, 217, 14, 232,
+///         108, 5, 255, 255
+///     ]
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct P64Image {
+    pub write_protected: bool,
+    /// Pulses of each half track.
+    pub half_tracks: BTreeMap<HalfTrackNo, PulseStream>,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+impl P64Image {
+    /// Load a P64 image from file path.
+    pub fn read_from_path(filename: &Path) -> std::io::Result<P64Image> {
+        let mut file = File::open(filename)?;
+        P64Image::read_from_reader(&mut file)
+    }
+
+    /// Load a P64 image from a reader.
+    pub fn read_from_reader<R: Read>(reader: &mut R) -> std::io::Result<P64Image> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() < HEADER_SIZE || &bytes[0..8] != SIGNATURE {
+            return Err(invalid_data("not a P64 file"));
+        }
+        let flags = read_u32(&bytes, 12);
+        let size = read_u32(&bytes, 16) as usize;
+        let content = bytes
+            .get(HEADER_SIZE..HEADER_SIZE + size)
+            .ok_or_else(|| invalid_data("P64 file is truncated"))?;
+        if crc32(content) != read_u32(&bytes, 20) {
+            return Err(invalid_data("P64 checksum mismatch"));
+        }
+
+        let mut image = P64Image {
+            write_protected: flags & FLAG_WRITE_PROTECTED != 0,
+            half_tracks: BTreeMap::new(),
+        };
+        let mut offset = 0;
+        while offset + CHUNK_HEADER_SIZE <= content.len() {
+            let signature = &content[offset..offset + 4];
+            let chunk_size = read_u32(content, offset + 4) as usize;
+            let chunk = content
+                .get(offset + CHUNK_HEADER_SIZE..offset + CHUNK_HEADER_SIZE + chunk_size)
+                .ok_or_else(|| invalid_data("P64 chunk is truncated"))?;
+            if crc32(chunk) != read_u32(content, offset + 8) {
+                return Err(invalid_data("P64 chunk checksum mismatch"));
+            }
+            if signature == CHUNK_DONE {
+                break;
+            }
+            if &signature[0..3] == CHUNK_HALF_TRACK {
+                image
+                    .half_tracks
+                    .insert(signature[3], decode_pulse_stream(chunk)?);
+            }
+            offset += CHUNK_HEADER_SIZE + chunk_size;
+        }
+        Ok(image)
+    }
+
+    pub fn write_to_path(&self, filename: &Path) -> std::io::Result<()> {
+        let mut file = File::create(filename)?;
+        self.write_to_writer(&mut file)
+    }
+
+    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut content = Vec::new();
+        for (half_track_no, pulse_stream) in &self.half_tracks {
+            let mut signature = [0_u8; 4];
+            signature[0..3].copy_from_slice(CHUNK_HALF_TRACK);
+            signature[3] = *half_track_no;
+            write_chunk(&mut content, &signature, &encode_pulse_stream(pulse_stream));
+        }
+        write_chunk(&mut content, CHUNK_DONE, &[]);
+
+        let flags = if self.write_protected {
+            FLAG_WRITE_PROTECTED
+        } else {
+            0
+        };
+        writer.write_all(SIGNATURE)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&flags.to_le_bytes())?;
+        writer.write_all(&(content.len() as u32).to_le_bytes())?;
+        writer.write_all(&crc32(&content).to_le_bytes())?;
+        writer.write_all(&content)
+    }
+}
+
+fn write_chunk(content: &mut Vec<u8>, signature: &[u8; 4], chunk: &[u8]) {
+    content.extend_from_slice(signature);
+    content.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+    content.extend_from_slice(&crc32(chunk).to_le_bytes());
+    content.extend_from_slice(chunk);
+}
+
+/// Probability models used to encode pulse streams. Each model is used with a
+/// context of 16 bits.
+const MODEL_POSITION: usize = 0;
+const MODEL_STRENGTH: usize = 4;
+const MODEL_POSITION_FLAG: usize = 8;
+const MODEL_STRENGTH_FLAG: usize = 9;
+const NUM_MODELS: usize = 10;
+const MODEL_SIZE: usize = 1 << 16;
+const PROBABILITY_BITS: u32 = 12;
+const PROBABILITY_INITIAL: u32 = 1 << (PROBABILITY_BITS - 1);
+const PROBABILITY_SHIFT: u32 = 4;
+
+/// Adaptive binary range coder used to compress pulse streams.
+struct RangeCoder {
+    probabilities: Vec<u32>,
+    low: u32,
+    high: u32,
+    code: u32,
+}
+
+impl RangeCoder {
+    fn new() -> RangeCoder {
+        RangeCoder {
+            probabilities: vec![PROBABILITY_INITIAL; NUM_MODELS * MODEL_SIZE],
+            low: 0,
+            high: 0xffff_ffff,
+            code: 0,
+        }
+    }
+
+    fn middle(&self, probability: usize) -> u32 {
+        let range = (self.high - self.low) as u64;
+        self.low + ((range * self.probabilities[probability] as u64) >> PROBABILITY_BITS) as u32
+    }
+
+    fn update(&mut self, probability: usize, bit: bool) {
+        let value = &mut self.probabilities[probability];
+        if bit {
+            *value += ((1 << PROBABILITY_BITS) - 1 - *value) >> PROBABILITY_SHIFT;
+        } else {
+            *value -= *value >> PROBABILITY_SHIFT;
+        }
+    }
+
+    fn encode_bit(&mut self, output: &mut Vec<u8>, probability: usize, bit: bool) {
+        let middle = self.middle(probability);
+        self.update(probability, bit);
+        if bit {
+            self.high = middle;
+        } else {
+            self.low = middle + 1;
+        }
+        while (self.low ^ self.high) & 0xff00_0000 == 0 {
+            output.push((self.high >> 24) as u8);
+            self.low <<= 8;
+            self.high = (self.high << 8) | 0xff;
+        }
+    }
+
+    fn flush(&mut self, output: &mut Vec<u8>) {
+        output.extend_from_slice(&self.high.to_be_bytes());
+    }
+
+    fn start_decoding(&mut self, input: &mut impl Iterator<Item = u8>) {
+        for _ in 0..4 {
+            self.code = (self.code << 8) | input.next().unwrap_or(0) as u32;
+        }
+    }
+
+    fn decode_bit(&mut self, input: &mut impl Iterator<Item = u8>, probability: usize) -> bool {
+        let middle = self.middle(probability);
+        let bit = self.code <= middle;
+        self.update(probability, bit);
+        if bit {
+            self.high = middle;
+        } else {
+            self.low = middle + 1;
+        }
+        while (self.low ^ self.high) & 0xff00_0000 == 0 {
+            self.low <<= 8;
+            self.high = (self.high << 8) | 0xff;
+            self.code = (self.code << 8) | input.next().unwrap_or(0) as u32;
+        }
+        bit
+    }
+
+    /// Probability of a flag. Each flag uses the first entry of its own model.
+    fn flag_probability(model: usize) -> usize {
+        model * MODEL_SIZE
+    }
+
+    /// Probability of a bit of a 32 bit value. Each byte uses its own model;
+    /// the context is the previous byte and the bits of the current byte.
+    fn value_probability(model: usize, byte_index: usize, previous_byte: u8, node: u32) -> usize {
+        (model + byte_index) * MODEL_SIZE + ((previous_byte as usize) << 8) + node as usize
+    }
+
+    fn encode_value(&mut self, output: &mut Vec<u8>, model: usize, value: u32) {
+        let mut previous_byte = 0;
+        for (byte_index, byte) in value.to_le_bytes().into_iter().enumerate() {
+            let mut node = 1;
+            for bit_index in (0..8).rev() {
+                let bit = (byte >> bit_index) & 1 == 1;
+                let probability = Self::value_probability(model, byte_index, previous_byte, node);
+                self.encode_bit(output, probability, bit);
+                node = (node << 1) | bit as u32;
+            }
+            previous_byte = byte;
+        }
+    }
+
+    fn decode_value(&mut self, input: &mut impl Iterator<Item = u8>, model: usize) -> u32 {
+        let mut bytes = [0_u8; 4];
+        let mut previous_byte = 0;
+        for (byte_index, byte) in bytes.iter_mut().enumerate() {
+            let mut node = 1;
+            for _ in 0..8 {
+                let probability = Self::value_probability(model, byte_index, previous_byte, node);
+                node = (node << 1) | self.decode_bit(input, probability) as u32;
+            }
+            *byte = node as u8;
+            previous_byte = *byte;
+        }
+        u32::from_le_bytes(bytes)
+    }
+}
+
+/// Encode the pulses of a half track chunk: number of pulses, size of the
+/// encoded data and the range encoded position and strength deltas.
+fn encode_pulse_stream(pulse_stream: &PulseStream) -> Vec<u8> {
+    let mut coder = RangeCoder::new();
+    let mut data = Vec::new();
+    let mut last_position = 0;
+    let mut last_delta = 0;
+    let mut last_strength = 0;
+    for pulse in &pulse_stream.pulses {
+        let delta = pulse.position.wrapping_sub(last_position);
+        coder.encode_bit(
+            &mut data,
+            RangeCoder::flag_probability(MODEL_POSITION_FLAG),
+            delta != last_delta,
+        );
+        if delta != last_delta {
+            coder.encode_value(&mut data, MODEL_POSITION, delta);
+            last_delta = delta;
+        }
+        coder.encode_bit(
+            &mut data,
+            RangeCoder::flag_probability(MODEL_STRENGTH_FLAG),
+            pulse.strength != last_strength,
+        );
+        if pulse.strength != last_strength {
+            coder.encode_value(
+                &mut data,
+                MODEL_STRENGTH,
+                pulse.strength.wrapping_sub(last_strength),
+            );
+            last_strength = pulse.strength;
+        }
+        last_position = pulse.position;
+    }
+    coder.flush(&mut data);
+
+    let mut result = Vec::with_capacity(data.len() + 8);
+    result.extend_from_slice(&(pulse_stream.pulses.len() as u32).to_le_bytes());
+    result.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    result.extend(data);
+    result
+}
+
+fn decode_pulse_stream(chunk: &[u8]) -> std::io::Result<PulseStream> {
+    if chunk.len() < 8 {
+        return Err(invalid_data("P64 half track chunk is truncated"));
+    }
+    let num_pulses = read_u32(chunk, 0) as usize;
+    let size = read_u32(chunk, 4) as usize;
+    let data = chunk
+        .get(8..8 + size)
+        .ok_or_else(|| invalid_data("P64 half track chunk is truncated"))?;
+
+    let mut input = data.iter().copied();
+    let mut coder = RangeCoder::new();
+    coder.start_decoding(&mut input);
+    let mut pulses = Vec::with_capacity(num_pulses);
+    let mut position = 0_u32;
+    let mut delta = 0;
+    let mut strength = 0_u32;
+    for _ in 0..num_pulses {
+        if coder.decode_bit(
+            &mut input,
+            RangeCoder::flag_probability(MODEL_POSITION_FLAG),
+        ) {
+            delta = coder.decode_value(&mut input, MODEL_POSITION);
+        }
+        if coder.decode_bit(
+            &mut input,
+            RangeCoder::flag_probability(MODEL_STRENGTH_FLAG),
+        ) {
+            strength = strength.wrapping_add(coder.decode_value(&mut input, MODEL_STRENGTH));
+        }
+        position = position.wrapping_add(delta);
+        if position >= P64_SAMPLES_PER_ROTATION {
+            return Err(invalid_data("P64 pulse is outside of the rotation"));
+        }
+        pulses.push(Pulse { position, strength });
+    }
+    Ok(PulseStream { pulses })
+}
+
+impl<L> Disk<L>
+where
+    L: GcrLayout + Default,
+{
+    /// Encode the disk into pulses.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// disk.set_name(&PetsciiString::from(&String::from("PULSES")));
+    ///
+    /// let mut bytes = Vec::new();
+    /// disk.encode_p64().unwrap().write_to_writer(&mut bytes).unwrap();
+    ///
+    /// let image = P64Image::read_from_reader(&mut bytes.as_slice()).unwrap();
+    /// let mut decoded = Disk::<Commodore1541>::new();
+    /// decoded.decode_p64(&image).unwrap();
+    /// assert_eq!(String::from(&decoded.get_name()), "PULSES");
+    /// assert_eq!(decoded.num_unused_sectors(), 664);
+    /// assert_eq!(decoded.sector_errors().count(), 0);
+    /// ```
+    pub fn encode_p64(&self) -> std::io::Result<P64Image> {
+        let mut image = P64Image::default();
+        for (half_track_no, raw_track) in self.raw_tracks() {
+            image
+                .half_tracks
+                .insert(half_track_no, PulseStream::from_raw_track(raw_track));
+        }
+        for track_no in 1..=self.num_tracks() {
+            let half_track_no = half_track_no(track_no).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("track {track_no} cannot be stored in a P64 file"),
+                )
+            })?;
+            image.half_tracks.insert(
+                half_track_no,
+                PulseStream::from_raw_track(&self.encode_raw_track(track_no)),
+            );
+        }
+        Ok(image)
+    }
+
+    /// Decode the pulses into this disk.
+    ///
+    /// Pulses are converted into GCR bits using the speed zone of the track
+    /// (or the track below a half track). The GCR bits are imported as raw
+    /// tracks; see [Disk::import_raw_track].
+    pub fn decode_p64(&mut self, image: &P64Image) -> std::io::Result<()> {
+        self.clear_raw_tracks();
+        self.clear_sector_errors();
+        for (half_track_no, pulse_stream) in &image.half_tracks {
+            let speed_zone = self.layout().speed_zone((*half_track_no / 2).max(1));
+            self.import_raw_track(*half_track_no, pulse_stream.to_raw_track(speed_zone))?;
+        }
+        Ok(())
+    }
+
+    /// Load the sectors of a P64 file.
+    pub fn read_p64_from_path(&mut self, filename: &Path) -> std::io::Result<()> {
+        let image = P64Image::read_from_path(filename)?;
+        self.decode_p64(&image)
+    }
+
+    /// Store the disk as a P64 file.
+    pub fn write_p64_to_path(&self, filename: &Path) -> std::io::Result<()> {
+        self.encode_p64()?.write_to_path(filename)
+    }
+}
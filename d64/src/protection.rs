@@ -0,0 +1,232 @@
+//! Scanner that looks for copy protection schemes on raw tracks.
+//!
+//! Use [ProtectionReport::scan] (or [G64Image::scan_protection] and
+//! [NibImage::scan_protection]) to find out which tracks of a raw image
+//! deviate from the standard format of a layout. Images without deviations can
+//! be reduced to the sectors of a disk without loss of information.
+use std::ops::RangeInclusive;
+
+use crate::{
+    gcr::{self, BitReader, GcrBlock},
+    track_no, G64Image, GcrLayout, HalfTrackNo, NibImage, RawTrack, SectorNo,
+};
+
+/// Number of bits of sync marks that are written by a standard drive.
+pub const STANDARD_SYNC_BITS: RangeInclusive<usize> = 32..=80;
+/// Tracks are long when they are more than this percentage longer than the
+/// capacity of their speed zone.
+pub const LONG_TRACK_TOLERANCE_PERCENT: usize = 2;
+
+/// Analysis of a single (half) track.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrackReport {
+    pub half_track_no: HalfTrackNo,
+    /// Number of bits of the track.
+    pub num_bits: usize,
+    /// Speed zone the track is recorded with.
+    pub speed_zone: u8,
+    /// Speed zone of the track according to the layout. None for half tracks
+    /// and tracks beyond the last track of the layout.
+    pub expected_speed_zone: Option<u8>,
+    /// Number of different sectors found on the track.
+    pub num_sectors: usize,
+    /// Number of sectors of the track according to the layout.
+    pub expected_num_sectors: usize,
+    /// Sectors with a header block checksum error.
+    pub header_checksum_errors: Vec<SectorNo>,
+    /// Sectors with a data block checksum error.
+    pub data_checksum_errors: Vec<SectorNo>,
+    /// Sectors whose header block is found more than once.
+    pub duplicate_sectors: Vec<SectorNo>,
+    /// Number of header blocks that refer to another track.
+    pub foreign_headers: usize,
+    /// Number of blocks that are neither header nor data blocks.
+    pub unknown_blocks: usize,
+    /// Shortest and longest sync mark in bits. None when no sync marks are
+    /// found.
+    pub sync_bits: Option<RangeInclusive<usize>>,
+    /// The track only contains sync, which hangs the drive when reading it.
+    pub killer_track: bool,
+    /// The track is longer than the capacity of its speed zone.
+    pub long_track: bool,
+}
+
+impl TrackReport {
+    /// Analyse a raw track for the given layout.
+    pub fn scan<L: GcrLayout>(
+        layout: &L,
+        half_track_no: HalfTrackNo,
+        raw_track: &RawTrack,
+    ) -> TrackReport {
+        let (expected_speed_zone, expected_num_sectors) = match track_no(half_track_no) {
+            Some(track_no) if (1..=layout.num_tracks()).contains(&track_no) => (
+                Some(layout.speed_zone(track_no)),
+                layout.num_sectors(track_no) as usize,
+            ),
+            _ => (None, 0),
+        };
+        let mut report = TrackReport {
+            half_track_no,
+            num_bits: raw_track.num_bits(),
+            speed_zone: raw_track.speed_zone(),
+            expected_speed_zone,
+            expected_num_sectors,
+            ..TrackReport::default()
+        };
+
+        let reader = BitReader::new(raw_track.as_bytes(), raw_track.num_bits());
+        let syncs = reader.find_syncs();
+        let has_zero_bit = (0..raw_track.num_bits()).any(|position| reader.bit(position) == 0);
+        let longest_sync = syncs.iter().map(|(_, num_bits)| *num_bits).max();
+        report.killer_track = report.num_bits > 0
+            && (!has_zero_bit || longest_sync.unwrap_or(0) * 2 > report.num_bits);
+        report.sync_bits = longest_sync.map(|longest_sync| {
+            let shortest_sync = syncs
+                .iter()
+                .map(|(_, num_bits)| *num_bits)
+                .min()
+                .unwrap_or(0);
+            shortest_sync..=longest_sync
+        });
+
+        let capacity = gcr::TRACK_CAPACITY[report.speed_zone as usize & 3] * 8;
+        report.long_track = report.num_bits * 100 > capacity * (100 + LONG_TRACK_TOLERANCE_PERCENT);
+
+        for (position, _) in &syncs {
+            if reader.read_block(*position) == GcrBlock::Unknown {
+                report.unknown_blocks += 1;
+            }
+        }
+
+        let track_no = track_no(half_track_no).unwrap_or(0);
+        let mut found = Vec::<SectorNo>::new();
+        for sector in raw_track.decode_sectors() {
+            let sector_no = sector.header.sector;
+            if sector.header.track != track_no {
+                report.foreign_headers += 1;
+                continue;
+            }
+            if !sector.header.checksum_ok {
+                report.header_checksum_errors.push(sector_no);
+                continue;
+            }
+            if sector.data.is_some() && !sector.data_checksum_ok {
+                report.data_checksum_errors.push(sector_no);
+            }
+            if found.contains(&sector_no) {
+                if !report.duplicate_sectors.contains(&sector_no) {
+                    report.duplicate_sectors.push(sector_no);
+                }
+            } else {
+                found.push(sector_no);
+            }
+        }
+        report.num_sectors = found.len();
+        report
+    }
+
+    /// Is the recorded speed zone different from the speed zone of the layout.
+    pub fn density_mismatch(&self) -> bool {
+        self.expected_speed_zone
+            .is_some_and(|expected_speed_zone| expected_speed_zone != self.speed_zone)
+    }
+
+    /// Does the track contain sync marks that a standard drive doesn't write.
+    pub fn non_standard_sync(&self) -> bool {
+        self.sync_bits.as_ref().is_some_and(|sync_bits| {
+            !STANDARD_SYNC_BITS.contains(sync_bits.start())
+                || !STANDARD_SYNC_BITS.contains(sync_bits.end())
+        })
+    }
+
+    /// Does the track follow the standard format of the layout. Half tracks
+    /// and tracks beyond the layout are standard when they are unformatted.
+    pub fn is_standard(&self) -> bool {
+        if self.expected_speed_zone.is_none() {
+            return self.sync_bits.is_none() && !self.killer_track;
+        }
+        self.num_sectors == self.expected_num_sectors
+            && self.header_checksum_errors.is_empty()
+            && self.data_checksum_errors.is_empty()
+            && self.duplicate_sectors.is_empty()
+            && self.foreign_headers == 0
+            && self.unknown_blocks == 0
+            && !self.killer_track
+            && !self.long_track
+            && !self.density_mismatch()
+            && !self.non_standard_sync()
+    }
+}
+
+/// Analysis of all tracks of a raw image.
+#[derive(Debug, Clone, Default)]
+pub struct ProtectionReport {
+    pub tracks: Vec<TrackReport>,
+}
+
+impl ProtectionReport {
+    /// Analyse the given raw tracks for the given layout.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// let mut image = disk.encode_g64().unwrap();
+    /// assert!(image.scan_protection::<Commodore1541>().can_reduce_to_sectors());
+    ///
+    /// image.set_track(36, RawTrack::from_bytes(vec![0xff; 6250], 0)).unwrap();
+    /// let report = image.scan_protection::<Commodore1541>();
+    /// assert!(!report.can_reduce_to_sectors());
+    /// let track = report.non_standard_tracks().next().unwrap();
+    /// assert_eq!(Some(track.half_track_no), half_track_no(36));
+    /// assert!(track.killer_track);
+    /// ```
+    pub fn scan<'a, L, I>(tracks: I) -> ProtectionReport
+    where
+        L: GcrLayout + Default,
+        I: IntoIterator<Item = (HalfTrackNo, &'a RawTrack)>,
+    {
+        let layout = L::default();
+        ProtectionReport {
+            tracks: tracks
+                .into_iter()
+                .map(|(half_track_no, raw_track)| {
+                    TrackReport::scan(&layout, half_track_no, raw_track)
+                })
+                .collect(),
+        }
+    }
+
+    /// Tracks that don't follow the standard format.
+    pub fn non_standard_tracks(&self) -> impl Iterator<Item = &TrackReport> {
+        self.tracks.iter().filter(|track| !track.is_standard())
+    }
+
+    /// Can the image be stored as sectors (for example a D64 file) without
+    /// loss of information.
+    pub fn can_reduce_to_sectors(&self) -> bool {
+        self.non_standard_tracks().next().is_none()
+    }
+}
+
+impl G64Image {
+    /// Analyse the tracks of this image for the given layout.
+    pub fn scan_protection<L: GcrLayout + Default>(&self) -> ProtectionReport {
+        ProtectionReport::scan::<L, _>(self.tracks())
+    }
+}
+
+impl NibImage {
+    /// Analyse the tracks of this image for the given layout.
+    pub fn scan_protection<L: GcrLayout + Default>(&self) -> ProtectionReport {
+        ProtectionReport::scan::<L, _>(
+            self.half_tracks
+                .iter()
+                .map(|(half_track_no, raw_track)| (*half_track_no, raw_track)),
+        )
+    }
+}
@@ -0,0 +1,371 @@
+//! Raw tracks contain the bit stream of a track as it passes the head of the
+//! disk drive.
+//!
+//! The sector model ([Track] and [Sector](crate::Sector)) can only contain
+//! data that is readable by the DOS of the drive. Copy protections often use
+//! half tracks, tracks of unusual length or non standard blocks. Those can be
+//! preserved by storing the raw tracks on the [Disk] as well.
+use std::{
+    collections::BTreeMap,
+    io::{Error, ErrorKind},
+};
+
+use crate::{
+    gcr::{self, DecodedSector},
+    Disk, GcrLayout, Geometry, HalfTrackNo, SectorError, Track, TrackMut, TrackNo, G64_HALF_TRACKS,
+};
+
+/// Half track of the given track. None when the half track doesn't fit in a
+/// [HalfTrackNo], for tracks above 127.
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+///
+/// assert_eq!(half_track_no(18), Some(36));
+/// assert_eq!(half_track_no(127), Some(254));
+/// assert_eq!(half_track_no(128), None);
+/// ```
+pub fn half_track_no(track_no: TrackNo) -> Option<HalfTrackNo> {
+    track_no.checked_mul(2)
+}
+
+/// Track of the given half track. None for half tracks between two tracks.
+pub fn track_no(half_track_no: HalfTrackNo) -> Option<TrackNo> {
+    if half_track_no.is_multiple_of(2) {
+        Some(half_track_no / 2)
+    } else {
+        None
+    }
+}
+
+/// Bit stream of a single (half) track.
+///
+/// The bit stream is circular and doesn't need to be a multiple of 8 bits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RawTrack {
+    data: Vec<u8>,
+    num_bits: usize,
+    speed_zone: u8,
+}
+
+impl RawTrack {
+    /// Create a raw track using all bits of the given bytes.
+    pub fn from_bytes(data: Vec<u8>, speed_zone: u8) -> RawTrack {
+        let num_bits = data.len() * 8;
+        RawTrack::from_bits(data, num_bits, speed_zone)
+    }
+
+    /// Create a raw track using the first num_bits bits of the given bytes.
+    pub fn from_bits(data: Vec<u8>, num_bits: usize, speed_zone: u8) -> RawTrack {
+        RawTrack {
+            num_bits: num_bits.min(data.len() * 8),
+            data,
+            speed_zone,
+        }
+    }
+
+    /// Encode sectors into a raw track using the standard 1541 format.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    ///
+    /// let sectors = vec![[0x12_u8; 256]; 17];
+    /// let sector_refs = sectors.iter().map(|sector| &sector[..]).collect::<Vec<&[u8]>>();
+    /// let raw_track = RawTrack::encode(35, [0x30, 0x31], 0, &sector_refs);
+    ///
+    /// let decoded = raw_track.decode_sectors();
+    /// assert_eq!(decoded.len(), 17);
+    /// assert_eq!(decoded[16].header.sector, 16);
+    /// assert_eq!(decoded[16].data.as_ref().unwrap()[..], sectors[16][..]);
+    /// ```
+    pub fn encode(
+        track_no: TrackNo,
+        disk_id: [u8; 2],
+        speed_zone: u8,
+        sectors: &[&[u8]],
+    ) -> RawTrack {
+        RawTrack::from_bytes(
+            gcr::encode_track(track_no, disk_id, speed_zone, sectors),
+            speed_zone,
+        )
+    }
+
+    /// GCR encoded bytes of the track. The last byte can be partially used.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.data.as_slice()
+    }
+
+    /// Number of bits of the track.
+    pub fn num_bits(&self) -> usize {
+        self.num_bits
+    }
+
+    /// Speed zone (0-3) the track is recorded with.
+    pub fn speed_zone(&self) -> u8 {
+        self.speed_zone
+    }
+
+    /// Decode all sectors that can be found on the track.
+    pub fn decode_sectors(&self) -> Vec<DecodedSector> {
+        gcr::decode_sectors(&self.data, self.num_bits)
+    }
+
+    /// Error of each sector of the track as the 1541 would report when
+    /// reading the sector.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    ///
+    /// let sectors = vec![[0_u8; 256]; 2];
+    /// let sector_refs = sectors.iter().map(|sector| &sector[..]).collect::<Vec<&[u8]>>();
+    /// let raw_track = RawTrack::encode(35, [0x30, 0x31], 0, &sector_refs);
+    /// assert_eq!(
+    ///     raw_track.sector_errors(35, 3),
+    ///     vec![SectorError::Ok, SectorError::Ok, SectorError::HeaderNotFound]
+    /// );
+    ///
+    /// let empty_track = RawTrack::from_bytes(vec![0x55; 6250], 0);
+    /// assert_eq!(empty_track.sector_errors(35, 1), vec![SectorError::NoSync]);
+    /// ```
+    pub fn sector_errors(&self, track_no: TrackNo, num_sectors: usize) -> Vec<SectorError> {
+        let decoded_sectors = self.decode_sectors();
+        let no_header = if gcr::decode_blocks(&self.data, self.num_bits).is_empty() {
+            SectorError::NoSync
+        } else {
+            SectorError::HeaderNotFound
+        };
+        let mut result = vec![no_header; num_sectors];
+        for sector in decoded_sectors {
+            let sector_no = sector.header.sector as usize;
+            if sector.header.track != track_no
+                || sector_no >= num_sectors
+                || result[sector_no] == SectorError::Ok
+            {
+                continue;
+            }
+            result[sector_no] = if !sector.header.checksum_ok {
+                SectorError::HeaderChecksum
+            } else if sector.data.is_none() {
+                SectorError::DataBlockNotFound
+            } else if !sector.data_checksum_ok {
+                SectorError::DataChecksum
+            } else {
+                SectorError::Ok
+            };
+        }
+        result
+    }
+
+    /// Does the track only contain the given number of sectors in the standard
+    /// format, without errors. Such a track can be stored as sectors without
+    /// loss of information.
+    pub fn is_standard(&self, track_no: TrackNo, num_sectors: usize) -> bool {
+        let num_blocks = gcr::decode_blocks(&self.data, self.num_bits).len();
+        num_blocks == num_sectors * 2
+            && self
+                .sector_errors(track_no, num_sectors)
+                .iter()
+                .all(|sector_error| *sector_error == SectorError::Ok)
+    }
+}
+
+impl Track<'_> {
+    /// Encode the sectors of this track into a raw track.
+    pub fn encode_raw(&self, track_no: TrackNo, disk_id: [u8; 2], speed_zone: u8) -> RawTrack {
+        let sectors = self
+            .sectors()
+            .map(|sector| sector.as_slice())
+            .collect::<Vec<&[u8]>>();
+        RawTrack::encode(track_no, disk_id, speed_zone, &sectors)
+    }
+}
+
+impl TrackMut<'_> {
+    /// Store the sectors found on the raw track that belong to this track.
+    ///
+    /// Returns the number of sectors that were decoded. Sectors that could not be
+    /// found are left untouched.
+    pub fn decode_raw(&mut self, track_no: TrackNo, raw_track: &RawTrack) -> usize {
+        let mut num_decoded = 0;
+        for sector in raw_track.decode_sectors() {
+            if sector.header.track != track_no
+                || sector.header.sector as usize >= self.num_sectors()
+            {
+                continue;
+            }
+            if let Some(data) = sector.data {
+                self.get_sector_mut(sector.header.sector)
+                    .set_bytes(0, &data);
+                num_decoded += 1;
+            }
+        }
+        num_decoded
+    }
+}
+
+fn check_half_track_no(half_track_no: HalfTrackNo) -> std::io::Result<()> {
+    if G64_HALF_TRACKS.contains(&half_track_no) {
+        Ok(())
+    } else {
+        Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("half track {half_track_no} is outside the tracks of a G64 file"),
+        ))
+    }
+}
+
+/// Raw tracks stored on a disk, indexed by half track.
+pub(crate) type RawTracks = BTreeMap<HalfTrackNo, RawTrack>;
+
+impl<L> Disk<L>
+where
+    L: Geometry + Sized + Default,
+{
+    /// Get the raw track stored for the given half track.
+    pub fn get_raw_track(&self, half_track_no: HalfTrackNo) -> Option<&RawTrack> {
+        self.raw_tracks.get(&half_track_no)
+    }
+
+    /// Store a raw track on the disk.
+    ///
+    /// Raw tracks are preserved as is. They are not updated when sectors of
+    /// the disk are modified; use [Disk::remove_raw_track] or
+    /// [Disk::clear_raw_tracks] to let the sectors take precedence again.
+    ///
+    /// Fails for half tracks outside [G64_HALF_TRACKS], which cannot be
+    /// stored in a G64 file.
+    pub fn set_raw_track(
+        &mut self,
+        half_track_no: HalfTrackNo,
+        raw_track: RawTrack,
+    ) -> std::io::Result<()> {
+        check_half_track_no(half_track_no)?;
+        self.raw_tracks.insert(half_track_no, raw_track);
+        Ok(())
+    }
+
+    /// Remove the raw track of the given half track.
+    pub fn remove_raw_track(&mut self, half_track_no: HalfTrackNo) -> Option<RawTrack> {
+        self.raw_tracks.remove(&half_track_no)
+    }
+
+    /// Remove all raw tracks of the disk.
+    pub fn clear_raw_tracks(&mut self) {
+        self.raw_tracks.clear();
+    }
+
+    /// All raw tracks stored on the disk, ordered by half track.
+    pub fn raw_tracks(&self) -> impl Iterator<Item = (HalfTrackNo, &RawTrack)> {
+        self.raw_tracks
+            .iter()
+            .map(|(half_track_no, raw_track)| (*half_track_no, raw_track))
+    }
+}
+
+impl<L> Disk<L>
+where
+    L: GcrLayout + Default,
+{
+    /// Get the raw track of the given track. When no raw track is stored on
+    /// the disk it is encoded from the sectors of the track.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    ///
+    /// let raw_track = disk.encode_raw_track(18);
+    /// let mut copy = Disk::<Commodore1541>::new();
+    /// assert_eq!(copy.decode_raw_track(18, &raw_track), 19);
+    /// assert_eq!(String::from(&copy.get_name()), "NONAME");
+    /// ```
+    pub fn encode_raw_track(&self, track_no: TrackNo) -> RawTrack {
+        if let Some(raw_track) = half_track_no(track_no).and_then(|half| self.get_raw_track(half)) {
+            return raw_track.clone();
+        }
+        let layout = self.layout();
+        self.get_track(track_no).encode_raw(
+            track_no,
+            layout.get_disk_id(self),
+            layout.speed_zone(track_no),
+        )
+    }
+
+    /// Decode the sectors of a raw track into the given track of the disk.
+    ///
+    /// Returns the number of sectors that were decoded.
+    pub fn decode_raw_track(&mut self, track_no: TrackNo, raw_track: &RawTrack) -> usize {
+        self.get_track_mut(track_no).decode_raw(track_no, raw_track)
+    }
+
+    /// Import a raw track into the disk.
+    ///
+    /// The sectors found on the track are decoded into the disk. The raw track
+    /// itself is preserved on the disk when the sectors cannot represent it:
+    /// half tracks, tracks beyond the last track of the disk and tracks that
+    /// contain non standard, missing or damaged sectors. Errors of the sectors
+    /// are stored in the error table of the disk, see [Disk::get_sector_error].
+    ///
+    /// Returns true when the raw track is preserved. Fails for half tracks
+    /// outside [G64_HALF_TRACKS], without changing the disk.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// let raw_track = disk.encode_raw_track(18);
+    /// let half_track_18 = half_track_no(18).unwrap();
+    ///
+    /// let mut copy = Disk::<Commodore1541>::new();
+    /// assert!(!copy.import_raw_track(half_track_18, raw_track.clone()).unwrap());
+    /// assert!(copy.import_raw_track(half_track_18 + 1, raw_track.clone()).unwrap());
+    /// assert!(copy.import_raw_track(1, raw_track.clone()).is_err());
+    /// assert!(copy.import_raw_track(86, raw_track).is_err());
+    /// assert_eq!(copy.raw_tracks().count(), 1);
+    /// assert!(copy.encode_g64().is_ok());
+    /// ```
+    pub fn import_raw_track(
+        &mut self,
+        half_track_no: HalfTrackNo,
+        raw_track: RawTrack,
+    ) -> std::io::Result<bool> {
+        check_half_track_no(half_track_no)?;
+        let track_no = match track_no(half_track_no) {
+            Some(track_no) if (1..=self.num_tracks()).contains(&track_no) => track_no,
+            _ => {
+                self.set_raw_track(half_track_no, raw_track)?;
+                return Ok(true);
+            }
+        };
+        self.decode_raw_track(track_no, &raw_track);
+        let num_sectors = self.get_track(track_no).num_sectors();
+        for (sector_no, sector_error) in raw_track
+            .sector_errors(track_no, num_sectors)
+            .into_iter()
+            .enumerate()
+        {
+            self.set_sector_error((track_no, sector_no as u8), sector_error);
+        }
+        if raw_track.is_standard(track_no, num_sectors) {
+            self.remove_raw_track(half_track_no);
+            Ok(false)
+        } else {
+            self.set_raw_track(half_track_no, raw_track)?;
+            Ok(true)
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    gzip::{self, crc32},
+    Disk, Geometry,
+};
+
+/// Identifies the content of a file a disk was loaded from or saved to, to
+/// detect modifications by other programs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FileStamp {
+    path: PathBuf,
+    len: u64,
+    checksum: u32,
+}
+
+impl FileStamp {
+    pub(crate) fn new(filename: &Path, content: &[u8]) -> FileStamp {
+        FileStamp {
+            path: fs::canonicalize(filename).unwrap_or_else(|_| filename.to_path_buf()),
+            len: content.len() as u64,
+            checksum: crc32(content),
+        }
+    }
+
+    /// Stamp of the current content of the given file.
+    pub(crate) fn read(filename: &Path) -> std::io::Result<FileStamp> {
+        Ok(FileStamp::new(filename, &fs::read(filename)?))
+    }
+
+    fn refers_to(&self, filename: &Path) -> bool {
+        fs::canonicalize(filename).is_ok_and(|path| path == self.path)
+    }
+}
+
+/// Disk image read from a file. Gzip compressed images are decompressed.
+///
+/// Allows inspecting the image, for example its size, before deciding which
+/// layout to load it with. See [Disk::from_image_file].
+pub struct ImageFile {
+    file_stamp: FileStamp,
+    bytes: Vec<u8>,
+}
+
+impl ImageFile {
+    /// Read the disk image stored at the given file path.
+    pub fn read(filename: &Path) -> std::io::Result<ImageFile> {
+        let mut bytes = fs::read(filename)?;
+        let file_stamp = FileStamp::new(filename, &bytes);
+        if gzip::is_gzip(&bytes) {
+            bytes = gzip::decompress(&bytes)?;
+        }
+        Ok(ImageFile { file_stamp, bytes })
+    }
+
+    /// Content of the (decompressed) disk image.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(crate) fn into_parts(self) -> (FileStamp, Vec<u8>) {
+        (self.file_stamp, self.bytes)
+    }
+}
+
+/// Options of [Disk::save_to_path].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SaveOptions {
+    /// Keep the previous content of the file as a backup, by adding `.bak` to
+    /// the file name.
+    pub keep_backup: bool,
+    /// Overwrite the file, even when it was modified since the disk was
+    /// loaded from or saved to it.
+    pub overwrite_modified: bool,
+}
+
+/// Path of the backup of the given file: `disk.d64` becomes `disk.d64.bak`.
+pub fn backup_path(filename: &Path) -> PathBuf {
+    let mut backup = filename.as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+/// Path of the temporary file used to save the given file.
+fn temporary_path(filename: &Path) -> PathBuf {
+    let name = filename
+        .file_name()
+        .map_or(String::new(), |name| name.to_string_lossy().to_string());
+    filename.with_file_name(format!(".{name}.{}.tmp", std::process::id()))
+}
+
+/// Make a rename inside the directory durable. Not all platforms allow to sync
+/// a directory, so failures are ignored.
+fn sync_directory(filename: &Path) {
+    let directory = match filename.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    if let Ok(directory) = File::open(directory) {
+        let _ = directory.sync_all();
+    }
+}
+
+impl<L> Disk<L>
+where
+    L: Geometry + Sized + Default,
+{
+    /// Store the disk image at the given file path without the risk of leaving
+    /// a partially written file behind.
+    ///
+    /// The image is written to a temporary file in the same directory, which is
+    /// synced and renamed over the original file. When the file was modified
+    /// by another program since the disk was loaded from or saved to it, the
+    /// save is refused, unless [SaveOptions::overwrite_modified] is set.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    ///
+    /// let path = std::env::temp_dir().join("d64-save-to-path.d64");
+    /// let options = SaveOptions {
+    ///     keep_backup: true,
+    ///     ..SaveOptions::default()
+    /// };
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// disk.save_to_path(&path, options).unwrap();
+    ///
+    /// disk.set_name(&PetsciiString::from(&String::from("SECOND")));
+    /// disk.save_to_path(&path, options).unwrap();
+    /// let mut backup = Disk::<Commodore1541>::new();
+    /// backup.read_from_path(&backup_path(&path)).unwrap();
+    /// assert_eq!(String::from(&backup.get_name()), "NONAME");
+    ///
+    /// // Another program modifies the file.
+    /// let mut other = Disk::<Commodore1541>::new();
+    /// other.read_from_path(&path).unwrap();
+    /// other.set_name(&PetsciiString::from(&String::from("OTHER")));
+    /// other.write_to_path(&path).unwrap();
+    /// assert!(disk.save_to_path(&path, options).is_err());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// # std::fs::remove_file(&backup_path(&path)).unwrap();
+    /// ```
+    pub fn save_to_path(&mut self, filename: &Path, options: SaveOptions) -> std::io::Result<()> {
+        let exists = filename.exists();
+        if exists && !options.overwrite_modified {
+            if let Some(file_stamp) = self.file_stamp.as_ref() {
+                if file_stamp.refers_to(filename) && *file_stamp != FileStamp::read(filename)? {
+                    return Err(Error::other(
+                        "file was modified since the disk was loaded or saved",
+                    ));
+                }
+            }
+        }
+
+        let bytes = self.file_content(filename);
+        let temporary = temporary_path(filename);
+        let result = (|| {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temporary)?;
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+            drop(file);
+            if exists && options.keep_backup {
+                fs::copy(filename, backup_path(filename))?;
+            }
+            fs::rename(&temporary, filename)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temporary);
+        }
+        result?;
+        sync_directory(filename);
+
+        self.file_stamp = Some(FileStamp::new(filename, &bytes));
+        self.original_sectors.clear();
+        Ok(())
+    }
+}
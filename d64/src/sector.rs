@@ -1,22 +1,21 @@
-use std::io::{Read, Write};
-
-#[derive(Default)]
+/// Content of a single sector. A sector is a view into the bytes of the disk
+/// it belongs to, see [crate::Disk::get_sector].
+#[repr(transparent)]
 pub struct Sector {
-    data: Vec<u8>,
+    data: [u8],
 }
 
 impl Sector {
-    pub fn initialize(&mut self, data_len: usize) {
-        self.data.clear();
-        self.data.resize(data_len, 0);
+    /// View the given bytes as a sector.
+    pub fn from_slice(bytes: &[u8]) -> &Sector {
+        // SAFETY: Sector is a transparent wrapper around [u8].
+        unsafe { &*(bytes as *const [u8] as *const Sector) }
     }
 
-    pub fn read_from_reader<R: Read>(&mut self, reader: &mut R) -> std::io::Result<usize> {
-        // TODO raise error when not all bytes could be read.
-        reader.read(self.data.as_mut_slice())
-    }
-    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<usize> {
-        writer.write(self.data.as_slice())
+    /// View the given bytes as a sector for modification.
+    pub fn from_slice_mut(bytes: &mut [u8]) -> &mut Sector {
+        // SAFETY: Sector is a transparent wrapper around [u8].
+        unsafe { &mut *(bytes as *mut [u8] as *mut Sector) }
     }
 
     pub fn get_byte(&self, offset: usize) -> &u8 {
@@ -26,23 +25,20 @@ impl Sector {
         self.data[offset] = byte;
     }
     pub fn get_bytes(&self, offset: usize, result: &mut [u8]) {
-        for (i, item) in result.iter_mut().enumerate() {
-            let byte = *self.get_byte(offset + i);
-            *item = byte;
-        }
+        result.copy_from_slice(&self.data[offset..offset + result.len()]);
     }
     pub fn set_bytes(&mut self, offset: usize, result: &[u8]) {
-        for (i, item) in result.iter().enumerate() {
-            self.set_byte(offset + i, *item);
-        }
+        self.data[offset..offset + result.len()].copy_from_slice(result);
     }
     pub fn fill(&mut self, start_offset: usize, end_offset: usize, byte: u8) {
-        for offset in start_offset..end_offset {
-            self.set_byte(offset, byte);
-        }
+        self.data[start_offset..end_offset].fill(byte);
     }
 
     pub fn as_slice(&self) -> &[u8] {
-        &self.data.as_slice()
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.data
     }
 }
@@ -0,0 +1,246 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    fs::OpenOptions,
+    io::{Error, ErrorKind, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{save::FileStamp, Disk, Geometry, SectorRef};
+
+/// Modification of a single sector of a disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectorChange {
+    pub sector_ref: SectorRef,
+    /// Offset of the sector inside the image.
+    pub offset: usize,
+    /// Content of the sector before it was modified.
+    pub original: Vec<u8>,
+    /// Content of the sector after it was modified.
+    pub content: Vec<u8>,
+}
+
+/// Content of the sectors of the disk before they were modified.
+///
+/// The first time a sector is borrowed for modification since the disk was
+/// loaded or saved, its content is appended to a single buffer. The position
+/// of each copy is kept by the index of the sector in the image.
+#[derive(Debug, Default)]
+pub(crate) struct OriginalSectors {
+    snapshots: Vec<u8>,
+    positions: BTreeMap<usize, usize>,
+}
+
+impl OriginalSectors {
+    /// Forget the content of the borrowed sectors.
+    pub(crate) fn clear(&mut self) {
+        self.snapshots.clear();
+        self.positions.clear();
+    }
+
+    /// Remember the content of the given consecutive sectors, starting with
+    /// the sector with the given index, that weren't borrowed before.
+    pub(crate) fn remember(
+        &mut self,
+        sectors: &[u8],
+        bytes_per_sector: usize,
+        first_sector_index: usize,
+    ) {
+        for (sector_index, sector) in
+            (first_sector_index..).zip(sectors.chunks_exact(bytes_per_sector))
+        {
+            if let Entry::Vacant(entry) = self.positions.entry(sector_index) {
+                entry.insert(self.snapshots.len());
+                self.snapshots.extend_from_slice(sector);
+            }
+        }
+    }
+
+    /// Indices of the borrowed sectors, in image order.
+    fn sector_indices(&self) -> impl Iterator<Item = usize> + '_ {
+        self.positions.keys().copied()
+    }
+
+    /// Content of the given sector before it was borrowed.
+    fn original(&self, sector_index: usize, bytes_per_sector: usize) -> &[u8] {
+        let position = self.positions[&sector_index];
+        &self.snapshots[position..position + bytes_per_sector]
+    }
+}
+
+impl<L> Disk<L>
+where
+    L: Geometry + Sized + Default,
+{
+    /// Sectors whose content changed since the disk was loaded or saved,
+    /// ordered by sector.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// disk.clear_changes();
+    /// assert_eq!(disk.num_unused_sectors(), 664);
+    /// assert!(disk.modified_sectors().is_empty());
+    ///
+    /// disk.set_name(&PetsciiString::from(&String::from("RENAMED")));
+    /// assert_eq!(disk.modified_sectors(), vec![(18, 0)]);
+    /// ```
+    pub fn modified_sectors(&self) -> Vec<SectorRef> {
+        let sector_refs = self.all_sector_refs();
+        self.original_sectors
+            .sector_indices()
+            .map(|sector_index| sector_refs[sector_index])
+            .filter(|sector_ref| {
+                self.get_sector(*sector_ref).as_slice() != self.original(*sector_ref)
+            })
+            .collect()
+    }
+
+    /// Content of the given sector when the disk was loaded or saved.
+    fn original(&self, sector_ref: SectorRef) -> &[u8] {
+        let bytes_per_sector = self.get_sector(sector_ref).as_slice().len();
+        let sector_index = self.sector_offset(sector_ref) / bytes_per_sector;
+        self.original_sectors
+            .original(sector_index, bytes_per_sector)
+    }
+
+    /// Changes of the sectors since the disk was loaded or saved, ordered by
+    /// sector. The changes can be applied to another copy of the image using
+    /// [Disk::apply_changes].
+    pub fn changes(&self) -> Vec<SectorChange> {
+        self.modified_sectors()
+            .into_iter()
+            .map(|sector_ref| SectorChange {
+                sector_ref,
+                offset: self.sector_offset(sector_ref),
+                original: self.original(sector_ref).to_vec(),
+                content: self.get_sector(sector_ref).as_slice().to_vec(),
+            })
+            .collect()
+    }
+
+    /// Forget the changes, making the current content the reference for
+    /// [Disk::modified_sectors].
+    pub fn clear_changes(&mut self) {
+        self.original_sectors.clear();
+    }
+
+    /// Restore the content the modified sectors had when the disk was loaded
+    /// or saved.
+    pub(crate) fn revert_changes(&mut self) {
+        let sector_refs = self.all_sector_refs();
+        let original_sectors = std::mem::take(&mut self.original_sectors);
+        for sector_index in original_sectors.sector_indices() {
+            let sector = self.get_sector_mut(sector_refs[sector_index]);
+            let original = original_sectors.original(sector_index, sector.as_slice().len());
+            sector.set_bytes(0, original);
+        }
+        self.original_sectors.clear();
+    }
+
+    /// Apply changes, for example recorded on another copy of the image.
+    ///
+    /// Fails without modifying the disk when a sector of the disk doesn't
+    /// contain the original content of its change.
+    pub fn apply_changes(&mut self, changes: &[SectorChange]) -> std::io::Result<()> {
+        for change in changes {
+            if change.sector_ref.0 == 0
+                || change.sector_ref.0 > self.num_tracks()
+                || change.sector_ref.1 as usize >= self.get_track(change.sector_ref.0).num_sectors()
+                || self.get_sector(change.sector_ref).as_slice() != change.original.as_slice()
+                || change.content.len() != change.original.len()
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "change of sector {:?} doesn't match the disk",
+                        change.sector_ref
+                    ),
+                ));
+            }
+        }
+        for change in changes {
+            self.get_sector_mut(change.sector_ref)
+                .set_bytes(0, &change.content);
+        }
+        Ok(())
+    }
+
+    /// Write the modified sectors into an existing image. The writer must
+    /// contain the image as it was when the disk was loaded or saved.
+    pub fn write_changes_to_writer<W: Write + Seek>(&self, writer: &mut W) -> std::io::Result<()> {
+        for change in self.changes() {
+            writer.seek(SeekFrom::Start(change.offset as u64))?;
+            writer.write_all(&change.content)?;
+        }
+        writer.flush()
+    }
+
+    /// Write only the modified sectors into the existing image at the given
+    /// file path, which must be the file the disk was loaded from or saved to.
+    /// Fails when it isn't, or when another program modified the file since.
+    ///
+    /// Returns the number of sectors that were written.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    /// use petscii::*;
+    ///
+    /// let path = std::env::temp_dir().join("d64-write-changes-to-path.d64");
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// disk.write_to_path(&path).unwrap();
+    ///
+    /// disk.set_name(&PetsciiString::from(&String::from("RENAMED")));
+    /// assert_eq!(disk.write_changes_to_path(&path).unwrap(), 1);
+    /// assert!(disk.modified_sectors().is_empty());
+    ///
+    /// let mut loaded = Disk::<Commodore1541>::new();
+    /// loaded.read_from_path(&path).unwrap();
+    /// assert_eq!(String::from(&loaded.get_name()), "RENAMED");
+    ///
+    /// // Another program modifies the file.
+    /// loaded.set_name(&PetsciiString::from(&String::from("OTHER")));
+    /// loaded.write_to_path(&path).unwrap();
+    /// disk.set_name(&PetsciiString::from(&String::from("THIRD")));
+    /// assert!(disk.write_changes_to_path(&path).is_err());
+    ///
+    /// // Changes are only written to the file the disk belongs to.
+    /// let other_path = std::env::temp_dir().join("d64-write-changes-to-other-path.d64");
+    /// Disk::<Commodore1541>::new().write_to_path(&other_path).unwrap();
+    /// assert!(disk.write_changes_to_path(&other_path).is_err());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// # std::fs::remove_file(&other_path).unwrap();
+    /// ```
+    pub fn write_changes_to_path(&mut self, filename: &Path) -> std::io::Result<usize> {
+        if filename
+            .extension()
+            .is_some_and(|extension| extension == "gz")
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "compressed images can only be written as a whole",
+            ));
+        }
+        if self.file_stamp != Some(FileStamp::read(filename)?) {
+            return Err(Error::other(
+                "file isn't the file the disk was loaded from or saved to, or was modified since",
+            ));
+        }
+        let mut file = OpenOptions::new().write(true).open(filename)?;
+        let num_changes = self.modified_sectors().len();
+        self.write_changes_to_writer(&mut file)?;
+        file.sync_data()?;
+        self.clear_changes();
+        self.file_stamp = FileStamp::read(filename).ok();
+        Ok(num_changes)
+    }
+}
@@ -0,0 +1,340 @@
+//! Storage of the content of all sectors of a disk.
+use std::{
+    any::Any,
+    cell::Cell,
+    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
+    ops::{Deref, Range},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use crate::{disk::read_fully, SectorRef, ShortRead};
+
+/// Block device the sectors of a lazy disk are read from and written to. See
+/// [crate::LazyDisk].
+pub(crate) trait Device: Read + Write + Seek + Send + Any {}
+
+impl<D> Device for D where D: Read + Write + Seek + Send + Any {}
+
+/// Content of all sectors of a disk, stored track after track.
+pub(crate) enum SectorData {
+    /// All sectors are stored in memory.
+    Loaded(Vec<u8>),
+    /// Sectors are read from a device when they are accessed for the first
+    /// time.
+    Lazy(LazySectors),
+}
+
+impl Default for SectorData {
+    fn default() -> Self {
+        SectorData::Loaded(Vec::new())
+    }
+}
+
+impl SectorData {
+    /// Size of the content in bytes.
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            SectorData::Loaded(bytes) => bytes.len(),
+            SectorData::Lazy(lazy) => lazy.bytes.len(),
+        }
+    }
+
+    /// Content of the given range of bytes.
+    pub(crate) fn get(&self, range: Range<usize>) -> &[u8] {
+        match self {
+            SectorData::Loaded(bytes) => &bytes[range],
+            SectorData::Lazy(lazy) => lazy.get(range),
+        }
+    }
+
+    /// Content of the given range of bytes for modification.
+    pub(crate) fn get_mut(&mut self, range: Range<usize>) -> &mut [u8] {
+        match self {
+            SectorData::Loaded(bytes) => &mut bytes[range],
+            SectorData::Lazy(lazy) => lazy.get_mut(range),
+        }
+    }
+
+    /// Content of all sectors.
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        self.get(0..self.len())
+    }
+
+    /// Content of all sectors for modification.
+    pub(crate) fn as_mut_slice(&mut self) -> &mut [u8] {
+        let len = self.len();
+        self.get_mut(0..len)
+    }
+
+    /// Buffer that contains all sectors, to change its size. The sectors of a
+    /// lazy disk are read from the device and no longer written to it.
+    pub(crate) fn vec_mut(&mut self) -> &mut Vec<u8> {
+        if let SectorData::Lazy(lazy) = self {
+            *self = SectorData::Loaded(lazy.as_slice().to_vec());
+        }
+        match self {
+            SectorData::Loaded(bytes) => bytes,
+            SectorData::Lazy(_) => unreachable!(),
+        }
+    }
+
+    /// Consume the storage, returning the content of all sectors.
+    pub(crate) fn into_vec(self) -> Vec<u8> {
+        match self {
+            SectorData::Loaded(bytes) => bytes,
+            SectorData::Lazy(lazy) => lazy.as_slice().to_vec(),
+        }
+    }
+
+    /// Lazily read sectors, None when all sectors are stored in memory.
+    pub(crate) fn lazy(&self) -> Option<&LazySectors> {
+        match self {
+            SectorData::Loaded(_) => None,
+            SectorData::Lazy(lazy) => Some(lazy),
+        }
+    }
+
+    /// Consume the storage, returning the lazily read sectors.
+    pub(crate) fn into_lazy(self) -> Option<LazySectors> {
+        match self {
+            SectorData::Loaded(_) => None,
+            SectorData::Lazy(lazy) => Some(lazy),
+        }
+    }
+
+    /// Lazily read sectors for modification.
+    pub(crate) fn lazy_mut(&mut self) -> Option<&mut LazySectors> {
+        match self {
+            SectorData::Loaded(_) => None,
+            SectorData::Lazy(lazy) => Some(lazy),
+        }
+    }
+
+    /// Whether a sector couldn't be read from the device since the last
+    /// [SectorData::take_error].
+    pub(crate) fn read_failed(&mut self) -> bool {
+        self.lazy_mut().is_some_and(|lazy| {
+            let device = lazy
+                .device
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner);
+            device.error.is_some()
+        })
+    }
+
+    /// Error that occurred while sectors were read from the device. The
+    /// sectors that couldn't be read are read again when they are accessed.
+    pub(crate) fn take_error(&mut self) -> Option<Error> {
+        self.lazy_mut().and_then(LazySectors::take_error)
+    }
+}
+
+/// Sectors of a disk that are read from a device on first access.
+///
+/// Sectors that weren't read yet contain zeros. A sector is read before a
+/// reference to it is handed out, and is never read again while the storage
+/// is shared. Reading a sector through a shared reference therefore only
+/// changes bytes that aren't borrowed.
+pub(crate) struct LazySectors {
+    bytes: Box<[Cell<u8>]>,
+    bytes_per_sector: usize,
+    sector_refs: Vec<SectorRef>,
+    device: Mutex<LazyDevice>,
+}
+
+struct LazyDevice {
+    device: Box<dyn Device>,
+    /// Offset of the image inside the device.
+    base_offset: u64,
+    /// Whether each sector was read from the device, by index in the image.
+    loaded: Vec<bool>,
+    /// Sectors that couldn't be read. They are handed out as zeros.
+    failed: Vec<usize>,
+    /// First error that occurred while reading.
+    error: Option<Error>,
+}
+
+impl LazyDevice {
+    /// Remember the first error that occurred while reading.
+    fn fail(&mut self, error: Error) {
+        self.error.get_or_insert(error);
+    }
+}
+
+// SAFETY: the bytes are only written through a shared reference while the
+// device is locked, and only for sectors that weren't read yet. No reference
+// to those sectors exists until they are marked as read.
+unsafe impl Sync for LazySectors {}
+
+impl LazySectors {
+    /// Sectors with the given references, stored at the given offset of the
+    /// device.
+    pub(crate) fn new(
+        device: Box<dyn Device>,
+        base_offset: u64,
+        sector_refs: Vec<SectorRef>,
+        bytes_per_sector: usize,
+    ) -> LazySectors {
+        LazySectors {
+            bytes: vec![Cell::new(0); sector_refs.len() * bytes_per_sector].into_boxed_slice(),
+            bytes_per_sector,
+            device: Mutex::new(LazyDevice {
+                device,
+                base_offset,
+                loaded: vec![false; sector_refs.len()],
+                failed: Vec::new(),
+                error: None,
+            }),
+            sector_refs,
+        }
+    }
+
+    fn get(&self, range: Range<usize>) -> &[u8] {
+        let mut device = self.device.lock().unwrap_or_else(PoisonError::into_inner);
+        self.read_sectors(&mut device, &range);
+        // SAFETY: all sectors of the range were read, so their bytes aren't
+        // written anymore while the storage is shared.
+        unsafe { &*(&self.bytes[range] as *const [Cell<u8>] as *const [u8]) }
+    }
+
+    fn get_mut(&mut self, range: Range<usize>) -> &mut [u8] {
+        let mut device = self.device.lock().unwrap_or_else(PoisonError::into_inner);
+        self.read_sectors(&mut device, &range);
+        drop(device);
+        // SAFETY: Cell<u8> has the same layout as u8, and the storage is
+        // borrowed exclusively.
+        unsafe { &mut *(&mut self.bytes[range] as *mut [Cell<u8>] as *mut [u8]) }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        self.get(0..self.bytes.len())
+    }
+
+    /// Read the sectors of the given range of bytes that weren't read yet.
+    fn read_sectors(&self, device: &mut LazyDevice, range: &Range<usize>) {
+        let first_sector = range.start / self.bytes_per_sector;
+        let end_sector = range.end.div_ceil(self.bytes_per_sector);
+        let mut sector_index = first_sector;
+        while sector_index < end_sector {
+            if device.loaded[sector_index] {
+                sector_index += 1;
+                continue;
+            }
+            let num_sectors = device.loaded[sector_index..end_sector]
+                .iter()
+                .take_while(|loaded| !**loaded)
+                .count();
+            self.read_run(device, sector_index..sector_index + num_sectors);
+            sector_index += num_sectors;
+        }
+    }
+
+    /// Read consecutive sectors that weren't read yet with a single read.
+    fn read_run(&self, device: &mut LazyDevice, sector_indices: Range<usize>) {
+        let start = sector_indices.start * self.bytes_per_sector;
+        let end = sector_indices.end * self.bytes_per_sector;
+        let cells = &self.bytes[start..end];
+        // SAFETY: the sectors weren't read yet, so no reference to their
+        // bytes exists, and the device is locked.
+        let buffer =
+            unsafe { std::slice::from_raw_parts_mut(cells.as_ptr() as *mut u8, cells.len()) };
+        let result = device
+            .device
+            .seek(SeekFrom::Start(device.base_offset + start as u64))
+            .and_then(|_| read_fully(&mut device.device, buffer));
+        let num_sectors_read = match result {
+            Ok(bytes_read) if bytes_read < buffer.len() => {
+                let num_sectors_read = bytes_read / self.bytes_per_sector;
+                buffer[num_sectors_read * self.bytes_per_sector..].fill(0);
+                device.fail(Error::new(
+                    ErrorKind::UnexpectedEof,
+                    ShortRead {
+                        sector_ref: self.sector_refs[sector_indices.start + num_sectors_read],
+                        bytes_read: bytes_read % self.bytes_per_sector,
+                        bytes_per_sector: self.bytes_per_sector,
+                    },
+                ));
+                num_sectors_read
+            }
+            Ok(_) => sector_indices.len(),
+            Err(error) => {
+                buffer.fill(0);
+                device.fail(error);
+                0
+            }
+        };
+        device
+            .failed
+            .extend(sector_indices.start + num_sectors_read..sector_indices.end);
+        device.loaded[sector_indices].fill(true);
+    }
+
+    fn take_error(&mut self) -> Option<Error> {
+        let device = self
+            .device
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for sector_index in device.failed.drain(..) {
+            device.loaded[sector_index] = false;
+        }
+        device.error.take()
+    }
+
+    /// Write the given bytes to the device at the given offset of the image.
+    /// The bytes must already be stored in the sectors.
+    pub(crate) fn write(&mut self, offset: usize, content: &[u8]) -> std::io::Result<()> {
+        let device = self
+            .device
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        device
+            .device
+            .seek(SeekFrom::Start(device.base_offset + offset as u64))?;
+        device.device.write_all(content)
+    }
+
+    /// Forget the content of all sectors, so they are read from the device
+    /// again.
+    pub(crate) fn forget(&mut self) {
+        self.bytes.iter_mut().for_each(|byte| *byte.get_mut() = 0);
+        let device = self
+            .device
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        device.loaded.fill(false);
+        device.failed.clear();
+    }
+
+    /// Device the sectors are read from. Sectors can't be read while the
+    /// device is borrowed.
+    pub(crate) fn device(&self) -> DeviceGuard<'_> {
+        DeviceGuard(self.device.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub(crate) fn device_mut(&mut self) -> &mut dyn Device {
+        let device = self
+            .device
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        device.device.as_mut()
+    }
+
+    pub(crate) fn into_device(self) -> Box<dyn Device> {
+        let device = self
+            .device
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        device.device
+    }
+}
+
+/// Shared access to the device of [LazySectors].
+pub(crate) struct DeviceGuard<'a>(MutexGuard<'a, LazyDevice>);
+
+impl Deref for DeviceGuard<'_> {
+    type Target = dyn Device;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.device.as_ref()
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+
+use crate::SectorRef;
+
+/// Error of a sector found when reading it from the physical media.
+///
+/// The values are the codes used by the error table of D64 files. The DOS
+/// error numbers are mentioned in the documentation of each variant.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub enum SectorError {
+    /// No error (00, OK).
+    #[default]
+    Ok = 0x01,
+    /// Header block of the sector could not be found (20, READ ERROR).
+    HeaderNotFound = 0x02,
+    /// No sync mark found on the track (21, READ ERROR).
+    NoSync = 0x03,
+    /// Data block of the sector could not be found (22, READ ERROR).
+    DataBlockNotFound = 0x04,
+    /// Checksum of the data block doesn't match (23, READ ERROR).
+    DataChecksum = 0x05,
+    /// Checksum of the header block doesn't match (27, READ ERROR).
+    HeaderChecksum = 0x09,
+    /// Disk id of the header block doesn't match the disk (29, DISK ID MISMATCH).
+    DiskIdMismatch = 0x0b,
+}
+
+impl From<u8> for SectorError {
+    fn from(src: u8) -> SectorError {
+        match src {
+            0x02 => SectorError::HeaderNotFound,
+            0x03 => SectorError::NoSync,
+            0x04 => SectorError::DataBlockNotFound,
+            0x05 => SectorError::DataChecksum,
+            0x09 => SectorError::HeaderChecksum,
+            0x0b => SectorError::DiskIdMismatch,
+            _ => SectorError::Ok,
+        }
+    }
+}
+
+impl From<SectorError> for u8 {
+    fn from(src: SectorError) -> u8 {
+        src as u8
+    }
+}
+
+/// Errors of the sectors of a disk. Sectors that aren't in the table don't
+/// have an error.
+pub(crate) type SectorErrors = BTreeMap<SectorRef, SectorError>;
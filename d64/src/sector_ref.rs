@@ -6,3 +6,6 @@ pub type SectorNo = u8;
 /// First element references a track.
 /// Second element references a sector within the track.
 pub type SectorRef = (TrackNo, SectorNo);
+/// Half track number. Track 1 is half track 2, the half track between track 1
+/// and track 2 is half track 3.
+pub type HalfTrackNo = u8;
@@ -0,0 +1,43 @@
+use std::fmt;
+
+use crate::SectorRef;
+
+/// Sector that could not be read completely because the image ended early.
+///
+/// Returned as the inner error of an [std::io::Error] with kind
+/// [std::io::ErrorKind::UnexpectedEof] by [crate::Disk::read_from_reader], and
+/// as warning by [crate::Disk::read_truncated_from_reader].
+///
+/// # Example
+///
+/// ```
+/// use d64::*;
+/// use commodore1541::*;
+///
+/// let bytes = vec![0; 21 * 256 + 100];
+/// let mut disk = Disk::<Commodore1541>::new();
+/// let error = disk.read_from_reader(&mut bytes.as_slice()).unwrap_err();
+/// let short_read = error.get_ref().unwrap().downcast_ref::<ShortRead>().unwrap();
+/// assert_eq!(short_read.sector_ref, (2, 0));
+/// assert_eq!(short_read.bytes_read, 100);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShortRead {
+    pub sector_ref: SectorRef,
+    /// Number of bytes of the sector that could be read.
+    pub bytes_read: usize,
+    /// Number of bytes of a complete sector.
+    pub bytes_per_sector: usize,
+}
+
+impl fmt::Display for ShortRead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "image is truncated at track {} sector {} ({} of {} bytes read)",
+            self.sector_ref.0, self.sector_ref.1, self.bytes_read, self.bytes_per_sector
+        )
+    }
+}
+
+impl std::error::Error for ShortRead {}
@@ -1,41 +1,69 @@
-use std::io::{Read, Write};
-
 use crate::Sector;
 
-#[derive(Default)]
-pub struct Track {
-    sectors: Vec<Sector>,
+/// View into the sectors of a single track of a disk.
+#[derive(Clone, Copy)]
+pub struct Track<'a> {
+    data: &'a [u8],
+    bytes_per_sector: usize,
+}
+
+/// View into the sectors of a single track of a disk for modification.
+pub struct TrackMut<'a> {
+    data: &'a mut [u8],
+    bytes_per_sector: usize,
 }
 
-impl Track {
-    pub fn initialize(&mut self, num_sectors: u8, bytes_per_sector: u16) {
-        self.sectors.clear();
-        for _sector_no in 0..num_sectors {
-            let mut sector = Sector::default();
-            sector.initialize(bytes_per_sector as usize);
-            self.sectors.push(sector);
+impl<'a> Track<'a> {
+    /// View the given bytes as a track with sectors of the given size.
+    pub fn from_slice(data: &'a [u8], bytes_per_sector: usize) -> Track<'a> {
+        Track {
+            data,
+            bytes_per_sector,
         }
     }
 
-    pub fn read_from_reader<R: Read>(&mut self, reader: &mut R) -> std::io::Result<()> {
-        for sector in &mut self.sectors {
-            sector.read_from_reader(reader)?;
-        }
-        Ok(())
+    /// Number of sectors of this track.
+    pub fn num_sectors(&self) -> usize {
+        self.data.len() / self.bytes_per_sector
+    }
+
+    /// All sectors of this track, ordered by sector number.
+    pub fn sectors(&self) -> impl Iterator<Item = &'a Sector> {
+        self.data
+            .chunks_exact(self.bytes_per_sector)
+            .map(Sector::from_slice)
+    }
+
+    pub fn get_sector(&self, sector_no: u8) -> &'a Sector {
+        let offset = sector_no as usize * self.bytes_per_sector;
+        Sector::from_slice(&self.data[offset..offset + self.bytes_per_sector])
     }
-    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        for sector in &self.sectors {
-            sector.write_to_writer(writer)?;
+
+    pub fn as_slice(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+impl<'a> TrackMut<'a> {
+    /// View the given bytes as a track with sectors of the given size.
+    pub fn from_slice(data: &'a mut [u8], bytes_per_sector: usize) -> TrackMut<'a> {
+        TrackMut {
+            data,
+            bytes_per_sector,
         }
-        Ok(())
     }
 
-    pub fn get_sector(&self, sector_no: u8) -> &Sector {
-        let index = sector_no as usize;
-        &self.sectors[index]
+    /// Number of sectors of this track.
+    pub fn num_sectors(&self) -> usize {
+        self.data.len() / self.bytes_per_sector
     }
+
     pub fn get_sector_mut(&mut self, sector_no: u8) -> &mut Sector {
-        let index = sector_no as usize;
-        &mut self.sectors[index]
+        let offset = sector_no as usize * self.bytes_per_sector;
+        Sector::from_slice_mut(&mut self.data[offset..offset + self.bytes_per_sector])
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.data
     }
 }
@@ -0,0 +1,186 @@
+//! X64 files contain a 64 byte header followed by the sectors of a disk.
+//!
+//! The header describes the device type, the number of tracks and whether an
+//! error table follows the sectors. Use [Disk::read_x64_from_reader] and
+//! [Disk::write_x64_to_writer] to read and write them.
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use crate::{Disk, SectorError, TrackNo, X64Layout};
+
+const SIGNATURE: [u8; 4] = [0x43, 0x15, 0x41, 0x64];
+const VERSION_MAJOR: u8 = 1;
+const VERSION_MINOR: u8 = 2;
+const HEADER_SIZE: usize = 64;
+const OFFSET_DEVICE_TYPE: usize = 6;
+const OFFSET_NUM_TRACKS: usize = 7;
+const OFFSET_NUM_SIDES: usize = 8;
+const OFFSET_ERROR_INFO: usize = 9;
+const OFFSET_DESCRIPTION: usize = 32;
+const DESCRIPTION_LENGTH: usize = 31;
+
+/// Header of an X64 file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct X64Header {
+    pub version: (u8, u8),
+    /// Device type of the image, see [X64Layout::x64_device_type].
+    pub device_type: u8,
+    pub num_tracks: TrackNo,
+    pub num_sides: u8,
+    /// Is the error table of the disk stored after the sectors.
+    pub has_error_info: bool,
+    /// Description of the image.
+    pub description: String,
+}
+
+impl X64Header {
+    /// Create the header that describes the given disk.
+    pub fn for_disk<L: X64Layout + Default>(disk: &Disk<L>) -> X64Header {
+        let layout = disk.layout();
+        X64Header {
+            version: (VERSION_MAJOR, VERSION_MINOR),
+            device_type: layout.x64_device_type(),
+            num_tracks: disk.num_tracks(),
+            num_sides: layout.x64_num_sides(),
+            has_error_info: disk.sector_errors().next().is_some(),
+            description: String::new(),
+        }
+    }
+
+    pub fn read_from_reader<R: Read>(reader: &mut R) -> std::io::Result<X64Header> {
+        let mut bytes = [0_u8; HEADER_SIZE];
+        reader.read_exact(&mut bytes)?;
+        if bytes[0..4] != SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "not a X64 file"));
+        }
+        let description = &bytes[OFFSET_DESCRIPTION..OFFSET_DESCRIPTION + DESCRIPTION_LENGTH];
+        let description_length = description
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(DESCRIPTION_LENGTH);
+        Ok(X64Header {
+            version: (bytes[4], bytes[5]),
+            device_type: bytes[OFFSET_DEVICE_TYPE],
+            num_tracks: bytes[OFFSET_NUM_TRACKS],
+            num_sides: bytes[OFFSET_NUM_SIDES],
+            has_error_info: bytes[OFFSET_ERROR_INFO] != 0,
+            description: String::from_utf8_lossy(&description[..description_length]).into_owned(),
+        })
+    }
+
+    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut bytes = [0_u8; HEADER_SIZE];
+        bytes[0..4].copy_from_slice(&SIGNATURE);
+        bytes[4] = self.version.0;
+        bytes[5] = self.version.1;
+        bytes[OFFSET_DEVICE_TYPE] = self.device_type;
+        bytes[OFFSET_NUM_TRACKS] = self.num_tracks;
+        bytes[OFFSET_NUM_SIDES] = self.num_sides;
+        bytes[OFFSET_ERROR_INFO] = self.has_error_info as u8;
+        let description = self.description.as_bytes();
+        let description_length = description.len().min(DESCRIPTION_LENGTH);
+        bytes[OFFSET_DESCRIPTION..OFFSET_DESCRIPTION + description_length]
+            .copy_from_slice(&description[..description_length]);
+        writer.write_all(&bytes)
+    }
+}
+
+impl<L> Disk<L>
+where
+    L: X64Layout + Default,
+{
+    /// Load a X64 image from file path.
+    pub fn read_x64_from_path(&mut self, filename: &Path) -> std::io::Result<X64Header> {
+        let mut file = File::open(filename)?;
+        self.read_x64_from_reader(&mut file)
+    }
+
+    /// Load a X64 image from a reader.
+    ///
+    /// The device type of the image must match the layout of the disk. The
+    /// number of tracks of the disk is taken from the header.
+    pub fn read_x64_from_reader<R: Read>(&mut self, reader: &mut R) -> std::io::Result<X64Header> {
+        let header = X64Header::read_from_reader(reader)?;
+        let layout = self.shared_layout();
+        if header.device_type != layout.x64_device_type() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "device type of X64 file doesn't match the layout",
+            ));
+        }
+        let num_tracks = if header.num_tracks == 0 {
+            layout.num_tracks()
+        } else {
+            header.num_tracks
+        };
+        if (1..=num_tracks).any(|track_no| layout.num_sectors(track_no) == 0) {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "number of tracks of X64 file isn't supported by the layout",
+            ));
+        }
+
+        self.resize_tracks(num_tracks);
+        self.read_from_reader(reader)?;
+        if header.has_error_info {
+            let sector_refs = self.all_sector_refs();
+            let mut errors = vec![0_u8; sector_refs.len()];
+            reader.read_exact(&mut errors)?;
+            for (sector_ref, error) in sector_refs.into_iter().zip(errors) {
+                self.set_sector_error(sector_ref, SectorError::from(error));
+            }
+        }
+        Ok(header)
+    }
+
+    /// Store the disk as X64 file.
+    pub fn write_x64_to_path(&self, filename: &Path, description: &str) -> std::io::Result<()> {
+        let mut file = File::create(filename)?;
+        self.write_x64_to_writer(&mut file, description)
+    }
+
+    /// Store the disk as X64 image. The error table of the disk is stored
+    /// when any sector has an error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use d64::*;
+    /// use commodore1541::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// disk.set_sector_error((35, 16), SectorError::DataChecksum);
+    ///
+    /// let mut bytes = Vec::new();
+    /// disk.write_x64_to_writer(&mut bytes, "EMPTY DISK").unwrap();
+    /// assert_eq!(bytes.len(), 64 + 683 * 256 + 683);
+    ///
+    /// let mut read_disk = Disk::<Commodore1541>::new();
+    /// let header = read_disk.read_x64_from_reader(&mut bytes.as_slice()).unwrap();
+    /// assert_eq!(header.description, "EMPTY DISK");
+    /// assert_eq!(header.num_tracks, 35);
+    /// assert_eq!(String::from(&read_disk.get_name()), "NONAME");
+    /// assert_eq!(read_disk.get_sector_error((35, 16)), SectorError::DataChecksum);
+    /// assert_eq!(read_disk.sector_errors().count(), 1);
+    /// ```
+    pub fn write_x64_to_writer<W: Write>(
+        &self,
+        writer: &mut W,
+        description: &str,
+    ) -> std::io::Result<()> {
+        let header = X64Header {
+            description: String::from(description),
+            ..X64Header::for_disk(self)
+        };
+        header.write_to_writer(writer)?;
+        self.write_to_writer(writer)?;
+        if header.has_error_info {
+            writer.write_all(&self.error_table())?;
+        }
+        Ok(())
+    }
+}
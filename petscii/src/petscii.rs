@@ -85,13 +85,13 @@ pub fn decode_petscii(petscii: u8) -> char {
 /// assert_eq!(encode_petscii('9', PETSCII_NBSP), PETSCII_NINE);
 /// ```
 pub fn encode_petscii(ascii: char, default: u8) -> u8 {
-    if ('A'..='Z').contains(&ascii) {
+    if ascii.is_ascii_uppercase() {
         return (ascii as u8 - b'A') + PETSCII_A;
     }
-    if ('a'..='z').contains(&ascii) {
+    if ascii.is_ascii_lowercase() {
         return (ascii as u8 - b'a') + PETSCII_A;
     }
-    if ('0'..='9').contains(&ascii) {
+    if ascii.is_ascii_digit() {
         return (ascii as u8 - b'0') + PETSCII_ZERO;
     }
     default
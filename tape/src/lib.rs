@@ -0,0 +1,40 @@
+//! Crate to read and write tape images (T64 and TAP) of Commodore 8 bit
+//! computers.
+//!
+//! # T64 files
+//!
+//! T64 files are archives that contain the programs of a tape. Use [T64Image]
+//! to read and write them, and [T64Image::write_to_disk] and
+//! [T64Image::read_from_disk] to convert them to and from PRG files on a disk.
+//!
+//! ```
+//! use commodore1541::*;
+//! use d64::*;
+//! use petscii::*;
+//! use tape::*;
+//!
+//! let mut image = T64Image::new(PetsciiString::from(&String::from("GAMES")));
+//! image.entries.push(T64Entry::from_prg(
+//!     PetsciiString::from(&String::from("HELLO")),
+//!     &[0x01, 0x08, 0x0b, 0x08, 0x0a, 0x00],
+//! ));
+//!
+//! let mut disk = Disk::<Commodore1541>::new();
+//! disk.format();
+//! image.write_to_disk(&mut disk);
+//!
+//! let entries = disk.list_entries();
+//! assert_eq!(String::from(&entries[0].name), "HELLO");
+//! assert_eq!(disk.read_file(&entries[0]), vec![0x01, 0x08, 0x0b, 0x08, 0x0a, 0x00]);
+//! ```
+//!
+//! # TAP files
+//!
+//! TAP files contain the pulses of a tape. Use [TapImage] to read and write
+//! them. Files stored using the CBM ROM loader can be decoded with
+//! [TapImage::decode_files] and encoded with [TapImage::encode_files].
+mod t64;
+mod tap;
+
+pub use t64::*;
+pub use tap::*;
@@ -0,0 +1,400 @@
+//! T64 files contain the programs of a tape, together with their load
+//! addresses.
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use commodore1541::{FileEntry, FileType};
+use d64::{Disk, Layout};
+use petscii::{PetsciiString, PETSCII_NBSP};
+
+const SIGNATURE: &[u8; 19] = b"C64 tape image file";
+/// All known T64 files start with this.
+const SIGNATURE_PREFIX: &[u8; 3] = b"C64";
+const VERSION: u16 = 0x0101;
+const HEADER_SIZE: usize = 64;
+const OFFSET_VERSION: usize = 0x20;
+const OFFSET_MAX_ENTRIES: usize = 0x22;
+const OFFSET_USED_ENTRIES: usize = 0x24;
+const OFFSET_TAPE_NAME: usize = 0x28;
+const TAPE_NAME_LENGTH: usize = 24;
+const ENTRY_SIZE: usize = 32;
+const OFFSET_ENTRY_TYPE: usize = 0;
+const OFFSET_FILE_TYPE: usize = 1;
+const OFFSET_START_ADDRESS: usize = 2;
+const OFFSET_END_ADDRESS: usize = 4;
+const OFFSET_DATA: usize = 8;
+const OFFSET_FILE_NAME: usize = 16;
+const FILE_NAME_LENGTH: usize = 16;
+/// Names in T64 files are padded with spaces.
+const PADDING: u8 = 0x20;
+/// Size of the load address at the start of PRG files.
+const LOAD_ADDRESS_SIZE: usize = 2;
+
+/// Type of a T64 directory entry.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub enum T64EntryType {
+    /// Directory entry isn't in use.
+    Free = 0x00,
+    /// Normal tape file.
+    #[default]
+    Normal = 0x01,
+    /// Tape file with header.
+    NormalWithHeader = 0x02,
+    /// Memory snapshot.
+    Snapshot = 0x03,
+    /// Tape block.
+    Block = 0x04,
+    /// Digitized stream.
+    Stream = 0x05,
+}
+
+impl From<u8> for T64EntryType {
+    fn from(src: u8) -> T64EntryType {
+        match src {
+            0x01 => T64EntryType::Normal,
+            0x02 => T64EntryType::NormalWithHeader,
+            0x03 => T64EntryType::Snapshot,
+            0x04 => T64EntryType::Block,
+            0x05 => T64EntryType::Stream,
+            _ => T64EntryType::Free,
+        }
+    }
+}
+
+impl From<T64EntryType> for u8 {
+    fn from(src: T64EntryType) -> u8 {
+        src as u8
+    }
+}
+
+/// Single file of a T64 file.
+#[derive(Debug, Clone, Default)]
+pub struct T64Entry {
+    pub entry_type: T64EntryType,
+    /// File type the file gets when stored on a disk.
+    pub file_type: FileType,
+    /// Address where the file is loaded into memory.
+    pub start_address: u16,
+    pub name: PetsciiString,
+    /// Content of the file, without load address.
+    pub data: Vec<u8>,
+}
+
+impl T64Entry {
+    /// Create an entry from the content of a PRG file: a load address followed
+    /// by the data.
+    pub fn from_prg(name: PetsciiString, prg: &[u8]) -> T64Entry {
+        let start_address = match prg {
+            [low, high, ..] => u16::from_le_bytes([*low, *high]),
+            _ => 0,
+        };
+        T64Entry {
+            name,
+            start_address,
+            data: prg.get(LOAD_ADDRESS_SIZE..).unwrap_or_default().to_vec(),
+            ..T64Entry::default()
+        }
+    }
+
+    /// Content of the entry as PRG file: the load address followed by the data.
+    pub fn to_prg(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.data.len() + LOAD_ADDRESS_SIZE);
+        result.extend_from_slice(&self.start_address.to_le_bytes());
+        result.extend_from_slice(&self.data);
+        result
+    }
+
+    /// Address directly after the last byte of the file in memory.
+    pub fn end_address(&self) -> u16 {
+        self.start_address.wrapping_add(self.data.len() as u16)
+    }
+}
+
+/// Content of a T64 file.
+///
+/// # Example
+///
+/// ```
+/// use petscii::*;
+/// use tape::*;
+///
+/// let mut image = T64Image::new(PetsciiString::from(&String::from("DEMO")));
+/// image.entries.push(T64Entry::from_prg(
+///     PetsciiString::from(&String::from("INTRO")),
+///     &[0x00, 0xc0, 0xa9, 0x00, 0x60],
+/// ));
+///
+/// let mut bytes = Vec::new();
+/// image.write_to_writer(&mut bytes).unwrap();
+///
+/// let image = T64Image::read_from_reader(&mut bytes.as_slice()).unwrap();
+/// assert_eq!(String::from(&image.name), "DEMO");
+/// assert_eq!(String::from(&image.entries[0].name), "INTRO");
+/// assert_eq!(image.entries[0].start_address, 0xc000);
+/// assert_eq!(image.entries[0].end_address(), 0xc003);
+/// assert_eq!(image.entries[0].data, vec![0xa9, 0x00, 0x60]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct T64Image {
+    pub version: u16,
+    pub name: PetsciiString,
+    /// Number of directory entries reserved in the file. The directory grows
+    /// when more entries are used.
+    pub max_entries: u16,
+    /// Used entries, in the order they are stored in the directory.
+    pub entries: Vec<T64Entry>,
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ]) as usize
+}
+
+/// Some tools don't set the file type, or store it without the closed flag.
+fn read_file_type(file_type: u8) -> FileType {
+    match file_type {
+        0x00 => FileType::Program,
+        file_type => FileType::from(file_type | 0x80),
+    }
+}
+
+/// Read a name padded with spaces.
+fn read_name(bytes: &[u8]) -> PetsciiString {
+    let length = bytes
+        .iter()
+        .rposition(|byte| !matches!(*byte, PADDING | PETSCII_NBSP | 0))
+        .map_or(0, |position| position + 1);
+    PetsciiString::fixed_size(&bytes[..length])
+}
+
+/// Write a name padded with spaces.
+fn write_name(bytes: &mut [u8], name: &PetsciiString) {
+    bytes.fill(PADDING);
+    let name = name.as_slice();
+    let length = name.len().min(bytes.len());
+    bytes[..length].copy_from_slice(&name[..length]);
+}
+
+impl T64Image {
+    /// Create an image without any entries.
+    pub fn new(name: PetsciiString) -> T64Image {
+        T64Image {
+            version: VERSION,
+            name,
+            max_entries: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Load a T64 image from file path.
+    pub fn read_from_path(filename: &Path) -> std::io::Result<T64Image> {
+        let mut file = File::open(filename)?;
+        T64Image::read_from_reader(&mut file)
+    }
+
+    /// Load a T64 image from a reader.
+    ///
+    /// Many T64 files contain incorrect end addresses. The size of an entry is
+    /// limited to the data until the next entry or the end of the file.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use petscii::*;
+    /// use tape::*;
+    ///
+    /// let mut image = T64Image::new(PetsciiString::from(&String::from("DEMO")));
+    /// for name in ["FIRST", "SECOND"] {
+    ///     image.entries.push(T64Entry::from_prg(
+    ///         PetsciiString::from(&String::from(name)),
+    ///         &[0x00, 0xc0, 0xa9, 0x00, 0x60],
+    ///     ));
+    /// }
+    /// let mut bytes = Vec::new();
+    /// image.write_to_writer(&mut bytes).unwrap();
+    ///
+    /// // Data offsets of both entries point past the end of the file.
+    /// bytes[64 + 8..64 + 12].copy_from_slice(&0x1000_u32.to_le_bytes());
+    /// bytes[96 + 8..96 + 12].copy_from_slice(&0x2000_u32.to_le_bytes());
+    ///
+    /// let image = T64Image::read_from_reader(&mut bytes.as_slice()).unwrap();
+    /// assert_eq!(image.entries.len(), 2);
+    /// assert!(image.entries[0].data.is_empty());
+    /// assert!(image.entries[1].data.is_empty());
+    /// ```
+    pub fn read_from_reader<R: Read>(reader: &mut R) -> std::io::Result<T64Image> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() < HEADER_SIZE || &bytes[0..SIGNATURE_PREFIX.len()] != SIGNATURE_PREFIX {
+            return Err(Error::new(ErrorKind::InvalidData, "not a T64 file"));
+        }
+        let max_entries = read_u16(&bytes, OFFSET_MAX_ENTRIES);
+        let directory_end = HEADER_SIZE + max_entries as usize * ENTRY_SIZE;
+        if bytes.len() < directory_end {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "T64 directory is truncated",
+            ));
+        }
+
+        let directory = bytes[HEADER_SIZE..directory_end]
+            .chunks_exact(ENTRY_SIZE)
+            .filter(|entry| T64EntryType::from(entry[OFFSET_ENTRY_TYPE]) != T64EntryType::Free)
+            .collect::<Vec<&[u8]>>();
+        let mut data_offsets = directory
+            .iter()
+            .map(|entry| read_u32(entry, OFFSET_DATA).min(bytes.len()))
+            .collect::<Vec<usize>>();
+        data_offsets.push(bytes.len());
+        data_offsets.sort_unstable();
+
+        let mut entries = Vec::with_capacity(directory.len());
+        for entry in directory {
+            let start_address = read_u16(entry, OFFSET_START_ADDRESS);
+            let end_address = read_u16(entry, OFFSET_END_ADDRESS);
+            let data_offset = read_u32(entry, OFFSET_DATA).min(bytes.len());
+            let next_offset = data_offsets
+                .iter()
+                .copied()
+                .find(|offset| *offset > data_offset)
+                .unwrap_or(bytes.len());
+            let data_size =
+                (end_address.wrapping_sub(start_address) as usize).min(next_offset - data_offset);
+            entries.push(T64Entry {
+                entry_type: T64EntryType::from(entry[OFFSET_ENTRY_TYPE]),
+                file_type: read_file_type(entry[OFFSET_FILE_TYPE]),
+                start_address,
+                name: read_name(&entry[OFFSET_FILE_NAME..OFFSET_FILE_NAME + FILE_NAME_LENGTH]),
+                data: bytes[data_offset..data_offset + data_size].to_vec(),
+            });
+        }
+
+        Ok(T64Image {
+            version: read_u16(&bytes, OFFSET_VERSION),
+            name: read_name(&bytes[OFFSET_TAPE_NAME..OFFSET_TAPE_NAME + TAPE_NAME_LENGTH]),
+            max_entries,
+            entries,
+        })
+    }
+
+    pub fn write_to_path(&self, filename: &Path) -> std::io::Result<()> {
+        let mut file = File::create(filename)?;
+        self.write_to_writer(&mut file)
+    }
+
+    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let num_entries = self.entries.len();
+        let max_entries = (self.max_entries as usize).max(num_entries).max(1);
+        if max_entries > u16::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "too many entries for a T64 file",
+            ));
+        }
+
+        let mut header = vec![0_u8; HEADER_SIZE + max_entries * ENTRY_SIZE];
+        header[0..SIGNATURE.len()].copy_from_slice(SIGNATURE);
+        header[OFFSET_VERSION..OFFSET_VERSION + 2].copy_from_slice(&self.version.to_le_bytes());
+        header[OFFSET_MAX_ENTRIES..OFFSET_MAX_ENTRIES + 2]
+            .copy_from_slice(&(max_entries as u16).to_le_bytes());
+        header[OFFSET_USED_ENTRIES..OFFSET_USED_ENTRIES + 2]
+            .copy_from_slice(&(num_entries as u16).to_le_bytes());
+        write_name(
+            &mut header[OFFSET_TAPE_NAME..OFFSET_TAPE_NAME + TAPE_NAME_LENGTH],
+            &self.name,
+        );
+
+        let mut data_offset = header.len();
+        for (index, entry) in self.entries.iter().enumerate() {
+            let bytes = &mut header[HEADER_SIZE + index * ENTRY_SIZE..][..ENTRY_SIZE];
+            bytes[OFFSET_ENTRY_TYPE] = u8::from(entry.entry_type);
+            bytes[OFFSET_FILE_TYPE] = u8::from(entry.file_type);
+            bytes[OFFSET_START_ADDRESS..OFFSET_START_ADDRESS + 2]
+                .copy_from_slice(&entry.start_address.to_le_bytes());
+            bytes[OFFSET_END_ADDRESS..OFFSET_END_ADDRESS + 2]
+                .copy_from_slice(&entry.end_address().to_le_bytes());
+            bytes[OFFSET_DATA..OFFSET_DATA + 4]
+                .copy_from_slice(&(data_offset as u32).to_le_bytes());
+            write_name(
+                &mut bytes[OFFSET_FILE_NAME..OFFSET_FILE_NAME + FILE_NAME_LENGTH],
+                &entry.name,
+            );
+            data_offset += entry.data.len();
+        }
+        writer.write_all(&header)?;
+        for entry in &self.entries {
+            writer.write_all(&entry.data)?;
+        }
+        Ok(())
+    }
+
+    /// Store every entry of the image as PRG file on the disk.
+    pub fn write_to_disk<L>(&self, disk: &mut Disk<L>)
+    where
+        L: Layout<FileEntryType = FileEntry, StringType = PetsciiString> + Default,
+    {
+        for entry in &self.entries {
+            let file_entry = FileEntry {
+                name: entry.name.clone(),
+                file_type: FileType::Program,
+                ..FileEntry::default()
+            };
+            disk.create_file(&file_entry, &entry.to_prg());
+        }
+    }
+
+    /// Create an image containing every PRG file of the disk. The name of the
+    /// image is the name of the disk.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use commodore1541::*;
+    /// use d64::*;
+    /// use petscii::*;
+    /// use tape::*;
+    ///
+    /// let mut disk = Disk::<Commodore1541>::new();
+    /// disk.format();
+    /// let entry = FileEntry {
+    ///     name: PetsciiString::from(&String::from("GAME")),
+    ///     file_type: FileType::Program,
+    ///     ..FileEntry::default()
+    /// };
+    /// disk.create_file(&entry, &[0x01, 0x08, 0x00, 0x00, 0x00]);
+    ///
+    /// let image = T64Image::read_from_disk(&mut disk);
+    /// assert_eq!(String::from(&image.name), "NONAME");
+    /// assert_eq!(image.entries.len(), 1);
+    /// assert_eq!(image.entries[0].start_address, 0x0801);
+    /// assert_eq!(image.entries[0].data, vec![0x00, 0x00, 0x00]);
+    /// ```
+    pub fn read_from_disk<L>(disk: &mut Disk<L>) -> T64Image
+    where
+        L: Layout<FileEntryType = FileEntry, StringType = PetsciiString> + Default,
+    {
+        let mut image = T64Image::new(read_name(disk.get_name().as_slice()));
+        for file_entry in disk.list_entries() {
+            if file_entry.file_type != FileType::Program {
+                continue;
+            }
+            let content = disk.read_file(&file_entry);
+            image.entries.push(T64Entry::from_prg(
+                read_name(file_entry.name.as_slice()),
+                &content,
+            ));
+        }
+        image
+    }
+}
@@ -0,0 +1,522 @@
+//! TAP files contain the pulses of a tape, as they are read by the datasette.
+//!
+//! [TapImage::decode_files] decodes the files stored with the encoding of the
+//! CBM kernal ROM loader, [TapImage::encode_files] creates the pulses of a
+//! tape containing the given files.
+use std::{
+    fs::File,
+    io::{Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use petscii::{PetsciiString, PETSCII_NBSP};
+
+const SIGNATURE: &[u8; 12] = b"C64-TAPE-RAW";
+const HEADER_SIZE: usize = 20;
+const OFFSET_VERSION: usize = 12;
+const OFFSET_DATA_SIZE: usize = 16;
+/// Pulse lengths are stored in units of 8 clock cycles.
+const CYCLES_PER_UNIT: u32 = 8;
+/// Pulses that don't fit in a single byte are stored as pause.
+const PAUSE: u8 = 0;
+
+/// Length in cycles of the pulses used by the CBM ROM loader.
+const SHORT_PULSE: u32 = 0x30 * CYCLES_PER_UNIT;
+const MEDIUM_PULSE: u32 = 0x42 * CYCLES_PER_UNIT;
+const LONG_PULSE: u32 = 0x56 * CYCLES_PER_UNIT;
+/// Pulses longer than this are not part of the ROM loader encoding.
+const MAX_PULSE: u32 = 0x70 * CYCLES_PER_UNIT;
+/// Length of the silence between two files.
+const SILENCE: u32 = 0x10_0000;
+
+/// Number of short pulses before the first copy of a header and a data block.
+const HEADER_PILOT_LENGTH: usize = 0x6a00;
+const DATA_PILOT_LENGTH: usize = 0x1a00;
+/// Number of short pulses between the first copy and the repeated copy.
+const INTERBLOCK_LENGTH: usize = 0x4f;
+/// Number of short pulses after the repeated copy.
+const TRAILER_LENGTH: usize = 0x4e;
+
+/// Countdown sequence that precedes the first copy of a block. The repeated
+/// copy uses the same sequence without the high bit.
+const COUNTDOWN_FIRST: [u8; 9] = [0x89, 0x88, 0x87, 0x86, 0x85, 0x84, 0x83, 0x82, 0x81];
+const COUNTDOWN_REPEAT: [u8; 9] = [0x09, 0x08, 0x07, 0x06, 0x05, 0x04, 0x03, 0x02, 0x01];
+
+const HEADER_BLOCK_SIZE: usize = 192;
+const OFFSET_START_ADDRESS: usize = 1;
+const OFFSET_END_ADDRESS: usize = 3;
+const OFFSET_FILE_NAME: usize = 5;
+const FILE_NAME_LENGTH: usize = 16;
+/// Names and the rest of the header block are padded with spaces.
+const PADDING: u8 = 0x20;
+
+/// Type of file stored in a header block.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub enum TapFileType {
+    /// Program that is loaded at the start of BASIC.
+    RelocatableProgram = 0x01,
+    /// Sequential data; the data is stored in the following header blocks.
+    SequentialHeader = 0x02,
+    /// Program that is loaded at its start address.
+    #[default]
+    Program = 0x03,
+    /// Block of sequential data.
+    SequentialData = 0x04,
+    /// End of tape marker.
+    EndOfTape = 0x05,
+}
+
+impl TapFileType {
+    fn from_u8(src: u8) -> Option<TapFileType> {
+        match src {
+            0x01 => Some(TapFileType::RelocatableProgram),
+            0x02 => Some(TapFileType::SequentialHeader),
+            0x03 => Some(TapFileType::Program),
+            0x04 => Some(TapFileType::SequentialData),
+            0x05 => Some(TapFileType::EndOfTape),
+            _ => None,
+        }
+    }
+}
+
+/// File found on a tape.
+#[derive(Debug, Clone, Default)]
+pub struct TapFile {
+    pub file_type: TapFileType,
+    pub name: PetsciiString,
+    /// Address where the file is loaded into memory.
+    pub start_address: u16,
+    /// Address directly after the last byte of the file in memory.
+    pub end_address: u16,
+    pub data: Vec<u8>,
+    /// Were both copies of the header or data block damaged. The data of the
+    /// best copy is used.
+    pub has_errors: bool,
+}
+
+impl TapFile {
+    /// Create a program file from the content of a PRG file: a load address
+    /// followed by the data.
+    pub fn from_prg(name: PetsciiString, prg: &[u8]) -> TapFile {
+        let start_address = match prg {
+            [low, high, ..] => u16::from_le_bytes([*low, *high]),
+            _ => 0,
+        };
+        let data = prg.get(2..).unwrap_or_default().to_vec();
+        TapFile {
+            file_type: TapFileType::Program,
+            name,
+            start_address,
+            end_address: start_address.wrapping_add(data.len() as u16),
+            data,
+            has_errors: false,
+        }
+    }
+
+    /// Content of the file as PRG file: the load address followed by the data.
+    pub fn to_prg(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.data.len() + 2);
+        result.extend_from_slice(&self.start_address.to_le_bytes());
+        result.extend_from_slice(&self.data);
+        result
+    }
+
+    fn header_block(&self) -> Vec<u8> {
+        let mut block = vec![PADDING; HEADER_BLOCK_SIZE];
+        block[0] = self.file_type as u8;
+        block[OFFSET_START_ADDRESS..OFFSET_START_ADDRESS + 2]
+            .copy_from_slice(&self.start_address.to_le_bytes());
+        block[OFFSET_END_ADDRESS..OFFSET_END_ADDRESS + 2]
+            .copy_from_slice(&self.end_address.to_le_bytes());
+        let name = self.name.as_slice();
+        let length = name.len().min(FILE_NAME_LENGTH);
+        block[OFFSET_FILE_NAME..OFFSET_FILE_NAME + length].copy_from_slice(&name[..length]);
+        block
+    }
+}
+
+/// Content of a TAP file: the length of each pulse in clock cycles.
+///
+/// # Example
+///
+/// ```
+/// use commodore1541::*;
+/// use d64::*;
+/// use petscii::*;
+/// use tape::*;
+///
+/// let program = TapFile::from_prg(
+///     PetsciiString::from(&String::from("HELLO")),
+///     &[0x01, 0x08, 0x0b, 0x08, 0x0a, 0x00],
+/// );
+/// let image = TapImage::encode_files(&[program]);
+///
+/// let mut bytes = Vec::new();
+/// image.write_to_writer(&mut bytes).unwrap();
+/// let image = TapImage::read_from_reader(&mut bytes.as_slice()).unwrap();
+///
+/// let files = image.decode_files();
+/// assert_eq!(files.len(), 1);
+/// assert_eq!(String::from(&files[0].name), "HELLO");
+/// assert_eq!(files[0].start_address, 0x0801);
+/// assert!(!files[0].has_errors);
+///
+/// let mut disk = Disk::<Commodore1541>::new();
+/// disk.format();
+/// let entry = FileEntry {
+///     name: files[0].name.clone(),
+///     file_type: FileType::Program,
+///     ..FileEntry::default()
+/// };
+/// disk.create_file(&entry, &files[0].to_prg());
+/// let entries = disk.list_entries();
+/// assert_eq!(disk.read_file(&entries[0]), files[0].to_prg());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TapImage {
+    pub version: u8,
+    /// Length of each pulse in clock cycles.
+    pub pulses: Vec<u32>,
+}
+
+fn invalid_data(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+impl TapImage {
+    /// Load a TAP image from file path.
+    pub fn read_from_path(filename: &Path) -> std::io::Result<TapImage> {
+        let mut file = File::open(filename)?;
+        TapImage::read_from_reader(&mut file)
+    }
+
+    /// Load a TAP image from a reader.
+    pub fn read_from_reader<R: Read>(reader: &mut R) -> std::io::Result<TapImage> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() < HEADER_SIZE || &bytes[0..SIGNATURE.len()] != SIGNATURE {
+            return Err(invalid_data("not a TAP file"));
+        }
+        let version = bytes[OFFSET_VERSION];
+        let data_size = u32::from_le_bytes([
+            bytes[OFFSET_DATA_SIZE],
+            bytes[OFFSET_DATA_SIZE + 1],
+            bytes[OFFSET_DATA_SIZE + 2],
+            bytes[OFFSET_DATA_SIZE + 3],
+        ]) as usize;
+        let data = bytes
+            .get(HEADER_SIZE..HEADER_SIZE + data_size)
+            .unwrap_or(&bytes[HEADER_SIZE..]);
+
+        let mut pulses = Vec::with_capacity(data.len());
+        let mut bytes = data.iter();
+        while let Some(byte) = bytes.next() {
+            if *byte != PAUSE {
+                pulses.push(*byte as u32 * CYCLES_PER_UNIT);
+            } else if version == 0 {
+                pulses.push(256 * CYCLES_PER_UNIT);
+            } else {
+                let mut cycles = [0_u8; 4];
+                for cycle_byte in cycles.iter_mut().take(3) {
+                    *cycle_byte = *bytes
+                        .next()
+                        .ok_or_else(|| invalid_data("TAP pause is truncated"))?;
+                }
+                pulses.push(u32::from_le_bytes(cycles));
+            }
+        }
+        Ok(TapImage { version, pulses })
+    }
+
+    pub fn write_to_path(&self, filename: &Path) -> std::io::Result<()> {
+        let mut file = File::create(filename)?;
+        self.write_to_writer(&mut file)
+    }
+
+    /// Store the image. Pulses that don't fit in a single byte are stored as
+    /// pause; version 0 files lose the length of those pulses.
+    pub fn write_to_writer<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut data = Vec::with_capacity(self.pulses.len());
+        for cycles in &self.pulses {
+            let units = cycles / CYCLES_PER_UNIT;
+            if (1..256).contains(&units) {
+                data.push(units as u8);
+            } else {
+                data.push(PAUSE);
+                if self.version != 0 {
+                    data.extend_from_slice(&cycles.min(&0xff_ffff).to_le_bytes()[0..3]);
+                }
+            }
+        }
+
+        writer.write_all(SIGNATURE)?;
+        writer.write_all(&[self.version, 0, 0, 0])?;
+        writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        writer.write_all(&data)
+    }
+
+    /// Decode the files stored using the encoding of the CBM ROM loader.
+    ///
+    /// Each block is stored twice on tape. The first copy is used, unless it is
+    /// damaged and the repeated copy isn't.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use petscii::*;
+    /// use tape::*;
+    ///
+    /// let program = TapFile::from_prg(
+    ///     PetsciiString::from(&String::from("GAME")),
+    ///     &[0x00, 0xc0, 0x60],
+    /// );
+    /// let mut image = TapImage::encode_files(&[program]);
+    /// // Damage the first copy of the header block, directly after its pilot.
+    /// image.pulses[0x6a00 + 2] = 0x20 * 8;
+    ///
+    /// let files = image.decode_files();
+    /// assert_eq!(String::from(&files[0].name), "GAME");
+    /// assert_eq!(files[0].data, vec![0x60]);
+    /// assert!(!files[0].has_errors);
+    /// ```
+    pub fn decode_files(&self) -> Vec<TapFile> {
+        let blocks = merge_copies(decode_blocks(&self.pulses));
+        let mut result = Vec::new();
+        let mut blocks = blocks.into_iter().peekable();
+        while let Some(header) = blocks.next() {
+            if header.data.len() != HEADER_BLOCK_SIZE {
+                continue;
+            }
+            let file_type = match TapFileType::from_u8(header.data[0]) {
+                Some(file_type @ (TapFileType::Program | TapFileType::RelocatableProgram)) => {
+                    file_type
+                }
+                _ => continue,
+            };
+            let start_address = u16::from_le_bytes([
+                header.data[OFFSET_START_ADDRESS],
+                header.data[OFFSET_START_ADDRESS + 1],
+            ]);
+            let end_address = u16::from_le_bytes([
+                header.data[OFFSET_END_ADDRESS],
+                header.data[OFFSET_END_ADDRESS + 1],
+            ]);
+            let name = &header.data[OFFSET_FILE_NAME..OFFSET_FILE_NAME + FILE_NAME_LENGTH];
+            let name_length = name
+                .iter()
+                .rposition(|byte| !matches!(*byte, PADDING | PETSCII_NBSP | 0))
+                .map_or(0, |position| position + 1);
+
+            let expected_size = end_address.wrapping_sub(start_address) as usize;
+            let data_block = match blocks.peek() {
+                Some(block) if block.data.len() == expected_size => blocks.next(),
+                _ => None,
+            };
+            let has_errors =
+                header.has_errors || data_block.as_ref().is_none_or(|block| block.has_errors);
+            result.push(TapFile {
+                file_type,
+                name: PetsciiString::fixed_size(&name[..name_length]),
+                start_address,
+                end_address,
+                data: data_block.map(|block| block.data).unwrap_or_default(),
+                has_errors,
+            });
+        }
+        result
+    }
+
+    /// Create a version 1 image containing the given files using the encoding
+    /// of the CBM ROM loader.
+    pub fn encode_files(files: &[TapFile]) -> TapImage {
+        let mut encoder = Encoder::default();
+        for file in files {
+            encoder.block_pair(HEADER_PILOT_LENGTH, &file.header_block());
+            encoder.block_pair(DATA_PILOT_LENGTH, &file.data);
+            encoder.pulses.push(SILENCE);
+        }
+        TapImage {
+            version: 1,
+            pulses: encoder.pulses,
+        }
+    }
+}
+
+/// Pulses used by the CBM ROM loader.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Pulse {
+    Short,
+    Medium,
+    Long,
+    Other,
+}
+
+impl Pulse {
+    fn from_cycles(cycles: u32) -> Pulse {
+        if cycles < (SHORT_PULSE + MEDIUM_PULSE) / 2 {
+            Pulse::Short
+        } else if cycles < (MEDIUM_PULSE + LONG_PULSE) / 2 {
+            Pulse::Medium
+        } else if cycles < MAX_PULSE {
+            Pulse::Long
+        } else {
+            Pulse::Other
+        }
+    }
+}
+
+/// Single copy of a block, or the best of both copies.
+#[derive(Debug, Clone)]
+struct Block {
+    /// Is this the repeated copy of the block.
+    repeated: bool,
+    data: Vec<u8>,
+    has_errors: bool,
+}
+
+/// Decode a single byte starting directly after its new data marker. Returns
+/// None when the pulses don't form a byte; the flag is true when the parity
+/// doesn't match.
+fn decode_byte(pulses: &[Pulse]) -> Option<(u8, bool)> {
+    let mut byte = 0_u8;
+    let mut parity = 1;
+    for bit_index in 0..9 {
+        let bit = match pulses.get(bit_index * 2..bit_index * 2 + 2)? {
+            [Pulse::Short, Pulse::Medium] => 0,
+            [Pulse::Medium, Pulse::Short] => 1,
+            _ => return None,
+        };
+        if bit_index < 8 {
+            byte |= bit << bit_index;
+            parity ^= bit;
+        } else {
+            return Some((byte, parity != bit));
+        }
+    }
+    None
+}
+
+/// Decode all blocks: sequences of bytes that are each preceded by a new data
+/// marker (long, medium) and ended by an end of data marker (long, short).
+fn decode_blocks(cycles: &[u32]) -> Vec<Block> {
+    let pulses = cycles
+        .iter()
+        .map(|cycles| Pulse::from_cycles(*cycles))
+        .collect::<Vec<Pulse>>();
+    let mut result = Vec::new();
+    let mut bytes = Vec::new();
+    let mut has_errors = false;
+    let mut position = 0;
+    while position + 1 < pulses.len() {
+        match (pulses[position], pulses[position + 1]) {
+            (Pulse::Long, Pulse::Medium) => match decode_byte(&pulses[position + 2..]) {
+                Some((byte, parity_error)) => {
+                    bytes.push(byte);
+                    has_errors |= parity_error;
+                    position += 20;
+                    continue;
+                }
+                None => has_errors = true,
+            },
+            (Pulse::Long, Pulse::Short) if !bytes.is_empty() => {
+                if let Some(block) = finish_block(&bytes, has_errors) {
+                    result.push(block);
+                }
+                bytes.clear();
+                has_errors = false;
+            }
+            _ => {}
+        }
+        position += 1;
+    }
+    result
+}
+
+/// Check the countdown and the checksum of the bytes of a block.
+fn finish_block(bytes: &[u8], has_errors: bool) -> Option<Block> {
+    if bytes.len() < COUNTDOWN_FIRST.len() + 1 {
+        return None;
+    }
+    let (countdown, rest) = bytes.split_at(COUNTDOWN_FIRST.len());
+    let repeated = countdown[0] & 0x80 == 0;
+    let expected_countdown = if repeated {
+        COUNTDOWN_REPEAT
+    } else {
+        COUNTDOWN_FIRST
+    };
+    let (data, checksum) = rest.split_at(rest.len() - 1);
+    let checksum_ok = data.iter().fold(0, |result, byte| result ^ byte) == checksum[0];
+    Some(Block {
+        repeated,
+        data: data.to_vec(),
+        has_errors: has_errors || countdown != expected_countdown || !checksum_ok,
+    })
+}
+
+/// Merge each first copy with the repeated copy that follows it.
+fn merge_copies(blocks: Vec<Block>) -> Vec<Block> {
+    let mut result: Vec<Block> = Vec::new();
+    for block in blocks {
+        match result.last_mut() {
+            Some(first)
+                if block.repeated && !first.repeated && first.data.len() == block.data.len() =>
+            {
+                if first.has_errors && !block.has_errors {
+                    first.data = block.data;
+                    first.has_errors = false;
+                }
+                first.repeated = true;
+            }
+            _ => result.push(block),
+        }
+    }
+    result
+}
+
+#[derive(Default)]
+struct Encoder {
+    pulses: Vec<u32>,
+}
+
+impl Encoder {
+    fn repeat(&mut self, cycles: u32, count: usize) {
+        self.pulses.resize(self.pulses.len() + count, cycles);
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.pulses.extend_from_slice(&[LONG_PULSE, MEDIUM_PULSE]);
+        let mut parity = 1;
+        for bit_index in 0..9 {
+            let bit = if bit_index < 8 {
+                (byte >> bit_index) & 1
+            } else {
+                parity
+            };
+            parity ^= bit;
+            if bit == 1 {
+                self.pulses.extend_from_slice(&[MEDIUM_PULSE, SHORT_PULSE]);
+            } else {
+                self.pulses.extend_from_slice(&[SHORT_PULSE, MEDIUM_PULSE]);
+            }
+        }
+    }
+
+    fn block(&mut self, countdown: &[u8], data: &[u8]) {
+        for byte in countdown {
+            self.byte(*byte);
+        }
+        for byte in data {
+            self.byte(*byte);
+        }
+        self.byte(data.iter().fold(0, |result, byte| result ^ byte));
+        self.pulses.extend_from_slice(&[LONG_PULSE, SHORT_PULSE]);
+    }
+
+    fn block_pair(&mut self, pilot_length: usize, data: &[u8]) {
+        self.repeat(SHORT_PULSE, pilot_length);
+        self.block(&COUNTDOWN_FIRST, data);
+        self.repeat(SHORT_PULSE, INTERBLOCK_LENGTH);
+        self.block(&COUNTDOWN_REPEAT, data);
+        self.repeat(SHORT_PULSE, TRAILER_LENGTH);
+    }
+}